@@ -1,5 +1,5 @@
 use caw_core::Stereo;
-use hound::WavReader;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use std::{fs, io::BufReader, path::Path};
 
 fn parse_wav_mono(buffer: &[u8]) -> Vec<f32> {
@@ -50,3 +50,43 @@ pub fn read_wav_stereo(
     let raw = fs::read(path)?;
     Ok(parse_wav_stereo(&raw))
 }
+
+fn f32_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn wav_spec(channels: u16, sample_rate_hz: u32) -> WavSpec {
+    WavSpec {
+        channels,
+        sample_rate: sample_rate_hz,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    }
+}
+
+pub fn write_wav_mono(
+    path: impl AsRef<Path>,
+    sample_rate_hz: u32,
+    samples: &[f32],
+) -> anyhow::Result<()> {
+    let mut writer = WavWriter::create(path, wav_spec(1, sample_rate_hz))?;
+    for &sample in samples {
+        writer.write_sample(f32_to_i16(sample))?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+pub fn write_wav_stereo(
+    path: impl AsRef<Path>,
+    sample_rate_hz: u32,
+    samples: impl IntoIterator<Item = Stereo<f32, f32>>,
+) -> anyhow::Result<()> {
+    let mut writer = WavWriter::create(path, wav_spec(2, sample_rate_hz))?;
+    for Stereo { left, right } in samples {
+        writer.write_sample(f32_to_i16(left))?;
+        writer.write_sample(f32_to_i16(right))?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
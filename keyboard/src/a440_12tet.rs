@@ -140,6 +140,26 @@ impl NoteName {
         }
     }
 
+    /// Returns a str representation of the note name where all accidentals are flat, formatted
+    /// like "C" or "Db"
+    pub const fn to_str_flat(self) -> &'static str {
+        match self.relative_midi_index {
+            0 => "C",
+            1 => "Db",
+            2 => "D",
+            3 => "Eb",
+            4 => "E",
+            5 => "F",
+            6 => "Gb",
+            7 => "G",
+            8 => "Ab",
+            9 => "A",
+            10 => "Bb",
+            11 => "B",
+            _ => unreachable!(),
+        }
+    }
+
     /// Parses a str like "C" or "C#"
     pub fn from_str_sharp(s: &str) -> Option<Self> {
         let relative_midi_index = match s {
@@ -162,7 +182,12 @@ impl NoteName {
         })
     }
 
-    const fn wrapping_add_semitones(self, num_semitones: i8) -> Self {
+    /// The number of semitones from C up to this note
+    pub const fn semitones_above_c(self) -> u8 {
+        self.relative_midi_index
+    }
+
+    pub const fn wrapping_add_semitones(self, num_semitones: i8) -> Self {
         Self::from_index(
             (self.relative_midi_index as i8 + num_semitones)
                 .rem_euclid(NOTES_PER_OCTAVE as i8) as u8,
@@ -425,7 +450,7 @@ pub mod chord {
         }
     }
 
-    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Third {
         Major,
         Minor,
@@ -433,19 +458,19 @@ pub mod chord {
         Sus4,
     }
 
-    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Fifth {
         Perfect,
         Diminished,
     }
 
-    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Seventh {
         Major,
         Minor,
     }
 
-    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub struct ChordType {
         pub third: Option<Third>,
         pub fifth: Option<Fifth>,
@@ -607,7 +632,7 @@ pub mod chord {
         }
     }
 
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
     pub struct Chord {
         pub root: NoteName,
         pub typ: ChordType,
@@ -0,0 +1,605 @@
+//! Chord symbols such as "Cmaj7", "F#m7b5", "Bb/D" or "Gsus4add9", and recognition of chords from
+//! sets of notes.
+
+use crate::{
+    Chord, ChordPosition, ChordType, DIMINISHED, Fifth, Inversion, MAJOR,
+    MINOR, Note, NoteName, OPEN, Octave, SUS_2, SUS_4, Seventh, Third,
+};
+use std::{fmt::Display, str::FromStr};
+
+const NOTES_PER_OCTAVE: u8 = 12;
+
+/// How a note which falls between two natural notes is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Accidental {
+    #[default]
+    Sharp,
+    Flat,
+}
+
+impl Accidental {
+    fn note_name_str(self, note_name: NoteName) -> &'static str {
+        match self {
+            Self::Sharp => note_name.to_str_sharp(),
+            Self::Flat => note_name.to_str_flat(),
+        }
+    }
+}
+
+/// A 9th added to a chord symbol, as in "C9", "Cadd9" or "C7b9".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ninth {
+    /// A flat 9th, a minor 2nd above the octave
+    Flat,
+    Major,
+    /// A sharp 9th, a minor 3rd above the octave
+    Sharp,
+}
+
+impl Ninth {
+    const fn semitones_above_root(self) -> i8 {
+        match self {
+            Self::Flat => 13,
+            Self::Major => 14,
+            Self::Sharp => 15,
+        }
+    }
+}
+
+/// A chord along with which note is played lowest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChordSymbol {
+    pub chord: Chord,
+    /// A 9th written in the symbol. `ChordType` has no 9th so this isn't included in the notes
+    /// of `chord`.
+    pub ninth: Option<Ninth>,
+    pub lowest_position: ChordPosition,
+    /// A note played below the chord which isn't part of the chord, as in "C/F#". If this is
+    /// set then `lowest_position` is ignored.
+    pub bass_note: Option<NoteName>,
+    /// How the root is written when the symbol is formatted
+    pub root_accidental: Accidental,
+    /// How the note after the slash is written when the symbol is formatted
+    pub bass_accidental: Accidental,
+}
+
+impl ChordSymbol {
+    /// A chord in root position.
+    pub fn new(chord: Chord) -> Self {
+        Self {
+            chord,
+            ninth: None,
+            lowest_position: ChordPosition::Root,
+            bass_note: None,
+            root_accidental: Accidental::default(),
+            bass_accidental: Accidental::default(),
+        }
+    }
+
+    /// The name of the lowest note.
+    pub fn bass(&self) -> NoteName {
+        if let Some(bass_note) = self.bass_note {
+            return bass_note;
+        }
+        let mut bass = self.chord.root;
+        self.chord.typ.with_semitones_above_root(
+            |semitones_above, position| {
+                if position == self.lowest_position {
+                    bass =
+                        self.chord.root.wrapping_add_semitones(semitones_above);
+                }
+            },
+        );
+        bass
+    }
+
+    /// The inversion which plays this chord with its lowest note in the right position. If the
+    /// symbol has a bass note which isn't part of the chord, this is root position and the bass
+    /// note must be played separately.
+    pub fn inversion(&self, root_octave: Octave) -> Inversion {
+        let lowest_position = if self.bass_note.is_some() {
+            ChordPosition::Root
+        } else {
+            self.lowest_position
+        };
+        Inversion::WithRootOctave {
+            root_octave,
+            lowest_position,
+        }
+    }
+}
+
+fn position_of_note_name(
+    chord: Chord,
+    note_name: NoteName,
+) -> Option<ChordPosition> {
+    let mut ret = None;
+    chord
+        .typ
+        .with_semitones_above_root(|semitones_above, position| {
+            if ret.is_none()
+                && chord.root.wrapping_add_semitones(semitones_above)
+                    == note_name
+            {
+                ret = Some(position);
+            }
+        });
+    ret
+}
+
+/// Parses the note name at the start of a string like "C", "F#" or "Bb", returning the note name,
+/// how it was written and the remainder of the string.
+fn parse_note_name_prefix(s: &str) -> Option<(NoteName, Accidental, &str)> {
+    let mut chars = s.chars();
+    let mut note_name = match chars.next()? {
+        'C' => NoteName::C,
+        'D' => NoteName::D,
+        'E' => NoteName::E,
+        'F' => NoteName::F,
+        'G' => NoteName::G,
+        'A' => NoteName::A,
+        'B' => NoteName::B,
+        _ => return None,
+    };
+    let mut accidental = Accidental::default();
+    let mut rest = chars.as_str();
+    loop {
+        if let Some(r) = rest.strip_prefix(['#', '♯']) {
+            note_name = note_name.wrapping_add_semitones(1);
+            accidental = Accidental::Sharp;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(['b', '♭']) {
+            note_name = note_name.wrapping_add_semitones(-1);
+            accidental = Accidental::Flat;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    Some((note_name, accidental, rest))
+}
+
+fn strip_any_prefix<'a>(s: &'a str, prefixes: &[&str]) -> Option<&'a str> {
+    prefixes.iter().find_map(|prefix| s.strip_prefix(prefix))
+}
+
+fn parse_chord_type(s: &str) -> Result<(ChordType, Option<Ninth>), String> {
+    let mut typ = MAJOR;
+    let mut ninth = None;
+    let mut rest = s;
+    while !rest.is_empty() {
+        // Longer tokens are checked before any tokens which are prefixes of them.
+        if let Some(r) = strip_any_prefix(rest, &["(", ")"]) {
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("no3") {
+            typ.third = None;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("no5") {
+            typ.fifth = None;
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["maj9", "M9", "Δ9"]) {
+            typ = typ.major_7();
+            ninth = Some(Ninth::Major);
+            rest = r;
+        } else if let Some(r) =
+            strip_any_prefix(rest, &["maj7", "M7", "Δ7", "Δ"])
+        {
+            typ = typ.major_7();
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("maj") {
+            typ.third = Some(Third::Major);
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["m7b5", "ø7", "ø"]) {
+            typ = DIMINISHED.minor_7();
+            rest = r;
+        } else if strip_any_prefix(rest, &["dim7", "°7", "o7"]).is_some() {
+            return Err(format!(
+                "Diminished 7th chords are not supported (in \"{}\").",
+                s
+            ));
+        } else if let Some(r) = strip_any_prefix(rest, &["dim", "°", "o"]) {
+            typ.third = Some(Third::Minor);
+            typ.fifth = Some(Fifth::Diminished);
+            rest = r;
+        } else if strip_any_prefix(rest, &["aug", "+"]).is_some() {
+            return Err(format!(
+                "Augmented chords are not supported (in \"{}\").",
+                s
+            ));
+        } else if let Some(r) = strip_any_prefix(rest, &["min", "m", "-"]) {
+            typ.third = Some(Third::Minor);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("sus2") {
+            typ.third = Some(Third::Sus2);
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["sus4", "sus"]) {
+            typ.third = Some(Third::Sus4);
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["add9", "add2"]) {
+            ninth = Some(Ninth::Major);
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["addb9", "b9"]) {
+            ninth = Some(Ninth::Flat);
+            rest = r;
+        } else if let Some(r) = strip_any_prefix(rest, &["add#9", "#9"]) {
+            ninth = Some(Ninth::Sharp);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("b5") {
+            typ.fifth = Some(Fifth::Diminished);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('9') {
+            if typ.seventh.is_none() {
+                typ.seventh = Some(Seventh::Minor);
+            }
+            ninth = Some(Ninth::Major);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('7') {
+            if typ.seventh.is_none() {
+                typ.seventh = Some(Seventh::Minor);
+            }
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('5') {
+            typ.third = None;
+            rest = r;
+        } else {
+            return Err(format!(
+                "Unexpected \"{}\" in chord symbol suffix \"{}\".",
+                rest, s
+            ));
+        }
+    }
+    Ok((typ, ninth))
+}
+
+/// Expected format: a root note like "C", "F#" or "Bb", followed by a suffix describing the chord
+/// type like "m7", "maj7", "m7b5", "sus4add9" or "9", optionally followed by a slash and the note
+/// which should be played lowest, such as "Bb/D" or "C/F#". The lowest note needn't be part of
+/// the chord.
+impl FromStr for ChordSymbol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (symbol, bass) = match s.split_once('/') {
+            Some((symbol, bass)) => (symbol, Some(bass)),
+            None => (s, None),
+        };
+        let Some((root, root_accidental, suffix)) =
+            parse_note_name_prefix(symbol)
+        else {
+            return Err(format!(
+                "Expected a note name at the start of \"{}\".",
+                s
+            ));
+        };
+        let (typ, ninth) = parse_chord_type(suffix)?;
+        let mut chord_symbol = ChordSymbol::new(Chord::new(root, typ));
+        chord_symbol.ninth = ninth;
+        chord_symbol.root_accidental = root_accidental;
+        if let Some(bass) = bass {
+            let Some((bass_note_name, bass_accidental, "")) =
+                parse_note_name_prefix(bass)
+            else {
+                return Err(format!(
+                    "Expected a note name after the slash in \"{}\".",
+                    s
+                ));
+            };
+            chord_symbol.bass_accidental = bass_accidental;
+            match position_of_note_name(chord_symbol.chord, bass_note_name) {
+                Some(position) => chord_symbol.lowest_position = position,
+                None => chord_symbol.bass_note = Some(bass_note_name),
+            }
+        }
+        Ok(chord_symbol)
+    }
+}
+
+fn chord_type_suffix(typ: ChordType, mut ninth: Option<Ninth>) -> String {
+    let mut s = String::new();
+    let half_diminished = typ.third == Some(Third::Minor)
+        && typ.fifth == Some(Fifth::Diminished)
+        && typ.seventh == Some(Seventh::Minor);
+    if half_diminished {
+        if ninth == Some(Ninth::Major) {
+            s.push_str("m9b5");
+            ninth = None;
+        } else {
+            s.push_str("m7b5");
+        }
+    } else if typ.third == Some(Third::Minor)
+        && typ.fifth == Some(Fifth::Diminished)
+        && typ.seventh.is_none()
+    {
+        s.push_str("dim");
+    } else {
+        if typ.third == Some(Third::Minor) {
+            s.push('m');
+        }
+        match typ.seventh {
+            Some(Seventh::Major) if ninth == Some(Ninth::Major) => {
+                s.push_str("maj9");
+                ninth = None;
+            }
+            Some(Seventh::Major) => s.push_str("maj7"),
+            Some(Seventh::Minor) if ninth == Some(Ninth::Major) => {
+                s.push('9');
+                ninth = None;
+            }
+            Some(Seventh::Minor) => s.push('7'),
+            None => (),
+        }
+        match typ.third {
+            Some(Third::Sus2) => s.push_str("sus2"),
+            Some(Third::Sus4) => s.push_str("sus4"),
+            None if typ.fifth == Some(Fifth::Perfect)
+                && typ.seventh.is_none()
+                && ninth.is_none() =>
+            {
+                s.push('5')
+            }
+            None => s.push_str("no3"),
+            _ => (),
+        }
+        if typ.fifth == Some(Fifth::Diminished) {
+            s.push_str("b5");
+        }
+    }
+    if let Some(ninth) = ninth {
+        if typ.seventh.is_none() {
+            s.push_str("add");
+        }
+        s.push_str(match ninth {
+            Ninth::Flat => "b9",
+            Ninth::Major => "9",
+            Ninth::Sharp => "#9",
+        });
+    }
+    if typ.fifth.is_none() {
+        s.push_str("no5");
+    }
+    s
+}
+
+/// Formats the chord in root position, ignoring its octave shift. Accidentals are written as
+/// sharps.
+impl Display for Chord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.root.to_str_sharp(),
+            chord_type_suffix(self.typ, None)
+        )
+    }
+}
+
+/// Formats the chord ignoring its octave shift, writing accidentals the way they were written
+/// when the symbol was parsed.
+impl Display for ChordSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.root_accidental.note_name_str(self.chord.root),
+            chord_type_suffix(self.chord.typ, self.ninth)
+        )?;
+        if self.bass_note.is_some()
+            || self.lowest_position != ChordPosition::Root
+        {
+            write!(f, "/{}", self.bass_accidental.note_name_str(self.bass()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Chord types considered when recognizing chords, in order of preference when several match
+/// equally well.
+const RECOGNIZED_CHORD_TYPES: &[(ChordType, Option<Ninth>)] = &[
+    (MAJOR, None),
+    (MINOR, None),
+    (MAJOR.minor_7(), None),
+    (MINOR.minor_7(), None),
+    (MAJOR.major_7(), None),
+    (DIMINISHED, None),
+    (DIMINISHED.minor_7(), None),
+    (SUS_4, None),
+    (SUS_2, None),
+    (SUS_4.minor_7(), None),
+    (MINOR.major_7(), None),
+    (MAJOR, Some(Ninth::Major)),
+    (MINOR, Some(Ninth::Major)),
+    (MAJOR.minor_7(), Some(Ninth::Major)),
+    (MINOR.minor_7(), Some(Ninth::Major)),
+    (MAJOR.major_7(), Some(Ninth::Major)),
+    (SUS_4, Some(Ninth::Major)),
+    (MAJOR.minor_7(), Some(Ninth::Flat)),
+    (MAJOR.minor_7(), Some(Ninth::Sharp)),
+    (OPEN, None),
+];
+
+fn pitch_class_mask(chord: Chord, ninth: Option<Ninth>) -> u16 {
+    let mut mask = 0;
+    let mut add = |semitones_above| {
+        mask |= 1
+            << chord
+                .root
+                .wrapping_add_semitones(semitones_above)
+                .semitones_above_c();
+    };
+    chord.typ.with_semitones_above_root(|semitones_above, _| {
+        add(semitones_above);
+    });
+    if let Some(ninth) = ninth {
+        add(ninth.semitones_above_root());
+    }
+    mask
+}
+
+/// Returns the chord which best matches a set of notes, or `None` if fewer than two distinct note
+/// names are present. The lowest note determines the inversion of the result (or is its bass note
+/// if it's not part of the chord), and breaks ties between chords containing the same notes (e.g.
+/// "Am7" and "C6").
+pub fn recognize_chord(
+    notes: impl IntoIterator<Item = Note>,
+) -> Option<ChordSymbol> {
+    let mut held_mask = 0u16;
+    let mut lowest: Option<Note> = None;
+    for note in notes {
+        held_mask |= 1 << note.note_name().semitones_above_c();
+        lowest = Some(lowest.map_or(note, |lowest| lowest.min(note)));
+    }
+    if held_mask.count_ones() < 2 {
+        return None;
+    }
+    let bass = lowest?.note_name();
+    let mut best: Option<(i32, Chord, Option<Ninth>)> = None;
+    for root_offset in 0..NOTES_PER_OCTAVE {
+        // Start from the bass note so chords rooted on the bass note win ties.
+        let root = bass.wrapping_add_semitones(root_offset as i8);
+        for &(typ, ninth) in RECOGNIZED_CHORD_TYPES {
+            let chord = Chord::new(root, typ);
+            let chord_mask = pitch_class_mask(chord, ninth);
+            let fifth_mask = match typ.fifth {
+                Some(Fifth::Perfect) => {
+                    1 << root.wrapping_add_semitones(7).semitones_above_c()
+                }
+                Some(Fifth::Diminished) => {
+                    1 << root.wrapping_add_semitones(6).semitones_above_c()
+                }
+                None => 0,
+            };
+            let matched = (held_mask & chord_mask).count_ones() as i32;
+            let extra = (held_mask & !chord_mask).count_ones() as i32;
+            let missing = chord_mask & !held_mask;
+            // A missing fifth is penalized less as it's commonly omitted from voicings.
+            let missing_penalty = (missing & !fifth_mask).count_ones() as i32
+                * 4
+                + (missing & fifth_mask).count_ones() as i32 * 2;
+            let root_missing = held_mask & (1 << root.semitones_above_c()) == 0;
+            let score = (matched * 4) - (extra * 4) - missing_penalty
+                + (root_offset == 0) as i32
+                - (root_missing as i32 * 4);
+            if best.is_none_or(|(best_score, _, _)| score > best_score) {
+                best = Some((score, chord, ninth));
+            }
+        }
+    }
+    let (score, chord, ninth) = best?;
+    if score <= 0 {
+        return None;
+    }
+    let mut chord_symbol = ChordSymbol::new(chord);
+    chord_symbol.ninth = ninth;
+    match position_of_note_name(chord, bass) {
+        Some(position) => chord_symbol.lowest_position = position,
+        None => chord_symbol.bass_note = Some(bass),
+    }
+    Some(chord_symbol)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::note;
+
+    fn parse(s: &str) -> ChordSymbol {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_symbols() {
+        assert_eq!(
+            parse("Cmaj7"),
+            ChordSymbol::new(Chord::new(NoteName::C, MAJOR.major_7()))
+        );
+        assert_eq!(
+            parse("F#m7b5"),
+            ChordSymbol::new(Chord::new(
+                NoteName::F_SHARP,
+                DIMINISHED.minor_7()
+            ))
+        );
+        assert_eq!(
+            parse("Bb/D"),
+            ChordSymbol {
+                lowest_position: ChordPosition::Third,
+                root_accidental: Accidental::Flat,
+                ..ChordSymbol::new(Chord::new(NoteName::B_FLAT, MAJOR))
+            }
+        );
+        assert_eq!(
+            parse("C/F#"),
+            ChordSymbol {
+                bass_note: Some(NoteName::F_SHARP),
+                bass_accidental: Accidental::Sharp,
+                ..ChordSymbol::new(Chord::new(NoteName::C, MAJOR))
+            }
+        );
+        assert_eq!(parse("C/F#").bass(), NoteName::F_SHARP);
+        assert_eq!(
+            parse("Gsus4add9"),
+            ChordSymbol {
+                ninth: Some(Ninth::Major),
+                ..ChordSymbol::new(Chord::new(NoteName::G, SUS_4))
+            }
+        );
+        assert!("C/H".parse::<ChordSymbol>().is_err());
+        assert!("C/".parse::<ChordSymbol>().is_err());
+        assert!("Cfoo".parse::<ChordSymbol>().is_err());
+        assert!("Caug".parse::<ChordSymbol>().is_err());
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for s in [
+            "C", "Cm", "C7", "Cm7", "Cmaj7", "Cmmaj7", "Cdim", "Cm7b5",
+            "Csus2", "Csus4", "C7sus4", "C5", "Cadd9", "Cmadd9", "C9", "Cm9",
+            "Cmaj9", "C7b9", "C7#9", "A#/D", "Bb/D", "F#m7/E", "G7/F", "C7no3",
+            "Cno5", "C/F#", "Eb/G", "Bb/F#", "Ebm7/Gb",
+        ] {
+            let symbol = parse(s);
+            assert_eq!(symbol.to_string(), s);
+            assert_eq!(symbol.to_string().parse::<ChordSymbol>(), Ok(symbol));
+        }
+        assert_eq!(parse("B♭/D").to_string(), "Bb/D");
+        assert_eq!(parse("CmM7").to_string(), "Cmmaj7");
+        assert_eq!(parse("Gsus4add9").to_string(), "Gsus4add9");
+        assert_eq!(parse("F#m7b5").to_string(), "F#m7b5");
+    }
+
+    #[test]
+    fn recognize() {
+        let recognize = |notes: &[Note]| {
+            recognize_chord(notes.iter().cloned()).map(|s| s.to_string())
+        };
+        assert_eq!(
+            recognize(&[note::C_4, note::E_4, note::G_4]),
+            Some("C".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::E_3, note::C_4, note::G_4]),
+            Some("C/E".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::D_3, note::F_4, note::A_4, note::C_5]),
+            Some("Dm7".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::G_2, note::B_3, note::F_4]),
+            Some("G7".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::C_3, note::D_4, note::G_4]),
+            Some("Csus2".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::A_3, note::C_4, note::E_4, note::G_4]),
+            Some("Am7".to_string())
+        );
+        assert_eq!(
+            recognize(&[note::C_3, note::E_4, note::B_FLAT_4, note::D_5]),
+            Some("C9".to_string())
+        );
+        assert_eq!(recognize(&[note::C_4]), None);
+    }
+}
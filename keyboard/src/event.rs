@@ -1,7 +1,7 @@
 use crate::{
-    MonoVoice, Note,
+    ChordSymbol, MonoVoice, Note,
     chord::{Chord, Inversion},
-    polyphony,
+    polyphony, recognize_chord,
 };
 use caw_core::{Buf, ConstBuf, Sig, SigCtx, SigT};
 use itertools::izip;
@@ -114,6 +114,41 @@ pub trait KeyEventsT {
         H: SigT<Item = u32>,
         L: SigT<Item = u32>,
         S: SigT<Item = ArpShape>;
+
+    /// The chord which best matches the currently held keys, or `None` if fewer than two
+    /// different note names are held.
+    fn held_chord(self) -> Sig<impl SigT<Item = Option<ChordSymbol>>>
+    where
+        Self: SigT<Item = KeyEvents> + Sized,
+    {
+        held_chord_from_key_events(self)
+    }
+}
+
+fn held_chord_from_key_events<K>(
+    mut key_events: K,
+) -> Sig<impl SigT<Item = Option<ChordSymbol>>>
+where
+    K: SigT<Item = KeyEvents>,
+{
+    let mut held_notes = HashSet::new();
+    let mut chord = None;
+    Sig::from_buf_fn(move |ctx, buf: &mut Vec<Option<ChordSymbol>>| {
+        buf.clear();
+        for key_events in key_events.sample(ctx).iter() {
+            if !key_events.is_empty() {
+                for key_event in key_events {
+                    if key_event.pressed {
+                        held_notes.insert(key_event.note);
+                    } else {
+                        held_notes.remove(&key_event.note);
+                    }
+                }
+                chord = recognize_chord(held_notes.iter().cloned());
+            }
+            buf.push(chord);
+        }
+    })
 }
 
 impl<K> KeyEventsT for Sig<K>
@@ -4,6 +4,15 @@ pub use a440_12tet::{chord::*, *};
 mod event;
 pub use event::*;
 
+mod scale;
+pub use scale::*;
+
+mod progression;
+pub use progression::*;
+
+mod chord_symbol;
+pub use chord_symbol::*;
+
 pub mod mono_voice;
 pub use mono_voice::MonoVoice;
 
@@ -0,0 +1,269 @@
+//! Chord progressions written in Roman numeral notation relative to a key.
+//!
+//! A progression is a whitespace-separated list of chords such as `"ii7 V7 Imaj7"`. Each chord
+//! is made up of:
+//! - optional accidentals (`b` or `#`) which lower or raise the root by a semitone, e.g. `bVII`
+//! - a numeral from `I` to `VII` naming the degree of the key's scale of the root note;
+//!   upper case numerals are major chords and lower case numerals are minor chords
+//! - an optional quality: `°`/`o`/`dim` for diminished or `ø` for half-diminished (which
+//!   implies a minor 7th)
+//! - an optional 7th: `7` for a minor 7th (i.e. a dominant 7th on a major chord), or
+//!   `maj7`/`M7`/`Δ7`/`Δ` for a major 7th; `m7b5` is also accepted for half-diminished chords
+//! - an optional suspension: `sus2` or `sus4`, replacing the third
+//!
+//! A `-` in place of a chord is a rest during which no chord is played.
+
+use crate::{Chord, ChordType, Fifth, Scale, Third};
+use caw_core::{Buf, Sig, SigCtx, SigT};
+
+/// The numerals in order of the scale degree they represent.
+const NUMERALS: [&str; 7] = ["VII", "III", "IV", "VI", "II", "V", "I"];
+const NUMERAL_DEGREES: [usize; 7] = [7, 3, 4, 6, 2, 5, 1];
+
+fn parse_numeral(s: &str) -> Option<(usize, bool, &str)> {
+    // Longer numerals are checked first so "VII" isn't parsed as "V" followed by "II".
+    NUMERALS
+        .iter()
+        .zip(NUMERAL_DEGREES)
+        .find_map(|(numeral, degree)| {
+            if let Some(rest) = s.strip_prefix(numeral) {
+                Some((degree, true, rest))
+            } else {
+                s.strip_prefix(numeral.to_lowercase().as_str())
+                    .map(|rest| (degree, false, rest))
+            }
+        })
+}
+
+fn strip_any_prefix<'a>(s: &'a str, prefixes: &[&str]) -> Option<&'a str> {
+    prefixes.iter().find_map(|prefix| s.strip_prefix(prefix))
+}
+
+fn parse_chord(s: &str, key: Scale) -> Result<Chord, String> {
+    let mut rest = s;
+    let mut accidental = 0i8;
+    loop {
+        if let Some(r) = rest.strip_prefix('b') {
+            accidental -= 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('#') {
+            accidental += 1;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    let Some((degree, upper_case, mut rest)) = parse_numeral(rest) else {
+        return Err(format!("Expected a Roman numeral in chord \"{}\".", s));
+    };
+    let mut typ = ChordType {
+        third: Some(if upper_case {
+            Third::Major
+        } else {
+            Third::Minor
+        }),
+        fifth: Some(Fifth::Perfect),
+        seventh: None,
+    };
+    if let Some(r) = strip_any_prefix(rest, &["°", "o", "dim"]) {
+        typ = typ.flat_5();
+        typ.third = Some(Third::Minor);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("ø") {
+        typ = typ.flat_5().minor_7();
+        typ.third = Some(Third::Minor);
+        rest = r;
+    } else if strip_any_prefix(rest, &["+", "aug"]).is_some() {
+        return Err(format!(
+            "Augmented chords are not supported (in chord \"{}\").",
+            s
+        ));
+    }
+    if let Some(r) = strip_any_prefix(rest, &["maj7", "M7", "Δ7", "Δ"]) {
+        typ = typ.major_7();
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("m7b5") {
+        typ = typ.flat_5().minor_7();
+        typ.third = Some(Third::Minor);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('7') {
+        if matches!(typ.fifth, Some(Fifth::Diminished)) && typ.seventh.is_none()
+        {
+            return Err(format!(
+                "Diminished 7th chords are not supported (in chord \"{}\").",
+                s
+            ));
+        }
+        typ = typ.minor_7();
+        rest = r;
+    }
+    if let Some(r) = rest.strip_prefix("sus2") {
+        typ.third = Some(Third::Sus2);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("sus4") {
+        typ.third = Some(Third::Sus4);
+        rest = r;
+    }
+    if !rest.is_empty() {
+        return Err(format!(
+            "Unexpected \"{}\" at end of chord \"{}\".",
+            rest, s
+        ));
+    }
+    if degree > key.num_degrees() as usize {
+        return Err(format!(
+            "Numeral in chord \"{}\" is beyond the {} degrees of the key.",
+            s,
+            key.num_degrees()
+        ));
+    }
+    let root = key
+        .degree_note_name(degree - 1)
+        .wrapping_add_semitones(accidental);
+    Ok(Chord::new(root, typ))
+}
+
+/// A sequence of chords, some of which may be rests.
+#[derive(Clone, Debug)]
+pub struct Progression {
+    chords: Vec<Option<Chord>>,
+}
+
+impl Progression {
+    /// Parse a progression of Roman numeral chords such as `"ii7 V7 Imaj7"` in a given key. The
+    /// numerals refer to the degrees of the key's scale, so scales without seven notes are
+    /// supported as long as the numerals don't exceed the number of notes in the scale.
+    pub fn parse(s: &str, key: Scale) -> Result<Self, String> {
+        let chords = s
+            .split_whitespace()
+            .map(|token| {
+                if token == "-" {
+                    Ok(None)
+                } else {
+                    parse_chord(token, key).map(Some)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { chords })
+    }
+
+    pub fn chords(&self) -> &[Option<Chord>] {
+        &self.chords
+    }
+
+    /// A signal which plays the chords of the progression in order, moving on to the next chord
+    /// each time `trig` is true and looping back to the start after the last chord. No chord is
+    /// played until the first trigger. The result can be passed to `ChordsT::key_events`.
+    pub fn sig<T>(self, mut trig: T) -> Sig<impl SigT<Item = Option<Chord>>>
+    where
+        T: SigT<Item = bool>,
+    {
+        let mut index: Option<usize> = None;
+        Sig::from_buf_fn(move |ctx: &SigCtx, buf: &mut Vec<Option<Chord>>| {
+            buf.clear();
+            for trig in trig.sample(ctx).iter() {
+                if trig && !self.chords.is_empty() {
+                    index =
+                        Some(index.map_or(0, |i| (i + 1) % self.chords.len()));
+                }
+                buf.push(index.and_then(|i| self.chords[i]));
+            }
+        })
+    }
+}
+
+/// Parse a progression of Roman numeral chords such as `"ii7 V7 Imaj7"` in a given key. Panics
+/// if the progression can't be parsed.
+pub fn progression(key: Scale, s: &str) -> Progression {
+    match Progression::parse(s, key) {
+        Ok(progression) => progression,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ScaleMode, note_name};
+
+    fn c_major() -> Scale {
+        Scale::new(note_name::C, ScaleMode::Major)
+    }
+
+    fn root_and_semitones(chord: Chord) -> (u8, Vec<i8>) {
+        let mut semitones = Vec::new();
+        chord
+            .typ
+            .with_semitones_above_root(|s, _| semitones.push(s));
+        (chord.root.semitones_above_c(), semitones)
+    }
+
+    #[test]
+    fn two_five_one() {
+        let chords = Progression::parse("ii7 V7 Imaj7", c_major()).unwrap();
+        let chords = chords
+            .chords()
+            .iter()
+            .map(|c| root_and_semitones(c.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chords,
+            vec![
+                (2, vec![0, 3, 7, 10]),
+                (7, vec![0, 4, 7, 10]),
+                (0, vec![0, 4, 7, 11]),
+            ]
+        );
+    }
+
+    #[test]
+    fn accidentals_and_qualities() {
+        let key = Scale::new(note_name::A, ScaleMode::Minor);
+        let chords = Progression::parse("iiø VII #viio Vsus4 -", key).unwrap();
+        let chords = chords.chords();
+        assert_eq!(
+            root_and_semitones(chords[0].unwrap()),
+            (11, vec![0, 3, 6, 10])
+        );
+        assert_eq!(root_and_semitones(chords[1].unwrap()), (7, vec![0, 4, 7]));
+        assert_eq!(root_and_semitones(chords[2].unwrap()), (8, vec![0, 3, 6]));
+        assert_eq!(root_and_semitones(chords[3].unwrap()), (4, vec![0, 5, 7]));
+        assert!(chords[4].is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Progression::parse("ii7 X", c_major()).is_err());
+        assert!(Progression::parse("Vx", c_major()).is_err());
+        assert!(Progression::parse("III+", c_major()).is_err());
+        assert!(Progression::parse("viio7", c_major()).is_err());
+    }
+
+    #[test]
+    fn numeral_beyond_key() {
+        let pentatonic = Scale::custom(note_name::C, &[0, 2, 4, 7, 9]);
+        assert!(Progression::parse("I V", pentatonic).is_ok());
+        assert!(Progression::parse("I VI", pentatonic).is_err());
+    }
+
+    #[test]
+    fn degree_semitones_saturates() {
+        let key = c_major();
+        assert_eq!(key.degree_semitones(15), 26);
+        assert_eq!(key.degree_semitones(usize::MAX), u8::MAX);
+        assert_eq!(key.degree_note_name(usize::MAX), key.degree_note_name(1));
+    }
+
+    #[test]
+    fn diatonic_chords() {
+        let key = c_major();
+        assert_eq!(
+            root_and_semitones(key.diatonic_chord(4, true)),
+            (7, vec![0, 4, 7, 10])
+        );
+        assert_eq!(
+            root_and_semitones(key.diatonic_chord(6, true)),
+            (11, vec![0, 3, 6, 10])
+        );
+    }
+}
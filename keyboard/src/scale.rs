@@ -0,0 +1,198 @@
+use crate::{
+    Chord, ChordType, Fifth, Note, NoteName, Seventh, Third, note_name,
+};
+use caw_core::{Buf, ConstBuf, SigCtx, SigT};
+use serde::{Deserialize, Serialize};
+
+const NOTES_PER_OCTAVE: u8 = 12;
+
+/// Common scales, defined by the intervals between their notes and the root note.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScaleMode {
+    /// Also known as Ionian
+    Major,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    /// Natural minor, also known as Aeolian
+    Minor,
+    Locrian,
+    HarmonicMinor,
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    WholeTone,
+    Chromatic,
+}
+
+impl ScaleMode {
+    /// The number of semitones above the root of each note in the scale, in ascending order.
+    pub const fn intervals(self) -> &'static [u8] {
+        match self {
+            Self::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Self::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Self::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Self::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Self::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Self::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Self::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Self::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Self::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            Self::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Self::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Self::Blues => &[0, 3, 5, 6, 7, 10],
+            Self::WholeTone => &[0, 2, 4, 6, 8, 10],
+            Self::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+/// A set of note names defined by a root note and the intervals above it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scale {
+    root: NoteName,
+    /// Bit `i` is set if the note `i` semitones above the root is in the scale
+    intervals_mask: u16,
+}
+
+impl Scale {
+    pub fn new(root: NoteName, mode: ScaleMode) -> Self {
+        Self::custom(root, mode.intervals())
+    }
+
+    /// A scale made up of the notes a given number of semitones above the root. Intervals are
+    /// wrapped to within an octave. The root note is always part of the scale.
+    pub fn custom(root: NoteName, intervals: &[u8]) -> Self {
+        let intervals_mask = intervals.iter().fold(1, |mask, &interval| {
+            mask | (1 << (interval % NOTES_PER_OCTAVE))
+        });
+        Self {
+            root,
+            intervals_mask,
+        }
+    }
+
+    pub const fn root(&self) -> NoteName {
+        self.root
+    }
+
+    /// The number of semitones above the root of each note in the scale, in ascending order.
+    pub fn intervals(&self) -> impl Iterator<Item = u8> {
+        let intervals_mask = self.intervals_mask;
+        (0..NOTES_PER_OCTAVE).filter(move |i| intervals_mask & (1 << i) != 0)
+    }
+
+    /// The number of notes in each octave of the scale.
+    pub const fn num_degrees(&self) -> u32 {
+        self.intervals_mask.count_ones()
+    }
+
+    /// The number of semitones above the root of the note at a given degree of the scale,
+    /// counting from 0. Degrees beyond the end of the scale continue into higher octaves. The
+    /// result saturates at `u8::MAX` for degrees too high to be represented.
+    pub fn degree_semitones(&self, degree: usize) -> u8 {
+        let num_degrees = self.num_degrees() as usize;
+        let octave = u8::try_from(degree / num_degrees).unwrap_or(u8::MAX);
+        let interval = self
+            .intervals()
+            .nth(degree % num_degrees)
+            .expect("scale always contains at least one note");
+        octave
+            .saturating_mul(NOTES_PER_OCTAVE)
+            .saturating_add(interval)
+    }
+
+    /// The name of the note at a given degree of the scale, counting from 0.
+    pub fn degree_note_name(&self, degree: usize) -> NoteName {
+        let degree = degree % self.num_degrees() as usize;
+        self.root
+            .wrapping_add_semitones(self.degree_semitones(degree) as i8)
+    }
+
+    /// The chord built by stacking alternate notes of the scale starting at a given degree
+    /// (counting from 0). E.g. in C major, degree 1 gives D minor and degree 4 with a seventh
+    /// gives G dominant 7. Intervals which can't be represented by a `ChordType` (such as
+    /// augmented fifths) are left out of the chord.
+    pub fn diatonic_chord(&self, degree: usize, seventh: bool) -> Chord {
+        let base = self.degree_semitones(degree);
+        let interval = |offset| self.degree_semitones(degree + offset) - base;
+        let third = match interval(2) {
+            3 => Some(Third::Minor),
+            4 => Some(Third::Major),
+            _ => None,
+        };
+        let fifth = match interval(4) {
+            6 => Some(Fifth::Diminished),
+            7 => Some(Fifth::Perfect),
+            _ => None,
+        };
+        let seventh = if seventh {
+            match interval(6) {
+                10 => Some(Seventh::Minor),
+                11 => Some(Seventh::Major),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        Chord::new(
+            self.degree_note_name(degree),
+            ChordType {
+                third,
+                fifth,
+                seventh,
+            },
+        )
+    }
+
+    fn semitones_above_root(&self, midi_index: i32) -> u8 {
+        (midi_index - self.root.semitones_above_c() as i32)
+            .rem_euclid(NOTES_PER_OCTAVE as i32) as u8
+    }
+
+    fn contains_midi_index(&self, midi_index: i32) -> bool {
+        self.intervals_mask & (1 << self.semitones_above_root(midi_index)) != 0
+    }
+
+    pub fn contains(&self, note: Note) -> bool {
+        self.contains_midi_index(note.to_midi_index() as i32)
+    }
+
+    /// The note in the scale closest to a (possibly fractional) midi index. Ties are resolved in
+    /// favour of the lower note.
+    pub fn nearest_note(&self, midi_index: f32) -> Note {
+        let midi_index = midi_index.clamp(0.0, 127.0);
+        let rounded = midi_index.round() as i32;
+        // Every scale contains its root so there is always a note within half an octave.
+        (rounded - 6..=rounded + 6)
+            .filter(|&i| (0..=127).contains(&i) && self.contains_midi_index(i))
+            .min_by(|&a, &b| {
+                (a as f32 - midi_index)
+                    .abs()
+                    .total_cmp(&(b as f32 - midi_index).abs())
+            })
+            .map(|i| Note::from_midi_index(i as u8))
+            .unwrap_or_else(|| Note::from_midi_index(rounded as u8))
+    }
+}
+
+/// Arbitrary default value so a scale can be used where a default value is required. The default
+/// scale is C major.
+impl Default for Scale {
+    fn default() -> Self {
+        Self::new(note_name::C, ScaleMode::Major)
+    }
+}
+
+impl SigT for Scale {
+    type Item = Self;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        ConstBuf {
+            value: *self,
+            count: ctx.num_samples,
+        }
+    }
+}
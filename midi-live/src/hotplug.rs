@@ -0,0 +1,372 @@
+use caw_core::{Sig, SigT};
+use caw_midi::{MidiEvent, MidiEvents};
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
+use midly::live::LiveEvent;
+use smallvec::{SmallVec, smallvec};
+use std::{
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+
+/// A midi event along with the name of the port it was received on.
+#[derive(Clone, Debug)]
+pub struct MidiPortEvent {
+    pub port_name: Arc<str>,
+    pub midi_event: MidiEvent,
+}
+
+/// A collection of simultaneous midi events received from any number of ports.
+#[derive(Clone, Debug, Default)]
+pub struct MidiPortEvents(SmallVec<[MidiPortEvent; 1]>);
+
+impl MidiPortEvents {
+    pub fn empty() -> Self {
+        Self(smallvec![])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, midi_port_event: MidiPortEvent) {
+        self.0.push(midi_port_event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MidiPortEvent> {
+        self.0.iter()
+    }
+
+    /// The events, discarding the names of the ports they came from.
+    pub fn midi_events(&self) -> MidiEvents {
+        let mut midi_events = MidiEvents::empty();
+        for MidiPortEvent { midi_event, .. } in self.iter() {
+            midi_events.push(midi_event.clone());
+        }
+        midi_events
+    }
+}
+
+impl IntoIterator for MidiPortEvents {
+    type Item = MidiPortEvent;
+
+    type IntoIter = smallvec::IntoIter<[MidiPortEvent; 1]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Returns true iff `name` matches `pattern`, where a `*` in the pattern matches any sequence of
+/// characters (including the empty sequence) and a `?` matches any single character.
+pub fn port_name_matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // The position of the most recent `*` in the pattern and the position in the name that it
+    // was matched up to, so we can backtrack and have it match one more character.
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            backtrack = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Messages sent from the thread which manages the connections to ports.
+enum SubscriptionMessage {
+    Event(MidiPortEvent),
+    Connected(Arc<str>),
+    Disconnected(Arc<str>),
+}
+
+struct PortConnection {
+    port: MidiInputPort,
+    port_name: Arc<str>,
+    #[allow(unused)]
+    midi_input_connection: MidiInputConnection<()>,
+}
+
+impl PortConnection {
+    fn new(
+        port: MidiInputPort,
+        port_name: Arc<str>,
+        message_sender: mpsc::Sender<SubscriptionMessage>,
+    ) -> anyhow::Result<Self> {
+        // Each connection consumes a `MidiInput` so create a new one for each port.
+        let midi_input = MidiInput::new("caw")?;
+        let connection_name = format!("caw {}", port_name);
+        let callback_port_name = Arc::clone(&port_name);
+        let midi_input_connection = midi_input
+            .connect(
+                &port,
+                connection_name.as_str(),
+                move |_timestamp_us, message, &mut ()| {
+                    if let Ok(LiveEvent::Midi { channel, message }) =
+                        LiveEvent::parse(message)
+                    {
+                        let midi_port_event = MidiPortEvent {
+                            port_name: Arc::clone(&callback_port_name),
+                            midi_event: MidiEvent { channel, message },
+                        };
+                        if message_sender
+                            .send(SubscriptionMessage::Event(midi_port_event))
+                            .is_err()
+                        {
+                            log::error!(
+                                "failed to send message from live midi thread"
+                            );
+                        }
+                    }
+                },
+                (),
+            )
+            .map_err(|_| {
+                anyhow::anyhow!("Failed to connect to midi port: {}", port_name)
+            })?;
+        Ok(Self {
+            port,
+            port_name,
+            midi_input_connection,
+        })
+    }
+}
+
+/// Owns the connections to ports. Enumerating and connecting to ports can block, so after the
+/// initial scan this runs on its own thread rather than the audio thread.
+struct PortScanner {
+    midi_input: MidiInput,
+    patterns: Vec<String>,
+    connections: Vec<PortConnection>,
+    message_sender: mpsc::Sender<SubscriptionMessage>,
+}
+
+impl PortScanner {
+    fn scan(&mut self) {
+        let matching_ports =
+            self.midi_input
+                .ports()
+                .into_iter()
+                .filter_map(|port| {
+                    let name = self.midi_input.port_name(&port).ok()?;
+                    if self.patterns.iter().any(|pattern| {
+                        port_name_matches_pattern(pattern, &name)
+                    }) {
+                        Some((port, name))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+        // Drop connections to ports which have gone away. A port which is unplugged and plugged
+        // back in usually gets a new id so this will also replace stale connections.
+        let message_sender = &self.message_sender;
+        self.connections.retain(|connection| {
+            let present = matching_ports
+                .iter()
+                .any(|(port, _)| port == &connection.port);
+            if !present {
+                log::warn!("Midi port disconnected: {}", connection.port_name);
+                let _ = message_sender.send(SubscriptionMessage::Disconnected(
+                    Arc::clone(&connection.port_name),
+                ));
+            }
+            present
+        });
+        for (port, name) in matching_ports {
+            if self.connections.iter().any(|c| c.port == port) {
+                continue;
+            }
+            match PortConnection::new(
+                port,
+                name.into(),
+                self.message_sender.clone(),
+            ) {
+                Ok(connection) => {
+                    log::info!(
+                        "Connected to midi port: {}",
+                        connection.port_name
+                    );
+                    let _ = self.message_sender.send(
+                        SubscriptionMessage::Connected(Arc::clone(
+                            &connection.port_name,
+                        )),
+                    );
+                    self.connections.push(connection);
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    }
+
+    /// Rescan periodically until the subscription is dropped. The rescan interval can be changed
+    /// by sending a new interval.
+    fn run(
+        mut self,
+        mut rescan_interval: Duration,
+        rescan_interval_receiver: mpsc::Receiver<Duration>,
+    ) {
+        loop {
+            match rescan_interval_receiver.recv_timeout(rescan_interval) {
+                Ok(new_rescan_interval) => {
+                    rescan_interval = new_rescan_interval
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => self.scan(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+/// Connects to every midi port whose name matches any of a collection of patterns, merging the
+/// events from all the ports into a single stream. The available ports are periodically rescanned
+/// on a background thread so devices that are plugged in after the subscription is created (or
+/// unplugged and plugged back in) are connected to automatically.
+pub struct MidiLiveSubscription {
+    connected_port_names: Vec<Arc<str>>,
+    message_receiver: mpsc::Receiver<SubscriptionMessage>,
+    // Events received while updating the connected port names, which haven't been yielded yet
+    pending_events: Vec<MidiPortEvent>,
+    // Dropping this stops the background thread.
+    rescan_interval_sender: mpsc::Sender<Duration>,
+}
+
+impl MidiLiveSubscription {
+    pub const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub(crate) fn new(midi_input: MidiInput, patterns: Vec<String>) -> Self {
+        let (message_sender, message_receiver) = mpsc::channel();
+        let mut scanner = PortScanner {
+            midi_input,
+            patterns,
+            connections: Vec::new(),
+            message_sender,
+        };
+        scanner.scan();
+        if scanner.connections.is_empty() {
+            log::warn!(
+                "No midi ports match any of {:?}. Waiting for a matching port to appear.",
+                scanner.patterns
+            );
+        }
+        let (rescan_interval_sender, rescan_interval_receiver) =
+            mpsc::channel();
+        thread::spawn(move || {
+            scanner.run(Self::DEFAULT_RESCAN_INTERVAL, rescan_interval_receiver)
+        });
+        let mut s = Self {
+            connected_port_names: Vec::new(),
+            message_receiver,
+            pending_events: Vec::new(),
+            rescan_interval_sender,
+        };
+        s.receive_messages();
+        s
+    }
+
+    /// Set how often to check for ports being added or removed.
+    pub fn with_rescan_interval(self, rescan_interval: Duration) -> Self {
+        let _ = self.rescan_interval_sender.send(rescan_interval);
+        self
+    }
+
+    /// The names of the ports which are currently connected.
+    pub fn connected_port_names(&mut self) -> impl Iterator<Item = &str> {
+        self.receive_messages();
+        self.connected_port_names.iter().map(|name| name.as_ref())
+    }
+
+    /// Handle all the messages sent from the background thread, keeping the events to be yielded
+    /// later.
+    fn receive_messages(&mut self) {
+        for message in self.message_receiver.try_iter() {
+            match message {
+                SubscriptionMessage::Event(midi_port_event) => {
+                    self.pending_events.push(midi_port_event)
+                }
+                SubscriptionMessage::Connected(port_name) => {
+                    self.connected_port_names.push(port_name)
+                }
+                SubscriptionMessage::Disconnected(port_name) => {
+                    self.connected_port_names.retain(|name| name != &port_name)
+                }
+            }
+        }
+    }
+
+    /// Events from all connected ports, tagged with the name of the port they came from. This
+    /// consumes `self` as the events can only be consumed once.
+    pub fn port_events(mut self) -> Sig<impl SigT<Item = MidiPortEvents>> {
+        Sig::from_buf_fn(move |ctx, buf| {
+            // This is called once per frame (not once per sample). This will add an imperceptible
+            // amount of latency (unless the output buffer is too large!), but reduce cpu usage.
+            buf.resize_with(ctx.num_samples, Default::default);
+            self.receive_messages();
+            let mut midi_port_events = MidiPortEvents::empty();
+            for midi_port_event in self.pending_events.drain(..) {
+                midi_port_events.push(midi_port_event);
+            }
+            // Only the first sample of each frame is populated with midi events.
+            buf[0] = midi_port_events;
+        })
+    }
+
+    /// Events from all connected ports merged into a single stream.
+    pub fn events(self) -> Sig<impl SigT<Item = MidiEvents>> {
+        self.port_events()
+            .map(|midi_port_events| midi_port_events.midi_events())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exact_match() {
+        assert!(port_name_matches_pattern("Launchkey", "Launchkey"));
+        assert!(!port_name_matches_pattern("Launchkey", "Launchkey MK3"));
+        assert!(!port_name_matches_pattern("Launchkey MK3", "Launchkey"));
+        assert!(port_name_matches_pattern("", ""));
+        assert!(!port_name_matches_pattern("", "a"));
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(port_name_matches_pattern("*", ""));
+        assert!(port_name_matches_pattern("*", "anything"));
+        assert!(port_name_matches_pattern("Launchkey*", "Launchkey MK3:0"));
+        assert!(port_name_matches_pattern("*MK3*", "Launchkey MK3:0"));
+        assert!(port_name_matches_pattern("*:0", "Launchkey MK3:0"));
+        assert!(!port_name_matches_pattern("*:1", "Launchkey MK3:0"));
+        assert!(port_name_matches_pattern("a*b*c", "aXbYbZc"));
+        assert!(!port_name_matches_pattern("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(port_name_matches_pattern("MK?", "MK3"));
+        assert!(!port_name_matches_pattern("MK?", "MK"));
+        assert!(!port_name_matches_pattern("MK?", "MK33"));
+        assert!(port_name_matches_pattern("?*", "x"));
+        assert!(!port_name_matches_pattern("?*", ""));
+    }
+
+    #[test]
+    fn backtracking() {
+        assert!(port_name_matches_pattern("*ab", "aab"));
+        assert!(port_name_matches_pattern("*a?c", "abacabc"));
+        assert!(!port_name_matches_pattern("*a?c", "abacab"));
+    }
+}
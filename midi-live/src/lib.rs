@@ -1,3 +1,9 @@
+mod hotplug;
+pub use hotplug::{
+    MidiLiveSubscription, MidiPortEvent, MidiPortEvents,
+    port_name_matches_pattern,
+};
+
 use caw_core::{Sig, SigT};
 use caw_midi::{MidiEvent, MidiMessages};
 use midir::{MidiInput, MidiInputConnection, MidiInputPort};
@@ -41,6 +47,21 @@ impl MidiLive {
         let port = &self.midi_input_ports[port_index];
         MidiLiveConnection::new(self.midi_input, port)
     }
+
+    /// Connect to all ports whose names match any of the given patterns, and continue to connect
+    /// to matching ports as they appear. In patterns, `*` matches any sequence of characters and
+    /// `?` matches any single character. Unlike `connect`, it's not an error if no ports currently
+    /// match.
+    pub fn subscribe<P: AsRef<str>>(
+        self,
+        patterns: impl IntoIterator<Item = P>,
+    ) -> MidiLiveSubscription {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| pattern.as_ref().to_string())
+            .collect();
+        MidiLiveSubscription::new(self.midi_input, patterns)
+    }
 }
 
 const NUM_CHANNELS: usize = 16;
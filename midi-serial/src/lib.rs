@@ -1,3 +1,6 @@
+pub mod parser;
+pub use parser::MidiStreamParser;
+
 use caw_core::{Sig, SigT};
 use caw_midi::MidiMessages;
 use midly::live::LiveEvent;
@@ -88,48 +91,30 @@ impl MidiLiveSerial {
     /// constraint is purely for simplicity and can be lifted eventually if necessary.
     pub fn channel(self, channel: u8) -> Sig<impl SigT<Item = MidiMessages>> {
         let mut raw_buf = Vec::new();
-        let mut message_buf = Vec::new();
+        let mut parser = MidiStreamParser::new();
         Sig::from_buf_fn(move |ctx, buf| {
             // This is called once per frame (not once per sample). This will add an imperceptible
             // amount of latency (unless the output buffer is too large!), but reduce cpu usage.
             buf.resize_with(ctx.num_samples, Default::default);
             let mut midi_messages = MidiMessages::empty();
             raw_buf.clear();
+            // The parser keeps its state between frames so messages split across multiple reads
+            // are still parsed correctly.
             if let Ok(()) = self.read_all_available(&mut raw_buf) {
-                let mut drain = raw_buf.drain(..).peekable();
-                // skip bytes until we get to the first midi status byte
-                while let Some(&first) = drain.peek() {
-                    if first > 127 {
-                        // midi status byte
-                        break;
-                    }
-                    drain.next();
-                }
-                message_buf.clear();
-                while let Some(byte) = drain.next() {
-                    message_buf.push(byte);
-                    // Keep reading bytes until we reach the end of the buffer or the next byte
-                    // would be the start of a new message.
-                    if let Some(&next_byte) = drain.peek() {
-                        if next_byte <= 127 {
-                            continue;
-                        }
-                    }
-                    // Hopefully at this point `message_buf` contains a valid midi message. Attempt
-                    // to parse it.
-                    if let Ok(LiveEvent::Midi {
+                parser.push_bytes(&raw_buf, |event| {
+                    // Discard messages that aren't meant for the requested channel. Eventually
+                    // we may want to support subscribing to multiple channels at once but for
+                    // simplicity we'll assume for now that only one channel can be subscribed.
+                    if let LiveEvent::Midi {
                         channel: message_channel,
                         message,
-                    }) = LiveEvent::parse(&message_buf)
+                    } = event
                     {
-                        // Discard messages that aren't meant for the requested channel. Eventually
-                        // we may want to support subscribing to multiple channels at once but for
-                        // simplicity we'll assume for now that only one channel can be subscribed.
                         if message_channel == channel {
                             midi_messages.push(message);
                         }
                     }
-                }
+                });
             }
             // Only the first sample of each frame is populated with midi messages.
             buf[0] = midi_messages;
@@ -0,0 +1,260 @@
+//! Incremental parser for a stream of raw MIDI bytes, such as those received from a serial port.
+//!
+//! Unlike `LiveEvent::parse` which expects to be given exactly one complete message, this parser
+//! is fed one byte at a time and handles:
+//!  - running status (data bytes with no status byte reuse the most recent channel status)
+//!  - SysEx messages of any length, which may also be split across several reads
+//!  - real-time messages (e.g. clock) interleaved inside other messages
+
+use midly::live::{LiveEvent, SystemRealtime};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+fn is_status_byte(byte: u8) -> bool {
+    byte & 0x80 != 0
+}
+
+fn is_realtime_status_byte(byte: u8) -> bool {
+    byte >= 0xF8
+}
+
+/// The number of data bytes which follow the given status byte. Not valid for SysEx.
+fn num_data_bytes(status: u8) -> usize {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => 2,
+        0xC0..=0xDF => 1,
+        0xF1 | 0xF3 => 1,
+        0xF2 => 2,
+        _ => 0,
+    }
+}
+
+#[derive(Default)]
+pub struct MidiStreamParser {
+    /// The status byte of the most recent channel message, used for running status.
+    running_status: Option<u8>,
+    /// The message currently being parsed, including its status byte.
+    message: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl MidiStreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn emit_message<F: FnMut(LiveEvent)>(&mut self, f: &mut F) {
+        if let Ok(event) = LiveEvent::parse(&self.message) {
+            f(event);
+        }
+        self.message.clear();
+    }
+
+    fn end_sysex<F: FnMut(LiveEvent)>(&mut self, f: &mut F) {
+        if self.in_sysex {
+            self.in_sysex = false;
+            self.emit_message(f);
+        }
+    }
+
+    /// Process a single byte, calling `f` on each message which is completed by the byte. Most
+    /// bytes complete at most one message, but a status byte which interrupts an unterminated
+    /// SysEx message may complete both the SysEx message and (if it has no data bytes) its own
+    /// message.
+    pub fn push_byte<F: FnMut(LiveEvent)>(&mut self, byte: u8, mut f: F) {
+        if is_realtime_status_byte(byte) {
+            // Real-time messages may appear anywhere in the stream (even in the middle of other
+            // messages) and don't affect the parser's state.
+            f(LiveEvent::Realtime(SystemRealtime::new(byte)));
+            return;
+        }
+        if is_status_byte(byte) {
+            // Any status byte terminates a SysEx message.
+            self.end_sysex(&mut f);
+            if byte == SYSEX_END {
+                return;
+            }
+            self.message.clear();
+            self.message.push(byte);
+            if byte < SYSEX_START {
+                self.running_status = Some(byte);
+            } else {
+                // System common messages cancel running status.
+                self.running_status = None;
+                if byte == SYSEX_START {
+                    self.in_sysex = true;
+                    return;
+                }
+            }
+        } else if self.in_sysex {
+            self.message.push(byte);
+            return;
+        } else if self.message.is_empty() {
+            match self.running_status {
+                Some(status) => {
+                    self.message.push(status);
+                    self.message.push(byte);
+                }
+                // Data byte with no preceding status byte. This can happen when we start
+                // listening to a device part way through a message.
+                None => return,
+            }
+        } else {
+            self.message.push(byte);
+        }
+        if self.message.len() > num_data_bytes(self.message[0]) {
+            self.emit_message(&mut f);
+        }
+    }
+
+    /// Process each byte in `bytes`, calling `f` on each complete message.
+    pub fn push_bytes<F: FnMut(LiveEvent)>(&mut self, bytes: &[u8], mut f: F) {
+        for &byte in bytes {
+            self.push_byte(byte, &mut f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use midly::{MidiMessage, live::SystemCommon};
+
+    fn parse(bytes: &[u8]) -> Vec<LiveEvent<'static>> {
+        let mut parser = MidiStreamParser::new();
+        let mut events = Vec::new();
+        parser.push_bytes(bytes, |event| events.push(event.to_static()));
+        events
+    }
+
+    fn parse_sysex(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut parser = MidiStreamParser::new();
+        let mut sysex = Vec::new();
+        parser.push_bytes(bytes, |event| {
+            if let LiveEvent::Common(SystemCommon::SysEx(data)) = event {
+                sysex.push(data.iter().map(|b| b.as_int()).collect());
+            }
+        });
+        sysex
+    }
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> LiveEvent<'static> {
+        LiveEvent::Midi {
+            channel: channel.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn complete_messages() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0x81, 60, 0]),
+            vec![
+                note_on(0, 60, 100),
+                LiveEvent::Midi {
+                    channel: 1.into(),
+                    message: MidiMessage::NoteOff {
+                        key: 60.into(),
+                        vel: 0.into(),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn running_status() {
+        assert_eq!(
+            parse(&[0x92, 60, 100, 64, 100, 67, 0]),
+            vec![note_on(2, 60, 100), note_on(2, 64, 100), note_on(2, 67, 0)]
+        );
+        assert_eq!(
+            parse(&[0xC0, 1, 2, 3]),
+            (1..=3)
+                .map(|program| LiveEvent::Midi {
+                    channel: 0.into(),
+                    message: MidiMessage::ProgramChange {
+                        program: program.into(),
+                    },
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF6, 64, 100]),
+            vec![
+                note_on(0, 60, 100),
+                LiveEvent::Common(SystemCommon::TuneRequest)
+            ]
+        );
+    }
+
+    #[test]
+    fn leading_data_bytes_are_skipped() {
+        assert_eq!(parse(&[100, 0x90, 60, 100]), vec![note_on(0, 60, 100)]);
+    }
+
+    #[test]
+    fn interleaved_realtime() {
+        let clock = LiveEvent::Realtime(SystemRealtime::TimingClock);
+        assert_eq!(
+            parse(&[0x90, 0xF8, 60, 0xF8, 100, 64, 0xFA, 100]),
+            vec![
+                clock,
+                clock,
+                note_on(0, 60, 100),
+                LiveEvent::Realtime(SystemRealtime::Start),
+                note_on(0, 64, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn sysex() {
+        let mut bytes = vec![SYSEX_START];
+        let data = (0..1000).map(|i| (i % 128) as u8).collect::<Vec<_>>();
+        bytes.extend_from_slice(&data);
+        bytes.push(SYSEX_END);
+        assert_eq!(parse_sysex(&bytes), vec![data]);
+    }
+
+    #[test]
+    fn sysex_with_realtime_and_split_reads() {
+        let mut parser = MidiStreamParser::new();
+        let mut events = Vec::new();
+        for chunk in [&[SYSEX_START, 1, 2][..], &[0xF8, 3], &[SYSEX_END, 0x90]]
+        {
+            parser.push_bytes(chunk, |event| events.push(event.to_static()));
+        }
+        // `to_static` discards the SysEx contents
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], LiveEvent::Realtime(SystemRealtime::TimingClock));
+        assert!(matches!(
+            events[1],
+            LiveEvent::Common(SystemCommon::SysEx(_))
+        ));
+        assert_eq!(
+            parse_sysex(&[SYSEX_START, 1, 2, 0xF8, 3, SYSEX_END]),
+            vec![vec![1, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn unterminated_sysex() {
+        assert_eq!(
+            parse_sysex(&[SYSEX_START, 1, 2, 0x90, 60, 100]),
+            vec![vec![1, 2]]
+        );
+        assert_eq!(
+            parse(&[SYSEX_START, 1, 2, 0x90, 60, 100])[1],
+            note_on(0, 60, 100)
+        );
+    }
+}
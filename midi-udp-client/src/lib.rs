@@ -1,30 +1,129 @@
 use caw_midi::MidiEvent;
-use midly::live::LiveEvent;
+use caw_midi_udp::protocol::{self, PacketWriter, TimestampedMidiEvent};
 pub use midly::{MidiMessage, num::u4};
-use std::net::{Ipv4Addr, ToSocketAddrs, UdpSocket};
+use std::{
+    cell::{Cell, RefCell},
+    net::{Ipv4Addr, ToSocketAddrs, UdpSocket},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The format of datagrams sent by a `MidiUdpClient`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MidiUdpFormat {
+    /// Multiple timestamped events per packet with sequence numbers (see `caw_midi_udp::protocol`)
+    #[default]
+    Framed,
+    /// A single raw MIDI message per packet with no sequence numbers or timestamps. Use this to
+    /// communicate with servers that predate the framed format.
+    Raw,
+}
 
 pub struct MidiUdpClient {
     socket: UdpSocket,
+    format: MidiUdpFormat,
+    session: u32,
+    start: Instant,
+    next_sequence: Cell<u32>,
+    queued: RefCell<Vec<TimestampedMidiEvent>>,
 }
 
 impl MidiUdpClient {
     pub fn new<A: ToSocketAddrs>(addrs: A) -> anyhow::Result<Self> {
         let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
         socket.connect(addrs)?;
-        Ok(Self { socket })
+        // The session only needs to differ from that of other clients sending to the same server,
+        // and from previous runs of this client.
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ (d.as_secs() as u32))
+            .unwrap_or(0)
+            ^ std::process::id();
+        Ok(Self {
+            socket,
+            format: MidiUdpFormat::default(),
+            session,
+            start: Instant::now(),
+            next_sequence: Cell::new(0),
+            queued: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn with_format(self, format: MidiUdpFormat) -> Self {
+        Self { format, ..self }
     }
 
-    pub fn send(
+    fn timestamp_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn send_packet(
         &self,
-        MidiEvent { channel, message }: MidiEvent,
+        events: &[TimestampedMidiEvent],
     ) -> anyhow::Result<()> {
-        let event = LiveEvent::Midi { channel, message };
-        let mut buf = Vec::new();
-        match event.write_std(&mut buf) {
-            Ok(()) => (),
-            Err(e) => anyhow::bail!("{e}"),
+        let mut remaining = events;
+        while !remaining.is_empty() {
+            let sequence = self.next_sequence.get();
+            self.next_sequence.set(sequence.wrapping_add(1));
+            let mut writer = PacketWriter::new(
+                self.session,
+                sequence,
+                remaining[0].timestamp_us,
+            );
+            let mut num_written = 0;
+            for event in remaining {
+                if !writer.push(event.clone())? {
+                    break;
+                }
+                num_written += 1;
+            }
+            if writer.is_empty() {
+                anyhow::bail!("MIDI event too large to fit in a packet");
+            }
+            self.socket.send(&writer.finish())?;
+            remaining = &remaining[num_written..];
         }
-        self.socket.send(&buf)?;
         Ok(())
     }
+
+    /// Immediately send a single event.
+    pub fn send(&self, midi_event: MidiEvent) -> anyhow::Result<()> {
+        match self.format {
+            MidiUdpFormat::Framed => {
+                self.send_packet(&[TimestampedMidiEvent {
+                    timestamp_us: self.timestamp_us(),
+                    midi_event,
+                }])
+            }
+            MidiUdpFormat::Raw => {
+                let mut buf = Vec::new();
+                protocol::encode_midi_event(midi_event, &mut buf)?;
+                self.socket.send(&buf)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Record an event to be sent on the next call to `flush`. The time of the call to `queue` is
+    /// sent along with the event.
+    pub fn queue(&self, midi_event: MidiEvent) {
+        let timestamp_us = self.timestamp_us();
+        self.queued.borrow_mut().push(TimestampedMidiEvent {
+            timestamp_us,
+            midi_event,
+        });
+    }
+
+    /// Send all queued events, packing as many events into each packet as possible.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let events = std::mem::take(&mut *self.queued.borrow_mut());
+        match self.format {
+            MidiUdpFormat::Framed => self.send_packet(&events),
+            MidiUdpFormat::Raw => {
+                for TimestampedMidiEvent { midi_event, .. } in events {
+                    self.send(midi_event)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
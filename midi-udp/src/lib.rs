@@ -1,17 +1,105 @@
 use caw_core::{Buf, SigCtx, SigT};
 use caw_midi::{MidiEvent, MidiEvents};
-use midly::live::LiveEvent;
 use std::{
+    collections::HashMap,
     io,
     net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
 };
 
-const BUF_SIZE: usize = 256;
+pub mod protocol;
+use protocol::{Datagram, Packet, TimestampedMidiEvent};
+
+const BUF_SIZE: usize = 2048;
+
+/// Counters describing the packets received by a `MidiLiveUdp` server. Loss and reordering can
+/// only be detected for packets in the framed format, since raw packets have no sequence numbers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MidiUdpStats {
+    pub framed_packets_received: u64,
+    pub raw_packets_received: u64,
+    pub events_received: u64,
+    /// Packets which were skipped over in the sequence and haven't (yet) arrived
+    pub packets_lost: u64,
+    /// Packets which arrived after a packet with a later sequence number
+    pub packets_reordered: u64,
+    /// Packets which were dropped because a packet with the same sequence number was already
+    /// received, or which were too old to check
+    pub packets_duplicated: u64,
+    pub malformed_packets_received: u64,
+}
+
+/// The number of sequence numbers before the most recent one which are remembered so that
+/// duplicate packets can be detected. Older packets are dropped.
+const SEQUENCE_WINDOW: u32 = 64;
+
+/// How a packet's sequence number relates to the packets received before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SequenceCheck {
+    /// The packet is later than all previous packets, and `lost` packets were skipped over
+    InOrder {
+        lost: u32,
+    },
+    /// The packet was previously skipped over and counted as lost
+    Reordered,
+    Duplicate,
+}
+
+/// Tracks the sequence numbers of packets from a single sender session.
+struct SessionState {
+    next_sequence: u32,
+    // Bit `i` is set if the packet with sequence number `next_sequence - 1 - i` was received.
+    received: u64,
+}
+
+impl SessionState {
+    fn new(sequence: u32) -> Self {
+        Self {
+            next_sequence: sequence,
+            received: 0,
+        }
+    }
+
+    fn check(&mut self, sequence: u32) -> SequenceCheck {
+        // Interpret the difference between sequence numbers as signed so that wrapping around is
+        // handled correctly.
+        let delta = sequence.wrapping_sub(self.next_sequence) as i32;
+        if delta >= 0 {
+            let shift = delta as u32 + 1;
+            self.received = if shift >= SEQUENCE_WINDOW {
+                0
+            } else {
+                self.received << shift
+            };
+            self.received |= 1;
+            self.next_sequence = sequence.wrapping_add(1);
+            SequenceCheck::InOrder { lost: delta as u32 }
+        } else {
+            let age = (-1 - delta) as u32;
+            if age >= SEQUENCE_WINDOW {
+                return SequenceCheck::Duplicate;
+            }
+            let bit = 1 << age;
+            if self.received & bit != 0 {
+                SequenceCheck::Duplicate
+            } else {
+                self.received |= bit;
+                SequenceCheck::Reordered
+            }
+        }
+    }
+}
 
 pub struct MidiLiveUdp {
     socket: UdpSocket,
     buf_raw: Vec<u8>,
     buf: Vec<MidiEvents>,
+    sessions: HashMap<u32, SessionState>,
+    stats: MidiUdpStats,
+    // Events received during the current frame along with the session they came from. Events
+    // from framed packets are sorted by the sender's timestamp before being yielded, so that events
+    // arrive in the order they were sent even when packets are reordered in transit.
+    framed_events_this_frame: Vec<(u32, TimestampedMidiEvent)>,
+    raw_events_this_frame: Vec<MidiEvent>,
 }
 
 impl MidiLiveUdp {
@@ -24,6 +112,10 @@ impl MidiLiveUdp {
             socket,
             buf_raw,
             buf: Vec::new(),
+            sessions: HashMap::new(),
+            stats: MidiUdpStats::default(),
+            framed_events_this_frame: Vec::new(),
+            raw_events_this_frame: Vec::new(),
         })
     }
 
@@ -35,41 +127,100 @@ impl MidiLiveUdp {
         Ok(self.socket.local_addr()?)
     }
 
-    fn recv_into_buf(&mut self) -> Result<bool, io::Error> {
+    pub fn stats(&self) -> MidiUdpStats {
+        self.stats
+    }
+
+    /// Returns the number of bytes received, or `None` if no datagram is available.
+    fn recv_into_buf(&mut self) -> Result<Option<usize>, io::Error> {
         match self.socket.recv(&mut self.buf_raw) {
             Ok(size) => {
                 if size >= BUF_SIZE {
                     log::warn!("UDP message too long for buffer!");
-                    Ok(false)
+                    self.stats.malformed_packets_received += 1;
+                    Ok(Some(0))
                 } else {
-                    Ok(true)
+                    Ok(Some(size))
                 }
             }
             Err(error) => match error.kind() {
                 io::ErrorKind::WouldBlock => {
                     // There is currently no datagram available.
-                    Ok(false)
+                    Ok(None)
                 }
                 _ => Err(error),
             },
         }
     }
 
-    fn recv_midi_event(&mut self) -> Result<Option<MidiEvent>, io::Error> {
-        if self.recv_into_buf()? {
-            match LiveEvent::parse(&self.buf_raw) {
-                Ok(LiveEvent::Midi { channel, message }) => {
-                    Ok(Some(MidiEvent { channel, message }))
-                }
-                Ok(_) => Ok(None),
-                Err(e) => {
-                    log::warn!("Failed to parse midi event: {e}");
-                    Ok(None)
+    fn handle_packet(&mut self, packet: Packet) {
+        self.stats.framed_packets_received += 1;
+        let session =
+            self.sessions.entry(packet.session).or_insert_with(|| {
+                log::info!("New MIDI UDP session: {}", packet.session);
+                SessionState::new(packet.sequence)
+            });
+        match session.check(packet.sequence) {
+            SequenceCheck::InOrder { lost } => {
+                if lost > 0 {
+                    log::warn!(
+                        "Lost {} MIDI UDP packet(s) (got sequence number {})",
+                        lost,
+                        packet.sequence
+                    );
+                    self.stats.packets_lost += lost as u64;
                 }
             }
-        } else {
-            Ok(None)
+            SequenceCheck::Reordered => {
+                // This packet was previously counted as lost.
+                log::warn!(
+                    "Received MIDI UDP packet out of order (sequence number {})",
+                    packet.sequence
+                );
+                self.stats.packets_reordered += 1;
+                self.stats.packets_lost =
+                    self.stats.packets_lost.saturating_sub(1);
+            }
+            SequenceCheck::Duplicate => {
+                log::warn!(
+                    "Dropping duplicate MIDI UDP packet (sequence number {})",
+                    packet.sequence
+                );
+                self.stats.packets_duplicated += 1;
+                return;
+            }
+        }
+        self.stats.events_received += packet.events.len() as u64;
+        self.framed_events_this_frame.extend(
+            packet
+                .events
+                .into_iter()
+                .map(|event| (packet.session, event)),
+        );
+    }
+
+    /// Receive a single datagram. Returns `false` if there were no datagrams available.
+    fn recv_datagram(&mut self) -> Result<bool, io::Error> {
+        let Some(size) = self.recv_into_buf()? else {
+            return Ok(false);
+        };
+        if size == 0 {
+            return Ok(true);
         }
+        match protocol::decode_datagram(&self.buf_raw[0..size]) {
+            Ok(Some(Datagram::Framed(packet))) => self.handle_packet(packet),
+            Ok(Some(Datagram::Raw(midi_event))) => {
+                self.stats.raw_packets_received += 1;
+                self.stats.events_received += 1;
+                self.raw_events_this_frame.push(midi_event);
+            }
+            Ok(None) => self.stats.raw_packets_received += 1,
+            Err(e) => {
+                log::warn!("Failed to parse MIDI UDP packet: {e}");
+                self.stats.malformed_packets_received += 1;
+            }
+        }
+        Ok(true)
     }
 }
 
@@ -80,21 +231,72 @@ impl SigT for MidiLiveUdp {
         // This is called once per frame (not once per sample). This will add an imperceptible
         // amount of latency (unless the output buffer is too large!), but reduce cpu usage.
         self.buf.resize_with(ctx.num_samples, Default::default);
-        let mut midi_events = MidiEvents::empty();
+        self.framed_events_this_frame.clear();
+        self.raw_events_this_frame.clear();
         loop {
-            match self.recv_midi_event() {
+            match self.recv_datagram() {
                 Err(e) => {
                     log::warn!("IO error reading from UDP socket: {e}");
                     break;
                 }
-                Ok(None) => break,
-                Ok(Some(midi_event)) => {
-                    midi_events.push(midi_event);
-                }
+                Ok(false) => break,
+                Ok(true) => (),
             }
         }
+        // Timestamps are only comparable within a session. The sort is stable so events with the
+        // same timestamp remain in the order they were received.
+        self.framed_events_this_frame
+            .sort_by_key(|(session, event)| (*session, event.timestamp_us));
+        let mut midi_events = MidiEvents::empty();
+        for (_, event) in self.framed_events_this_frame.drain(..) {
+            midi_events.push(event.midi_event);
+        }
+        for midi_event in self.raw_events_this_frame.drain(..) {
+            midi_events.push(midi_event);
+        }
         // Only the first sample of each frame is populated with midi messages.
         self.buf[0] = midi_events;
         &self.buf
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sequence_check() {
+        let mut session = SessionState::new(10);
+        assert_eq!(session.check(10), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(session.check(13), SequenceCheck::InOrder { lost: 2 });
+        assert_eq!(session.check(12), SequenceCheck::Reordered);
+        assert_eq!(session.check(12), SequenceCheck::Duplicate);
+        assert_eq!(session.check(13), SequenceCheck::Duplicate);
+        assert_eq!(session.check(10), SequenceCheck::Duplicate);
+        assert_eq!(session.check(11), SequenceCheck::Reordered);
+        assert_eq!(session.check(14), SequenceCheck::InOrder { lost: 0 });
+    }
+
+    #[test]
+    fn sequence_check_wraps_around() {
+        let mut session = SessionState::new(u32::MAX);
+        assert_eq!(session.check(u32::MAX), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(session.check(1), SequenceCheck::InOrder { lost: 1 });
+        assert_eq!(session.check(0), SequenceCheck::Reordered);
+        assert_eq!(session.check(u32::MAX), SequenceCheck::Duplicate);
+    }
+
+    #[test]
+    fn old_packets_are_dropped() {
+        let mut session = SessionState::new(0);
+        assert_eq!(session.check(0), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(
+            session.check(SEQUENCE_WINDOW + 1),
+            SequenceCheck::InOrder {
+                lost: SEQUENCE_WINDOW
+            }
+        );
+        assert_eq!(session.check(1), SequenceCheck::Duplicate);
+        assert_eq!(session.check(2), SequenceCheck::Reordered);
+    }
+}
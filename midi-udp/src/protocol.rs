@@ -0,0 +1,315 @@
+//! Wire format for sending MIDI events over UDP.
+//!
+//! A framed packet carries any number of MIDI events along with enough information for the
+//! receiver to detect lost and reordered packets. All integers are little-endian:
+//!
+//! | field     | size | description                                              |
+//! |-----------|------|----------------------------------------------------------|
+//! | magic     | 4    | the bytes "CAWM"                                         |
+//! | version   | 1    | the version of the format (currently 1)                  |
+//! | session   | 4    | chosen by the sender when it starts                      |
+//! | sequence  | 4    | incremented by 1 for each packet sent during a session   |
+//! | timestamp | 8    | microseconds since the start of the session              |
+//! | count     | 1    | number of events in the packet                           |
+//! | events    | ...  | `count` events                                           |
+//!
+//! Each event is encoded as:
+//!
+//! | field     | size | description                                              |
+//! |-----------|------|----------------------------------------------------------|
+//! | offset    | 4    | microseconds between the packet timestamp and the event  |
+//! | length    | 1    | number of bytes in the MIDI message                      |
+//! | message   | ...  | the MIDI message including its status byte               |
+//!
+//! Datagrams which don't begin with the magic bytes are parsed as a single raw MIDI message. This
+//! is unambiguous since the first byte of a MIDI message is a status byte which always has its
+//! high bit set, whereas the first byte of the magic bytes doesn't.
+
+use caw_midi::MidiEvent;
+use midly::live::LiveEvent;
+
+pub const MAGIC: [u8; 4] = *b"CAWM";
+
+pub const VERSION: u8 = 1;
+
+pub const HEADER_SIZE: usize = 22;
+
+/// The max number of bytes that can somewhat reliably be sent in a single UDP datagram.
+pub const MAX_PACKET_SIZE: usize = 508;
+
+/// Events with a timestamp after the packet's timestamp are stored as an offset relative to the
+/// packet's timestamp. This is the size of the offset plus the length byte.
+const EVENT_HEADER_SIZE: usize = 5;
+
+#[derive(Clone, Debug)]
+pub struct TimestampedMidiEvent {
+    /// Microseconds since the start of the sender's session
+    pub timestamp_us: u64,
+    pub midi_event: MidiEvent,
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub session: u32,
+    pub sequence: u32,
+    pub timestamp_us: u64,
+    pub events: Vec<TimestampedMidiEvent>,
+}
+
+/// The contents of a single UDP datagram.
+#[derive(Clone, Debug)]
+pub enum Datagram {
+    Framed(Packet),
+    /// A single MIDI event in the original unframed format.
+    Raw(MidiEvent),
+}
+
+/// Append the bytes of a MIDI event (including its status byte) to `out`.
+pub fn encode_midi_event(
+    MidiEvent { channel, message }: MidiEvent,
+    out: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let event = LiveEvent::Midi { channel, message };
+    match event.write_std(out) {
+        Ok(()) => Ok(()),
+        Err(e) => anyhow::bail!("{e}"),
+    }
+}
+
+/// Builds a framed packet one event at a time, keeping track of how much space is left.
+pub struct PacketWriter {
+    buf: Vec<u8>,
+    count: u8,
+    timestamp_us: u64,
+    event_buf: Vec<u8>,
+}
+
+impl PacketWriter {
+    pub fn new(session: u32, sequence: u32, timestamp_us: u64) -> Self {
+        let mut buf = Vec::with_capacity(MAX_PACKET_SIZE);
+        buf.extend_from_slice(&MAGIC);
+        buf.push(VERSION);
+        buf.extend_from_slice(&session.to_le_bytes());
+        buf.extend_from_slice(&sequence.to_le_bytes());
+        buf.extend_from_slice(&timestamp_us.to_le_bytes());
+        // placeholder for the event count
+        buf.push(0);
+        Self {
+            buf,
+            count: 0,
+            timestamp_us,
+            event_buf: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Attempts to add an event to the packet. Returns `Ok(false)` without changing the packet if
+    /// there isn't enough space left in the packet for the event.
+    pub fn push(
+        &mut self,
+        TimestampedMidiEvent {
+            timestamp_us,
+            midi_event,
+        }: TimestampedMidiEvent,
+    ) -> anyhow::Result<bool> {
+        self.event_buf.clear();
+        encode_midi_event(midi_event, &mut self.event_buf)?;
+        if self.event_buf.len() > u8::MAX as usize {
+            anyhow::bail!(
+                "MIDI message too long ({} bytes)",
+                self.event_buf.len()
+            );
+        }
+        if self.count == u8::MAX
+            || self.buf.len() + EVENT_HEADER_SIZE + self.event_buf.len()
+                > MAX_PACKET_SIZE
+        {
+            return Ok(false);
+        }
+        let offset_us = timestamp_us
+            .saturating_sub(self.timestamp_us)
+            .min(u32::MAX as u64) as u32;
+        self.buf.extend_from_slice(&offset_us.to_le_bytes());
+        self.buf.push(self.event_buf.len() as u8);
+        self.buf.extend_from_slice(&self.event_buf);
+        self.count += 1;
+        Ok(true)
+    }
+
+    /// Returns the encoded packet.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf[HEADER_SIZE - 1] = self.count;
+        self.buf
+    }
+}
+
+fn read_array<const N: usize>(
+    bytes: &[u8],
+    offset: &mut usize,
+) -> anyhow::Result<[u8; N]> {
+    let Some(slice) = bytes.get(*offset..(*offset + N)) else {
+        anyhow::bail!("Unexpected end of packet");
+    };
+    *offset += N;
+    let mut array = [0; N];
+    array.copy_from_slice(slice);
+    Ok(array)
+}
+
+fn decode_midi_event(bytes: &[u8]) -> anyhow::Result<Option<MidiEvent>> {
+    match LiveEvent::parse(bytes) {
+        Ok(LiveEvent::Midi { channel, message }) => {
+            Ok(Some(MidiEvent { channel, message }))
+        }
+        // System messages aren't represented by `MidiEvent`.
+        Ok(_) => Ok(None),
+        Err(e) => anyhow::bail!("Failed to parse midi event: {e}"),
+    }
+}
+
+fn decode_packet(bytes: &[u8]) -> anyhow::Result<Packet> {
+    let mut offset = MAGIC.len();
+    let [version] = read_array(bytes, &mut offset)?;
+    if version != VERSION {
+        anyhow::bail!("Unsupported packet version: {version}");
+    }
+    let session = u32::from_le_bytes(read_array(bytes, &mut offset)?);
+    let sequence = u32::from_le_bytes(read_array(bytes, &mut offset)?);
+    let timestamp_us = u64::from_le_bytes(read_array(bytes, &mut offset)?);
+    let [count] = read_array(bytes, &mut offset)?;
+    let mut events = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let offset_us = u32::from_le_bytes(read_array(bytes, &mut offset)?);
+        let [length] = read_array(bytes, &mut offset)?;
+        let Some(message_bytes) = bytes.get(offset..(offset + length as usize))
+        else {
+            anyhow::bail!("Unexpected end of packet");
+        };
+        offset += length as usize;
+        let Some(event_timestamp_us) =
+            timestamp_us.checked_add(offset_us as u64)
+        else {
+            anyhow::bail!("Event timestamp overflows");
+        };
+        if let Some(midi_event) = decode_midi_event(message_bytes)? {
+            events.push(TimestampedMidiEvent {
+                timestamp_us: event_timestamp_us,
+                midi_event,
+            });
+        }
+    }
+    Ok(Packet {
+        session,
+        sequence,
+        timestamp_us,
+        events,
+    })
+}
+
+/// Parse the contents of a datagram. Returns `Ok(None)` for raw datagrams containing valid MIDI
+/// messages which can't be represented as a `MidiEvent` (such as system messages).
+pub fn decode_datagram(bytes: &[u8]) -> anyhow::Result<Option<Datagram>> {
+    if bytes.starts_with(&MAGIC) {
+        Ok(Some(Datagram::Framed(decode_packet(bytes)?)))
+    } else {
+        Ok(decode_midi_event(bytes)?.map(Datagram::Raw))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use midly::MidiMessage;
+
+    fn note_on(key: u8) -> MidiEvent {
+        MidiEvent {
+            channel: 3.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn framed_round_trip() {
+        let mut writer = PacketWriter::new(7, 42, 1000);
+        for (i, key) in [60, 64, 67].into_iter().enumerate() {
+            assert!(
+                writer
+                    .push(TimestampedMidiEvent {
+                        timestamp_us: 1000 + i as u64 * 10,
+                        midi_event: note_on(key),
+                    })
+                    .unwrap()
+            );
+        }
+        let bytes = writer.finish();
+        let Some(Datagram::Framed(packet)) = decode_datagram(&bytes).unwrap()
+        else {
+            panic!("expected framed packet");
+        };
+        assert_eq!(packet.session, 7);
+        assert_eq!(packet.sequence, 42);
+        assert_eq!(packet.events.len(), 3);
+        assert_eq!(packet.events[2].timestamp_us, 1020);
+        assert_eq!(packet.events[1].midi_event.message, note_on(64).message);
+    }
+
+    #[test]
+    fn raw_fallback() {
+        let mut bytes = Vec::new();
+        encode_midi_event(note_on(60), &mut bytes).unwrap();
+        let Some(Datagram::Raw(event)) = decode_datagram(&bytes).unwrap()
+        else {
+            panic!("expected raw event");
+        };
+        assert_eq!(event.channel.as_int(), 3);
+    }
+
+    #[test]
+    fn packet_size_is_limited() {
+        let mut writer = PacketWriter::new(0, 0, 0);
+        let mut count = 0;
+        while writer
+            .push(TimestampedMidiEvent {
+                timestamp_us: 0,
+                midi_event: note_on(60),
+            })
+            .unwrap()
+        {
+            count += 1;
+        }
+        assert!(writer.finish().len() <= MAX_PACKET_SIZE);
+        assert!(count > 1);
+    }
+
+    #[test]
+    fn timestamp_overflow_is_rejected() {
+        let mut writer = PacketWriter::new(0, 0, u64::MAX - 5);
+        assert!(
+            writer
+                .push(TimestampedMidiEvent {
+                    timestamp_us: u64::MAX,
+                    midi_event: note_on(60),
+                })
+                .unwrap()
+        );
+        assert!(decode_datagram(&writer.finish()).is_ok());
+        let mut writer = PacketWriter::new(0, 0, u64::MAX - 5);
+        writer
+            .push(TimestampedMidiEvent {
+                timestamp_us: u64::MAX - 5,
+                midi_event: note_on(60),
+            })
+            .unwrap();
+        let mut bytes = writer.finish();
+        // Corrupt the event's offset so it overflows the packet's timestamp.
+        bytes[HEADER_SIZE..(HEADER_SIZE + 4)]
+            .copy_from_slice(&10_u32.to_le_bytes());
+        assert!(decode_datagram(&bytes).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, GateToTrigRisingEdge, Sig, SigCtx, SigT};
+use itertools::izip;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+builder! {
+    #[constructor = "bernoulli_gate"]
+    #[constructor_doc = "Randomly route each trigger to one of two outputs"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // The probability that a trigger is routed to the second output
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.5]
+        probability_01: f32,
+        // In latch mode, the outputs are gates which stay high until a trigger is routed to the
+        // other output.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "L"]
+        #[default = false]
+        latch: bool,
+    }
+}
+
+impl<P, L> Props<P, L>
+where
+    P: SigT<Item = f32>,
+    L: SigT<Item = bool>,
+{
+    /// Returns the two outputs as separate signals.
+    pub fn outputs<S>(
+        self,
+        trig: S,
+    ) -> (Sig<impl SigT<Item = bool>>, Sig<impl SigT<Item = bool>>)
+    where
+        S: SigT<Item = bool>,
+    {
+        let outputs = Sig(self.into_sig(trig)).shared();
+        (outputs.clone().map(|(a, _)| a), outputs.map(|(_, b)| b))
+    }
+}
+
+impl<P, L> Filter for Props<P, L>
+where
+    P: SigT<Item = f32>,
+    L: SigT<Item = bool>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = BernoulliGate<S, P, L>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        BernoulliGate {
+            props: self,
+            trig: Sig(sig).gate_to_trig_rising_edge().0,
+            rng: StdRng::from_os_rng(),
+            second: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct BernoulliGate<S, P, L>
+where
+    S: SigT<Item = bool>,
+    P: SigT<Item = f32>,
+    L: SigT<Item = bool>,
+{
+    props: Props<P, L>,
+    trig: GateToTrigRisingEdge<S>,
+    rng: StdRng,
+    /// Whether the most recent trigger was routed to the second output, or `None` before the
+    /// first trigger
+    second: Option<bool>,
+    buf: Vec<(bool, bool)>,
+}
+
+impl<S, P, L> SigT for BernoulliGate<S, P, L>
+where
+    S: SigT<Item = bool>,
+    P: SigT<Item = f32>,
+    L: SigT<Item = bool>,
+{
+    /// The values of the first and second outputs
+    type Item = (bool, bool);
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let trig = self.trig.sample(ctx);
+        let probability_01 = self.props.probability_01.sample(ctx);
+        let latch = self.props.latch.sample(ctx);
+        for (trig, probability_01, latch) in izip! {
+            trig.iter(),
+            probability_01.iter(),
+            latch.iter(),
+        } {
+            if trig {
+                self.second = Some(self.rng.random::<f32>() < probability_01);
+            }
+            let out = match self.second {
+                Some(second) if trig || latch => (!second, second),
+                _ => (false, false),
+            };
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_str(s: &str) -> Sig<impl SigT<Item = bool>> {
+        let mut values =
+            s.chars().map(|c| c == 'x').collect::<Vec<_>>().into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    fn to_strs<S>(mut sig: S, num_samples: usize) -> (String, String)
+    where
+        S: SigT<Item = (bool, bool)>,
+    {
+        let ctx = SigCtx {
+            sample_rate_hz: 1000.0,
+            batch_index: 0,
+            num_samples,
+        };
+        let to_char = |b: bool| if b { 'x' } else { '.' };
+        sig.sample(&ctx)
+            .iter()
+            .map(|(a, b)| (to_char(a), to_char(b)))
+            .unzip()
+    }
+
+    #[test]
+    fn routes_triggers_by_probability() {
+        let sig =
+            from_str("..x..x").filter(bernoulli_gate().probability_01(1.0));
+        assert_eq!(to_strs(sig, 6), ("......".into(), "..x..x".into()));
+        let sig =
+            from_str("..x..x").filter(bernoulli_gate().probability_01(0.0));
+        assert_eq!(to_strs(sig, 6), ("..x..x".into(), "......".into()));
+    }
+
+    #[test]
+    fn latch_outputs_are_low_until_first_trigger() {
+        let sig = from_str("...x..")
+            .filter(bernoulli_gate().probability_01(0.0).latch(true));
+        assert_eq!(to_strs(sig, 6), ("...xxx".into(), "......".into()));
+        let sig = from_str("...x..")
+            .filter(bernoulli_gate().probability_01(1.0).latch(true));
+        assert_eq!(to_strs(sig, 6), ("......".into(), "...xxx".into()));
+    }
+}
@@ -17,6 +17,18 @@ pub enum ChorusLfoOffset {
     Interleave(Channel),
 }
 
+impl ChorusLfoOffset {
+    /// The phase offset of the first LFO on this channel, where `right_offset_01` is the offset
+    /// used for the right channel when interleaving.
+    pub(crate) fn offset_01(self, right_offset_01: f32) -> f32 {
+        match self {
+            Self::None => 0.,
+            Self::Interleave(Channel::Left) => 0.,
+            Self::Interleave(Channel::Right) => right_offset_01,
+        }
+    }
+}
+
 builder! {
     #[constructor = "chorus"]
     #[constructor_doc = "Mix the signal with pitch-shifted copies of itself to produce interference patterns"]
@@ -104,14 +116,9 @@ where
     {
         let lfo_rate_hz = sig_shared(self.lfo_rate_hz);
         let lfo_reset_trig = sig_shared(self.lfo_reset_trig);
-        let total_offset_01 = match self.lfo_offset {
-            ChorusLfoOffset::None => 0.,
-            ChorusLfoOffset::Interleave(Channel::Left) => 0.,
-            ChorusLfoOffset::Interleave(Channel::Right) => {
-                // half of the step between the offset of two voices
-                0.5 / self.num_voices as f32
-            }
-        };
+        // half of the step between the offset of two voices
+        let total_offset_01 =
+            self.lfo_offset.offset_01(0.5 / self.num_voices as f32);
         let lfos: Vec<Osc<W, R, T>> = (0..self.num_voices)
             .map(|i| {
                 let offset_01 =
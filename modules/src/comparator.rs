@@ -0,0 +1,130 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+
+builder! {
+    #[constructor = "comparator"]
+    #[constructor_doc = "Schmitt trigger which turns on when its input rises above a threshold and off when it falls below"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "T"]
+        #[default = 0.0]
+        threshold: f32,
+        // The output turns on when the input exceeds the threshold by half the hysteresis, and
+        // turns off when the input falls below the threshold by half the hysteresis. This
+        // prevents noisy inputs near the threshold from toggling the output rapidly.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "H"]
+        #[default = 0.0]
+        hysteresis: f32,
+    }
+}
+
+impl<T, H> Filter for Props<T, H>
+where
+    T: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Comparator<S, T, H>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Comparator {
+            props: self,
+            sig,
+            state: false,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Comparator<S, T, H>
+where
+    S: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+{
+    props: Props<T, H>,
+    sig: S,
+    state: bool,
+    buf: Vec<bool>,
+}
+
+impl<S, T, H> SigT for Comparator<S, T, H>
+where
+    S: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let sig = self.sig.sample(ctx);
+        let threshold = self.props.threshold.sample(ctx);
+        let hysteresis = self.props.hysteresis.sample(ctx);
+        for (sample, threshold, hysteresis) in izip! {
+            sig.iter(),
+            threshold.iter(),
+            hysteresis.iter(),
+        } {
+            let half_hysteresis = hysteresis.abs() / 2.0;
+            if self.state {
+                if sample < threshold - half_hysteresis {
+                    self.state = false;
+                }
+            } else if sample > threshold + half_hysteresis {
+                self.state = true;
+            }
+            self.buf.push(self.state);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use caw_core::Sig;
+
+    fn from_vec(values: Vec<f32>) -> Sig<impl SigT<Item = f32>> {
+        let mut values = values.into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    fn run<S: SigT<Item = bool>>(mut sig: S, num_samples: usize) -> Vec<bool> {
+        let ctx = SigCtx {
+            sample_rate_hz: 1000.0,
+            batch_index: 0,
+            num_samples,
+        };
+        sig.sample(&ctx).iter().collect()
+    }
+
+    #[test]
+    fn without_hysteresis_follows_threshold() {
+        let values = vec![0.0, 0.6, 0.4, 0.6, 0.4];
+        let sig = from_vec(values).filter(comparator().threshold(0.5));
+        assert_eq!(run(sig, 5), [false, true, false, true, false]);
+    }
+
+    #[test]
+    fn hysteresis_ignores_noise_near_threshold() {
+        // The output turns on above 0.6 and off below 0.4.
+        let values = vec![0.55, 0.65, 0.45, 0.55, 0.35, 0.45, 0.55, 0.65];
+        let sig = from_vec(values)
+            .filter(comparator().threshold(0.5).hysteresis(0.2));
+        assert_eq!(
+            run(sig, 8),
+            [false, true, true, true, false, false, false, true]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, GateToTrigRisingEdge, Sig, SigCtx, SigT};
+use itertools::izip;
+
+builder! {
+    #[constructor = "counter"]
+    #[constructor_doc = "Counts the number of times its input is triggered"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // Count down instead of up while this is true
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "D"]
+        #[default = false]
+        down: bool,
+        // The count stays below this value. 0 means the count is unbounded.
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "L"]
+        #[default = 0]
+        length: u32,
+        // When true the count wraps around on reaching either end of its range, otherwise it
+        // stays at the end
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "W"]
+        #[default = true]
+        wrap: bool,
+        // Set the count to 0
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        #[default = false]
+        reset_trig: bool,
+    }
+}
+
+impl<D, L, W, R> Filter for Props<D, L, W, R>
+where
+    D: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    W: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = Counter<S, D, L, W, R>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Counter {
+            props: self,
+            trig: Sig(sig).gate_to_trig_rising_edge().0,
+            count: 0,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Counter<S, D, L, W, R>
+where
+    S: SigT<Item = bool>,
+    D: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    W: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    props: Props<D, L, W, R>,
+    trig: GateToTrigRisingEdge<S>,
+    count: u32,
+    buf: Vec<u32>,
+}
+
+impl<S, D, L, W, R> SigT for Counter<S, D, L, W, R>
+where
+    S: SigT<Item = bool>,
+    D: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    W: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    type Item = u32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let trig = self.trig.sample(ctx);
+        let down = self.props.down.sample(ctx);
+        let length = self.props.length.sample(ctx);
+        let wrap = self.props.wrap.sample(ctx);
+        let reset_trig = self.props.reset_trig.sample(ctx);
+        for (trig, down, length, wrap, reset_trig) in izip! {
+            trig.iter(),
+            down.iter(),
+            length.iter(),
+            wrap.iter(),
+            reset_trig.iter(),
+        } {
+            let max = if length == 0 { u32::MAX } else { length - 1 };
+            if reset_trig {
+                self.count = 0;
+            } else if trig {
+                self.count = match (down, self.count) {
+                    (false, count) if count >= max => {
+                        if wrap {
+                            0
+                        } else {
+                            max
+                        }
+                    }
+                    (false, count) => count + 1,
+                    (true, 0) => {
+                        if wrap {
+                            max
+                        } else {
+                            0
+                        }
+                    }
+                    (true, count) => count - 1,
+                };
+            }
+            // The length may have been reduced since the previous trigger.
+            self.count = self.count.min(max);
+            self.buf.push(self.count);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_vec<T: Clone + Default>(
+        values: Vec<T>,
+    ) -> Sig<impl SigT<Item = T>> {
+        let mut values = values.into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    /// Trigger the counter on every other sample, for `num_trigs` triggers.
+    fn trigs(num_trigs: usize) -> Sig<impl SigT<Item = bool>> {
+        from_vec((0..(num_trigs * 2)).map(|i| i % 2 == 0).collect())
+    }
+
+    /// The count after each trigger.
+    fn counts<S: SigT<Item = u32>>(mut sig: S, num_trigs: usize) -> Vec<u32> {
+        let ctx = SigCtx {
+            sample_rate_hz: 1000.0,
+            batch_index: 0,
+            num_samples: num_trigs * 2,
+        };
+        sig.sample(&ctx).iter().step_by(2).collect()
+    }
+
+    #[test]
+    fn counts_up_and_wraps() {
+        let sig = trigs(7).filter(counter().length(3));
+        assert_eq!(counts(sig, 7), [1, 2, 0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn counts_down_and_wraps() {
+        let sig = trigs(4).filter(counter().length(3).down(true));
+        assert_eq!(counts(sig, 4), [2, 1, 0, 2]);
+    }
+
+    #[test]
+    fn stops_at_ends_without_wrap() {
+        let sig = trigs(4).filter(counter().length(3).wrap(false));
+        assert_eq!(counts(sig, 4), [1, 2, 2, 2]);
+        let sig = trigs(2).filter(counter().length(3).wrap(false).down(true));
+        assert_eq!(counts(sig, 2), [0, 0]);
+    }
+
+    #[test]
+    fn reset_trig_sets_count_to_zero() {
+        let reset_trig = from_vec((0..10).map(|i| i == 6).collect());
+        let sig = trigs(5).filter(counter().reset_trig(reset_trig));
+        assert_eq!(counts(sig, 5), [1, 2, 3, 0, 1]);
+    }
+}
@@ -0,0 +1,192 @@
+//! A delay line which can be read at fractional delays with a choice of interpolation. This is a
+//! building block for delay-based effects rather than a signal itself.
+
+use std::f32::consts::PI;
+
+/// How values between samples are estimated when reading a delay line at a fractional delay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DelayInterpolation {
+    /// Cheapest, but attenuates high frequencies when the delay is modulated
+    Linear,
+    /// Catmull-Rom spline through the 4 nearest samples
+    #[default]
+    CubicHermite,
+    /// 3rd order Lagrange polynomial through the 4 nearest samples
+    Lagrange,
+    /// First order allpass filter. Has a flat frequency response, but the delay should be
+    /// modulated slowly as the filter has internal state.
+    Allpass,
+}
+
+impl DelayInterpolation {
+    /// The range of delays in samples which can be read with this interpolation from a delay line
+    /// with the given capacity.
+    fn delay_range(self, capacity: usize) -> (f32, f32) {
+        let max = capacity as f32;
+        match self {
+            Self::Linear => (0.0, max - 2.0),
+            Self::CubicHermite | Self::Lagrange => (1.0, max - 3.0),
+            Self::Allpass => (0.5, max - 3.0),
+        }
+    }
+}
+
+/// A fixed-size buffer of the most recent samples written to it.
+pub struct DelayLine {
+    buf: Vec<f32>,
+    /// Index of the most recently written sample
+    write_index: usize,
+}
+
+impl DelayLine {
+    /// Create a delay line which can be read at delays of up to `max_delay_samples`.
+    pub fn new(max_delay_samples: usize) -> Self {
+        // Leave room for the extra samples needed by interpolation.
+        Self {
+            buf: vec![0.0; max_delay_samples + 4],
+            write_index: 0,
+        }
+    }
+
+    /// Create a delay line which can be read at delays of up to `max_delay_s` at the given sample
+    /// rate.
+    pub fn new_s(max_delay_s: f32, sample_rate_hz: f32) -> Self {
+        Self::new((max_delay_s.max(0.0) * sample_rate_hz).ceil() as usize)
+    }
+
+    pub fn max_delay_samples(&self) -> usize {
+        self.buf.len() - 4
+    }
+
+    pub fn write(&mut self, sample: f32) {
+        self.write_index = (self.write_index + 1) % self.buf.len();
+        self.buf[self.write_index] = sample;
+    }
+
+    /// The sample written `delay_samples` writes ago, where 0 is the most recently written
+    /// sample. Delays longer than the delay line wrap around.
+    pub fn get(&self, delay_samples: usize) -> f32 {
+        let len = self.buf.len();
+        self.buf[(self.write_index + len - (delay_samples % len)) % len]
+    }
+
+    /// Read the delay line at a fractional delay in samples with linear, cubic Hermite or
+    /// Lagrange interpolation. Allpass interpolation requires state so must be read with a
+    /// `DelayTap`; if it's passed here then cubic Hermite interpolation is used instead. The
+    /// delay is clamped to the range supported by the interpolation method.
+    pub fn read(
+        &self,
+        delay_samples: f32,
+        interpolation: DelayInterpolation,
+    ) -> f32 {
+        let interpolation = match interpolation {
+            DelayInterpolation::Allpass => DelayInterpolation::CubicHermite,
+            other => other,
+        };
+        let (min, max) = interpolation.delay_range(self.buf.len());
+        let delay_samples = delay_samples.clamp(min, max);
+        let i = delay_samples.floor();
+        let f = delay_samples - i;
+        let i = i as usize;
+        match interpolation {
+            DelayInterpolation::Linear => {
+                let x0 = self.get(i);
+                let x1 = self.get(i + 1);
+                x0 + ((x1 - x0) * f)
+            }
+            DelayInterpolation::CubicHermite | DelayInterpolation::Allpass => {
+                let xm1 = self.get(i - 1);
+                let x0 = self.get(i);
+                let x1 = self.get(i + 1);
+                let x2 = self.get(i + 2);
+                let c1 = 0.5 * (x1 - xm1);
+                let c2 = xm1 - (2.5 * x0) + (2.0 * x1) - (0.5 * x2);
+                let c3 = (0.5 * (x2 - xm1)) + (1.5 * (x0 - x1));
+                ((((c3 * f) + c2) * f) + c1) * f + x0
+            }
+            DelayInterpolation::Lagrange => {
+                let xm1 = self.get(i - 1);
+                let x0 = self.get(i);
+                let x1 = self.get(i + 1);
+                let x2 = self.get(i + 2);
+                let fp1 = f + 1.0;
+                let fm1 = f - 1.0;
+                let fm2 = f - 2.0;
+                (-f * fm1 * fm2 / 6.0) * xm1
+                    + (fp1 * fm1 * fm2 / 2.0) * x0
+                    + (-fp1 * f * fm2 / 2.0) * x1
+                    + (fp1 * f * fm1 / 6.0) * x2
+            }
+        }
+    }
+}
+
+/// Reads a delay line at a fractional delay, keeping any state needed by the interpolation
+/// method. Each independent read position of a delay line should have its own tap, and the tap
+/// should be read once per sample written.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DelayTap {
+    pub interpolation: DelayInterpolation,
+    allpass_prev_output: f32,
+}
+
+impl DelayTap {
+    pub fn new(interpolation: DelayInterpolation) -> Self {
+        Self {
+            interpolation,
+            allpass_prev_output: 0.0,
+        }
+    }
+
+    pub fn read(&mut self, delay_line: &DelayLine, delay_samples: f32) -> f32 {
+        self.read_allpass_with(delay_line, delay_samples, |f| {
+            (1.0 - f) / (1.0 + f)
+        })
+    }
+
+    /// Like `read`, except that with allpass interpolation the filter coefficient is chosen so
+    /// the delay is exact at `omega` radians per sample rather than only at low frequencies. This
+    /// keeps feedback loops tuned to `omega` in tune even when the loop is only a few samples
+    /// long.
+    pub fn read_tuned(
+        &mut self,
+        delay_line: &DelayLine,
+        delay_samples: f32,
+        omega: f32,
+    ) -> f32 {
+        self.read_allpass_with(delay_line, delay_samples, |f| {
+            let omega = omega.clamp(1e-4, PI - 1e-4);
+            (((1.0 - f) * omega) / 2.0).sin()
+                / (((1.0 + f) * omega) / 2.0).sin()
+        })
+    }
+
+    /// Reads the delay line, computing the allpass filter coefficient from the fractional part of
+    /// the delay with `eta`.
+    fn read_allpass_with(
+        &mut self,
+        delay_line: &DelayLine,
+        delay_samples: f32,
+        eta: impl FnOnce(f32) -> f32,
+    ) -> f32 {
+        if self.interpolation != DelayInterpolation::Allpass {
+            return delay_line.read(delay_samples, self.interpolation);
+        }
+        let (min, max) = self.interpolation.delay_range(delay_line.buf.len());
+        let delay_samples = delay_samples.clamp(min, max);
+        let mut i = delay_samples.floor();
+        let mut f = delay_samples - i;
+        // Keep the fractional part in [0.5, 1.5) where the filter coefficient is well away from
+        // -1, as the filter rings badly close to it.
+        if f < 0.5 && i >= 1.0 {
+            i -= 1.0;
+            f += 1.0;
+        }
+        let eta = eta(f);
+        let i = i as usize;
+        let output = (eta * delay_line.get(i)) + delay_line.get(i + 1)
+            - (eta * self.allpass_prev_output);
+        self.allpass_prev_output = output;
+        output
+    }
+}
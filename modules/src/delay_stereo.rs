@@ -0,0 +1,291 @@
+use crate::low_level::{
+    biquad_filter::{BiquadFilterHighPass, BiquadFilterLowPass},
+    linearly_interpolating_ring_buffer::LinearlyInterpolatingRingBuffer,
+};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Sig, SigCtx, SigShared, SigT, Stereo, StereoPair};
+use itertools::izip;
+
+builder! {
+    #[constructor = "delay_stereo"]
+    #[constructor_doc = "Stereo delay with independent channel times, ping-pong and filtered feedback"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "TL"]
+        #[default = 0.25]
+        time_left_s: f32,
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "TR"]
+        #[default = 0.25]
+        time_right_s: f32,
+        // ratio of the delayed signal fed back into the delay
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 0.4]
+        feedback_ratio: f32,
+        // 0 is dry signal, 1 is all delay
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "M"]
+        #[default = 0.5]
+        mix_01: f32,
+        // Cutoff of a low-pass filter applied to the feedback, so each repeat is darker than the
+        // last.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "LP"]
+        #[default = 20_000.0]
+        feedback_low_pass_hz: f32,
+        // Cutoff of a high-pass filter applied to the feedback, so each repeat is thinner than
+        // the last.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "HP"]
+        #[default = 20.0]
+        feedback_high_pass_hz: f32,
+        // How hard the feedback is driven into a tanh saturator. At 0 there is no saturation.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 0.0]
+        feedback_saturation: f32,
+        // When true, the input is mixed to mono and fed into the left channel, and the feedback
+        // from each channel goes into the other channel, so repeats bounce between the channels.
+        #[default = false]
+        ping_pong: bool,
+        // The longest delay time. Delay times are clamped to this value. Memory for the delay is
+        // allocated up front based on this value.
+        #[default = 4.0]
+        max_delay_s: f32,
+    }
+}
+
+impl<TL, TR, F, M, LP, HP, D> Props<TL, TR, F, M, LP, HP, D>
+where
+    TL: SigT<Item = f32>,
+    TR: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+    LP: SigT<Item = f32>,
+    HP: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+{
+    /// Set the delay times to a number of beats at a given tempo. E.g. 0.75 beats is a dotted
+    /// eighth note in 4/4 time.
+    pub fn sync_to_bpm<B>(
+        self,
+        bpm: B,
+        beats_left: f32,
+        beats_right: f32,
+    ) -> Props<
+        Sig<impl SigT<Item = f32>>,
+        Sig<impl SigT<Item = f32>>,
+        F,
+        M,
+        LP,
+        HP,
+        D,
+    >
+    where
+        B: SigT<Item = f32>,
+    {
+        let period_s: Sig<SigShared<_>> = Sig(bpm).bpm_to_period_s().shared();
+        self.time_left_s(period_s.clone() * beats_left)
+            .time_right_s(period_s * beats_right)
+    }
+
+    /// Apply the delay to a stereo signal.
+    pub fn stereo<SL, SR>(
+        self,
+        stereo: Stereo<SL, SR>,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>>
+    where
+        SL: SigT<Item = f32>,
+        SR: SigT<Item = f32>,
+    {
+        let sig = Sig(stereo.left)
+            .zip(stereo.right)
+            .map(|(left, right)| Stereo::new(left, right));
+        let delay: Sig<SigShared<_>> = Sig(DelayStereo {
+            props: self,
+            sig,
+            state: None,
+            buf: Vec::new(),
+        })
+        .shared();
+        Stereo::new(
+            delay.clone().map(|s: StereoPair<f32>| s.left),
+            delay.map(|s: StereoPair<f32>| s.right),
+        )
+    }
+
+    /// Apply the delay to a mono signal, producing a stereo signal.
+    pub fn mono<S>(
+        self,
+        sig: S,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>>
+    where
+        S: SigT<Item = f32>,
+    {
+        let sig: Sig<SigShared<_>> = Sig(sig).shared();
+        self.stereo(Stereo::new(sig.clone(), sig))
+    }
+}
+
+struct DelayChannel {
+    ring: LinearlyInterpolatingRingBuffer,
+    low_pass: BiquadFilterLowPass,
+    high_pass: BiquadFilterHighPass,
+}
+
+impl DelayChannel {
+    fn new(size: usize) -> Self {
+        Self {
+            ring: LinearlyInterpolatingRingBuffer::new(size),
+            low_pass: BiquadFilterLowPass::new(),
+            high_pass: BiquadFilterHighPass::new(),
+        }
+    }
+
+    fn filter_feedback(
+        &mut self,
+        sample: f32,
+        low_pass_hz: f32,
+        high_pass_hz: f32,
+        saturation: f32,
+        sample_rate_hz: f32,
+    ) -> f32 {
+        let sample = self.high_pass.process(
+            sample as f64,
+            high_pass_hz as f64,
+            0.0,
+            sample_rate_hz as f64,
+        );
+        let sample = self.low_pass.process(
+            sample,
+            low_pass_hz as f64,
+            0.0,
+            sample_rate_hz as f64,
+        ) as f32;
+        if saturation > 0.0 {
+            (sample * saturation).tanh() / saturation.tanh()
+        } else {
+            sample
+        }
+    }
+}
+
+struct State {
+    channels: StereoPair<DelayChannel>,
+    max_index: f32,
+}
+
+pub struct DelayStereo<S, TL, TR, F, M, LP, HP, D>
+where
+    S: SigT<Item = StereoPair<f32>>,
+    TL: SigT<Item = f32>,
+    TR: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+    LP: SigT<Item = f32>,
+    HP: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+{
+    props: Props<TL, TR, F, M, LP, HP, D>,
+    sig: S,
+    // Created on the first frame as the size of the delay depends on the sample rate.
+    state: Option<State>,
+    buf: Vec<StereoPair<f32>>,
+}
+
+impl<S, TL, TR, F, M, LP, HP, D> SigT
+    for DelayStereo<S, TL, TR, F, M, LP, HP, D>
+where
+    S: SigT<Item = StereoPair<f32>>,
+    TL: SigT<Item = f32>,
+    TR: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+    LP: SigT<Item = f32>,
+    HP: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+{
+    type Item = StereoPair<f32>;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let state = self.state.get_or_insert_with(|| {
+            let max_index =
+                (self.props.max_delay_s.max(0.0) * ctx.sample_rate_hz).ceil();
+            // Leave room for interpolating past the maximum index.
+            let size = max_index as usize + 2;
+            State {
+                channels: Stereo::new_fn(|| DelayChannel::new(size)),
+                max_index,
+            }
+        });
+        let sig = self.sig.sample(ctx);
+        let time_left_s = self.props.time_left_s.sample(ctx);
+        let time_right_s = self.props.time_right_s.sample(ctx);
+        let feedback_ratio = self.props.feedback_ratio.sample(ctx);
+        let mix_01 = self.props.mix_01.sample(ctx);
+        let low_pass_hz = self.props.feedback_low_pass_hz.sample(ctx);
+        let high_pass_hz = self.props.feedback_high_pass_hz.sample(ctx);
+        let saturation = self.props.feedback_saturation.sample(ctx);
+        for (
+            sample,
+            time_left_s,
+            time_right_s,
+            feedback_ratio,
+            mix_01,
+            low_pass_hz,
+            high_pass_hz,
+            saturation,
+        ) in izip! {
+            sig.iter(),
+            time_left_s.iter(),
+            time_right_s.iter(),
+            feedback_ratio.iter(),
+            mix_01.iter(),
+            low_pass_hz.iter(),
+            high_pass_hz.iter(),
+            saturation.iter(),
+        } {
+            let index_of = |time_s: f32| {
+                (time_s * ctx.sample_rate_hz).clamp(0.0, state.max_index)
+            };
+            let left = &mut state.channels.left;
+            let right = &mut state.channels.right;
+            let delayed = Stereo::new(
+                left.ring.query(index_of(time_left_s)).unwrap_or(0.0),
+                right.ring.query(index_of(time_right_s)).unwrap_or(0.0),
+            );
+            let feedback = Stereo::new(
+                left.filter_feedback(
+                    delayed.left,
+                    low_pass_hz,
+                    high_pass_hz,
+                    saturation,
+                    ctx.sample_rate_hz,
+                ),
+                right.filter_feedback(
+                    delayed.right,
+                    low_pass_hz,
+                    high_pass_hz,
+                    saturation,
+                    ctx.sample_rate_hz,
+                ),
+            ) * feedback_ratio;
+            if self.props.ping_pong {
+                let mono = (sample.left + sample.right) / 2.0;
+                left.ring.insert(mono + feedback.right);
+                right.ring.insert(feedback.left);
+            } else {
+                left.ring.insert(sample.left + feedback.left);
+                right.ring.insert(sample.right + feedback.right);
+            }
+            self.buf.push(Stereo::new(
+                (sample.left * (1.0 - mix_01)) + (delayed.left * mix_01),
+                (sample.right * (1.0 - mix_01)) + (delayed.right * mix_01),
+            ));
+        }
+        &self.buf
+    }
+}
@@ -0,0 +1,178 @@
+use crate::down_sample::DownsampleState;
+use crate::low_level::half_band::Oversampler;
+pub use crate::low_level::half_band::Oversampling;
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+use std::sync::Arc;
+
+/// The function that maps the driven input of a distortion to its output.
+#[derive(Clone)]
+pub enum DistortionCurve {
+    /// Smooth symmetric saturation
+    Tanh,
+    /// Clip the signal to between -1 and 1
+    HardClip,
+    /// Asymmetric saturation which clips the negative half of the signal more gently than the
+    /// positive half, adding even harmonics like an overdriven valve
+    Tube,
+    /// Reflect the parts of the signal beyond -1 and 1 back into that range, repeatedly
+    Foldback,
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>),
+}
+
+impl DistortionCurve {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(f32) -> f32 + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(f))
+    }
+
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            Self::Tanh => x.tanh(),
+            Self::HardClip => x.clamp(-1.0, 1.0),
+            Self::Tube => {
+                if x >= 0.0 {
+                    x.tanh()
+                } else {
+                    const NEGATIVE_LIMIT: f32 = 1.5;
+                    NEGATIVE_LIMIT * (x / NEGATIVE_LIMIT).tanh()
+                }
+            }
+            Self::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+            Self::Custom(f) => f(x),
+        }
+    }
+}
+
+builder! {
+    #[constructor = "distortion"]
+    #[constructor_doc = "Waveshaping distortion with oversampling to reduce aliasing, and optional bit crushing"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[default = DistortionCurve::Tanh]
+        curve: DistortionCurve,
+        // The input is multiplied by this before the curve is applied
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 1.0]
+        drive: f32,
+        // Added to the driven input before the curve is applied, making the distortion
+        // asymmetric. The resulting DC offset is removed from the output.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "B"]
+        #[default = 0.0]
+        bias: f32,
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "G"]
+        #[default = 1.0]
+        output_gain: f32,
+        // Number of bits used to represent the output between -1 and 1. At 0 there is no bit
+        // depth reduction.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "BD"]
+        #[default = 0.0]
+        bit_depth: f32,
+        // Artificially reduce the sample rate of the output by this factor. See `down_sample`.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DS"]
+        #[default = 1.0]
+        down_sample: f32,
+        // The curve is applied at a multiple of the sample rate to reduce aliasing. Bit depth
+        // reduction and down sampling are applied afterwards at the original sample rate, as
+        // their aliasing is part of their character.
+        #[default = Oversampling::X4]
+        oversampling: Oversampling,
+    }
+}
+
+impl<D, B, G, BD, DS> Filter for Props<D, B, G, BD, DS>
+where
+    D: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    G: SigT<Item = f32>,
+    BD: SigT<Item = f32>,
+    DS: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Distortion<S, D, B, G, BD, DS>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Distortion {
+            oversampler: Oversampler::new(self.oversampling),
+            props: self,
+            sig,
+            down_sample_state: DownsampleState::default(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Distortion<S, D, B, G, BD, DS>
+where
+    S: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    G: SigT<Item = f32>,
+    BD: SigT<Item = f32>,
+    DS: SigT<Item = f32>,
+{
+    props: Props<D, B, G, BD, DS>,
+    sig: S,
+    oversampler: Oversampler,
+    down_sample_state: DownsampleState,
+    buf: Vec<f32>,
+}
+
+impl<S, D, B, G, BD, DS> SigT for Distortion<S, D, B, G, BD, DS>
+where
+    S: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    G: SigT<Item = f32>,
+    BD: SigT<Item = f32>,
+    DS: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let sig = self.sig.sample(ctx);
+        let drive = self.props.drive.sample(ctx);
+        let bias = self.props.bias.sample(ctx);
+        let output_gain = self.props.output_gain.sample(ctx);
+        let bit_depth = self.props.bit_depth.sample(ctx);
+        let down_sample = self.props.down_sample.sample(ctx);
+        let curve = &self.props.curve;
+        for (out, sample, drive, bias, output_gain, bit_depth, down_sample) in izip! {
+            self.buf.iter_mut(),
+            sig.iter(),
+            drive.iter(),
+            bias.iter(),
+            output_gain.iter(),
+            bit_depth.iter(),
+            down_sample.iter(),
+        } {
+            let dc_offset = curve.apply(bias);
+            let shaped = self.oversampler.process(sample, |x| {
+                curve.apply((x * drive) + bias) - dc_offset
+            });
+            let mut sample = shaped * output_gain;
+            if bit_depth > 0.0 {
+                let num_levels = 2_f32.powf(bit_depth - 1.0);
+                sample = (sample * num_levels).round() / num_levels;
+            }
+            *out = self.down_sample_state.process(sample, down_sample);
+        }
+        &self.buf
+    }
+}
@@ -33,13 +33,41 @@ where
             props: self,
             sig,
             buf: Vec::new(),
-            prev_input_sample: 0.0,
-            prev_output_sample: 0.0,
-            remaining_samples: 0.0,
+            state: DownsampleState::default(),
         }
     }
 }
 
+/// The state of a down sampler, for use by other modules which reduce the sample rate of a signal
+/// as part of their processing.
+#[derive(Default)]
+pub(crate) struct DownsampleState {
+    prev_input_sample: f32,
+    prev_output_sample: f32,
+    remaining_samples: f32,
+}
+
+impl DownsampleState {
+    pub(crate) fn process(&mut self, sample: f32, scale: f32) -> f32 {
+        let scale = scale.max(1.0);
+        let out;
+        if self.remaining_samples < 1.0 {
+            self.remaining_samples = self.remaining_samples.max(0.0);
+            self.prev_output_sample = (self.prev_input_sample
+                * self.remaining_samples)
+                + (sample * (1.0 - self.remaining_samples));
+            // linearly interpolate between the current and previous samples
+            out = self.prev_output_sample;
+            self.remaining_samples = scale;
+        } else {
+            out = self.prev_output_sample;
+            self.remaining_samples -= 1.0;
+        }
+        self.prev_input_sample = sample;
+        out
+    }
+}
+
 pub struct Downsample<C, S>
 where
     C: SigT<Item = f32>,
@@ -48,9 +76,7 @@ where
     props: Props<C>,
     sig: S,
     buf: Vec<f32>,
-    prev_input_sample: f32,
-    prev_output_sample: f32,
-    remaining_samples: f32,
+    state: DownsampleState,
 }
 
 impl<C, S> SigT for Downsample<C, S>
@@ -69,20 +95,7 @@ where
             sig.iter(),
             scale.iter(),
         } {
-            let scale = scale.max(1.0);
-            if self.remaining_samples < 1.0 {
-                self.remaining_samples = self.remaining_samples.max(0.0);
-                self.prev_output_sample = (self.prev_input_sample
-                    * self.remaining_samples)
-                    + (sample * (1.0 - self.remaining_samples));
-                // linearly interpolate between the current and previous samples
-                *out = self.prev_output_sample;
-                self.remaining_samples = scale;
-            } else {
-                *out = self.prev_output_sample;
-                self.remaining_samples -= 1.0;
-            }
-            self.prev_input_sample = sample;
+            *out = self.state.process(sample, scale);
         }
         &self.buf
     }
@@ -0,0 +1,231 @@
+pub use crate::low_level::biquad_filter::EqBandType;
+use crate::low_level::biquad_filter::{
+    BiquadFilterEq, EqBandParams, eq_band_gain_db,
+};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, Sig, SigBoxed, SigCtx, SigT};
+use std::sync::{Arc, RwLock};
+
+/// The most recent parameters of a band, and the sample rate they were processed at.
+type EqBandLatest = Arc<RwLock<Option<(EqBandParams, f32)>>>;
+
+/// A single band of an equalizer.
+pub struct EqBand {
+    band_type: EqBandType,
+    freq_hz: Sig<SigBoxed<f32>>,
+    gain_db: Sig<SigBoxed<f32>>,
+    q: Sig<SigBoxed<f32>>,
+    filter: BiquadFilterEq,
+    latest: EqBandLatest,
+    freq_hz_buf: Vec<f32>,
+    gain_db_buf: Vec<f32>,
+    q_buf: Vec<f32>,
+}
+
+builder! {
+    #[constructor = "equalizer"]
+    #[constructor_doc = "Parametric equalizer with any number of shelf, peak, notch and cut bands"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // Gain applied after all the bands
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "G"]
+        #[default = 0.0]
+        output_gain_db: f32,
+        #[default = Vec::new()]
+        bands: Vec<EqBand>,
+    }
+}
+
+impl<G> Props<G>
+where
+    G: SigT<Item = f32>,
+{
+    /// Add a band to the equalizer. Bands are applied in the order they are added. The gain is
+    /// ignored by notch and cut bands. Q controls the width of peak and notch bands, the slope of
+    /// shelves, and the resonance of cuts, where 0.707 gives a flat response with no resonance.
+    pub fn band<F, D, Q>(
+        mut self,
+        band_type: EqBandType,
+        freq_hz: F,
+        gain_db: D,
+        q: Q,
+    ) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        D: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.bands.push(EqBand {
+            band_type,
+            freq_hz: Sig(freq_hz).boxed(),
+            gain_db: Sig(gain_db).boxed(),
+            q: Sig(q).boxed(),
+            filter: BiquadFilterEq::new(),
+            latest: Arc::new(RwLock::new(None)),
+            freq_hz_buf: Vec::new(),
+            gain_db_buf: Vec::new(),
+            q_buf: Vec::new(),
+        });
+        self
+    }
+
+    pub fn low_shelf<F, D, Q>(self, freq_hz: F, gain_db: D, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        D: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::LowShelf, freq_hz, gain_db, q)
+    }
+
+    pub fn high_shelf<F, D, Q>(self, freq_hz: F, gain_db: D, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        D: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::HighShelf, freq_hz, gain_db, q)
+    }
+
+    pub fn peak<F, D, Q>(self, freq_hz: F, gain_db: D, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        D: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::Peak, freq_hz, gain_db, q)
+    }
+
+    pub fn notch<F, Q>(self, freq_hz: F, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::Notch, freq_hz, 0.0, q)
+    }
+
+    pub fn low_cut<F, Q>(self, freq_hz: F, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::LowCut, freq_hz, 0.0, q)
+    }
+
+    pub fn high_cut<F, Q>(self, freq_hz: F, q: Q) -> Self
+    where
+        F: SigT<Item = f32> + Send + Sync + 'static,
+        Q: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.band(EqBandType::HighCut, freq_hz, 0.0, q)
+    }
+
+    /// Returns a handle for querying the frequency response of the equalizer, e.g. to draw its
+    /// curve in a UI. The handle reflects the parameters of each band as of the most recently
+    /// processed frame, and only includes bands added before this method is called.
+    pub fn response(&self) -> EqResponse {
+        EqResponse {
+            bands: self.bands.iter().map(|band| band.latest.clone()).collect(),
+        }
+    }
+}
+
+/// Queries the frequency response of an equalizer while it is processing a signal.
+#[derive(Clone)]
+pub struct EqResponse {
+    bands: Vec<EqBandLatest>,
+}
+
+impl EqResponse {
+    /// The gain in dB applied by all the bands of the equalizer to a sine wave at `freq_hz`,
+    /// excluding the output gain. Bands which haven't processed a frame yet are ignored.
+    pub fn frequency_response(&self, freq_hz: f32) -> f32 {
+        self.bands
+            .iter()
+            .filter_map(|band| *band.read().unwrap())
+            .map(|(params, sample_rate_hz)| {
+                eq_band_gain_db(params, freq_hz as f64, sample_rate_hz as f64)
+            })
+            .sum::<f64>() as f32
+    }
+}
+
+impl<G> Filter for Props<G>
+where
+    G: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Equalizer<S, G>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Equalizer {
+            props: self,
+            sig,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Equalizer<S, G>
+where
+    S: SigT<Item = f32>,
+    G: SigT<Item = f32>,
+{
+    props: Props<G>,
+    sig: S,
+    buf: Vec<f32>,
+}
+
+impl<S, G> SigT for Equalizer<S, G>
+where
+    S: SigT<Item = f32>,
+    G: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.sig.sample(ctx).clone_to_vec(&mut self.buf);
+        for band in &mut self.props.bands {
+            band.freq_hz.sample(ctx).clone_to_vec(&mut band.freq_hz_buf);
+            band.gain_db.sample(ctx).clone_to_vec(&mut band.gain_db_buf);
+            band.q.sample(ctx).clone_to_vec(&mut band.q_buf);
+            let mut params = None;
+            for (i, sample) in self.buf.iter_mut().enumerate() {
+                let band_params = EqBandParams {
+                    band_type: band.band_type,
+                    freq_hz: band.freq_hz_buf[i] as f64,
+                    gain_db: band.gain_db_buf[i] as f64,
+                    q: band.q_buf[i] as f64,
+                };
+                *sample = band.filter.process(
+                    *sample as f64,
+                    band_params,
+                    ctx.sample_rate_hz as f64,
+                ) as f32;
+                params = Some(band_params);
+            }
+            if let Some(params) = params {
+                // Don't block the audio thread while the response is being read. If the lock is
+                // held, the update is skipped and the next batch will try again.
+                if let Ok(mut latest) = band.latest.try_write() {
+                    *latest = Some((params, ctx.sample_rate_hz));
+                }
+            }
+        }
+        let output_gain_db = self.props.output_gain_db.sample(ctx);
+        for (sample, output_gain_db) in
+            self.buf.iter_mut().zip(output_gain_db.iter())
+        {
+            *sample *= 10_f32.powf(output_gain_db / 20.0);
+        }
+        &self.buf
+    }
+}
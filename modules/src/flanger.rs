@@ -0,0 +1,214 @@
+use crate::{
+    ChorusLfoOffset, Sine, Waveform,
+    delay_line::{DelayInterpolation, DelayLine, DelayTap},
+    oscillator::{Oscillator, oscillator},
+};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+
+/// Whether the delayed signal is added to or subtracted from the input of the delay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlangerFeedbackPolarity {
+    /// Emphasizes harmonics of the delay frequency for a ringing, metallic sound
+    #[default]
+    Positive,
+    /// Emphasizes odd harmonics of half the delay frequency for a hollower sound
+    Negative,
+}
+
+builder! {
+    #[constructor = "flanger"]
+    #[constructor_doc = "Mix the signal with a copy of itself with a short modulated delay"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "R"]
+        #[default = 0.2]
+        lfo_rate_hz: f32,
+        #[generic_with_constraint = "Waveform"]
+        #[generic_name = "W"]
+        #[default = Sine]
+        lfo_waveform: Sine,
+        #[default = ChorusLfoOffset::None]
+        lfo_offset: ChorusLfoOffset,
+        // The LFO phase offset of the right channel when `lfo_offset` interleaves the right
+        // channel.
+        #[default = 0.25]
+        lfo_stereo_offset_01: f32,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "T"]
+        #[default = false]
+        lfo_reset_trig: bool,
+        // The delay at the center of the sweep
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DL"]
+        #[default = 0.003]
+        delay_s: f32,
+        // How far the delay sweeps either side of its center. In through-zero mode this should
+        // be at most `delay_s`.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DP"]
+        #[default = 0.002]
+        depth_s: f32,
+        // ratio of the delayed signal fed back into the delay
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 0.5]
+        feedback_ratio: f32,
+        #[default = FlangerFeedbackPolarity::Positive]
+        feedback_polarity: FlangerFeedbackPolarity,
+        // 0 is dry signal, 1 is all delay. The comb filtering is strongest at 0.5.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "M"]
+        #[default = 0.5]
+        mix_01: f32,
+        // Delay the dry signal by `delay_s` so the modulated delay sweeps through it. This lets
+        // the relative delay between the two signals pass through zero, giving the deep
+        // cancellation of tape flanging.
+        #[default = false]
+        through_zero: bool,
+    }
+}
+
+/// Upper limit on the total delay so the delay line can be allocated up front.
+const MAX_DELAY_S: f32 = 0.1;
+
+type Osc<W, R, T> = Oscillator<W, R, f32, f32, T>;
+
+pub struct Flanger<S, R, W, T, DL, DP, F, M>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    DL: SigT<Item = f32>,
+    DP: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    sig: S,
+    lfo: Osc<W, R, T>,
+    delay_s: DL,
+    depth_s: DP,
+    feedback_ratio: F,
+    feedback_polarity: FlangerFeedbackPolarity,
+    mix_01: M,
+    through_zero: bool,
+    // Created on the first frame as the size of the delay line depends on the sample rate.
+    delay_line: Option<DelayLine>,
+    // Holds the input without feedback so the delayed dry signal in through-zero mode is
+    // unaffected by the flanging. Only created in through-zero mode.
+    dry_delay_line: Option<DelayLine>,
+    modulated_tap: DelayTap,
+    dry_tap: DelayTap,
+    buf: Vec<f32>,
+}
+
+impl<R, W, T, DL, DP, F, M> Filter for Props<R, W, T, DL, DP, F, M>
+where
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    DL: SigT<Item = f32>,
+    DP: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Flanger<S, R, W, T, DL, DP, F, M>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let lfo = oscillator(self.lfo_waveform, self.lfo_rate_hz)
+            .reset_offset_01(
+                self.lfo_offset.offset_01(self.lfo_stereo_offset_01),
+            )
+            .reset_trig(self.lfo_reset_trig)
+            .build()
+            .0;
+        Flanger {
+            sig,
+            lfo,
+            delay_s: self.delay_s,
+            depth_s: self.depth_s,
+            feedback_ratio: self.feedback_ratio,
+            feedback_polarity: self.feedback_polarity,
+            mix_01: self.mix_01,
+            through_zero: self.through_zero,
+            delay_line: None,
+            dry_delay_line: None,
+            modulated_tap: DelayTap::new(DelayInterpolation::CubicHermite),
+            dry_tap: DelayTap::new(DelayInterpolation::CubicHermite),
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<S, R, W, T, DL, DP, F, M> SigT for Flanger<S, R, W, T, DL, DP, F, M>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    DL: SigT<Item = f32>,
+    DP: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let delay_line = self.delay_line.get_or_insert_with(|| {
+            DelayLine::new_s(MAX_DELAY_S, ctx.sample_rate_hz)
+        });
+        let mut dry_delay_line = self.through_zero.then(|| {
+            self.dry_delay_line.get_or_insert_with(|| {
+                DelayLine::new_s(MAX_DELAY_S, ctx.sample_rate_hz)
+            })
+        });
+        let feedback_sign = match self.feedback_polarity {
+            FlangerFeedbackPolarity::Positive => 1.0,
+            FlangerFeedbackPolarity::Negative => -1.0,
+        };
+        let sig = self.sig.sample(ctx);
+        let lfo = self.lfo.sample(ctx);
+        let delay_s = self.delay_s.sample(ctx);
+        let depth_s = self.depth_s.sample(ctx);
+        let feedback_ratio = self.feedback_ratio.sample(ctx);
+        let mix_01 = self.mix_01.sample(ctx);
+        for (sample, lfo, delay_s, depth_s, feedback_ratio, mix_01) in izip! {
+            sig.iter(),
+            lfo.iter(),
+            delay_s.iter(),
+            depth_s.iter(),
+            feedback_ratio.iter(),
+            mix_01.iter(),
+        } {
+            // The delay line is read before the current sample is written, so the most recent
+            // sample in the delay line is already one sample old.
+            let to_samples = |s: f32| (s * ctx.sample_rate_hz) - 1.0;
+            let wet = self
+                .modulated_tap
+                .read(delay_line, to_samples(delay_s + (lfo * depth_s)));
+            let dry = if let Some(dry_delay_line) = dry_delay_line.as_mut() {
+                let dry =
+                    self.dry_tap.read(dry_delay_line, to_samples(delay_s));
+                dry_delay_line.write(sample);
+                dry
+            } else {
+                sample
+            };
+            delay_line.write(sample + (wet * feedback_ratio * feedback_sign));
+            self.buf.push((dry * (1.0 - mix_01)) + (wet * mix_01));
+        }
+        &self.buf
+    }
+}
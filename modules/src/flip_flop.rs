@@ -0,0 +1,217 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, GateToTrigRisingEdge, Sig, SigCtx, SigT};
+use itertools::izip;
+
+builder! {
+    #[constructor = "flip_flop_toggle"]
+    #[constructor_doc = "A gate which toggles between on and off each time its input is triggered"]
+    #[generic_setter_type_name = "X"]
+    pub struct PropsToggle {
+        // Turn the output off
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        #[default = false]
+        reset_trig: bool,
+    }
+}
+
+impl<R> Filter for PropsToggle<R>
+where
+    R: SigT<Item = bool>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = FlipFlopToggle<S, R>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        FlipFlopToggle {
+            props: self,
+            trig: Sig(sig).gate_to_trig_rising_edge().0,
+            state: false,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct FlipFlopToggle<S, R>
+where
+    S: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    props: PropsToggle<R>,
+    trig: GateToTrigRisingEdge<S>,
+    state: bool,
+    buf: Vec<bool>,
+}
+
+impl<S, R> SigT for FlipFlopToggle<S, R>
+where
+    S: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let trig = self.trig.sample(ctx);
+        let reset_trig = self.props.reset_trig.sample(ctx);
+        for (trig, reset_trig) in izip! {
+            trig.iter(),
+            reset_trig.iter(),
+        } {
+            if reset_trig {
+                self.state = false;
+            } else if trig {
+                self.state = !self.state;
+            }
+            self.buf.push(self.state);
+        }
+        &self.buf
+    }
+}
+
+builder! {
+    #[constructor = "flip_flop_d"]
+    #[constructor_doc = "Latches the value of its input on the rising edge of a clock"]
+    #[generic_setter_type_name = "X"]
+    pub struct PropsD {
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "C"]
+        clock: _,
+        // Turn the output off
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        #[default = false]
+        reset_trig: bool,
+    }
+}
+
+impl<C, R> Filter for PropsD<C, R>
+where
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = FlipFlopD<S, C, R>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        FlipFlopD {
+            clock: Sig(self.clock).gate_to_trig_rising_edge().0,
+            reset_trig: self.reset_trig,
+            sig,
+            state: false,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct FlipFlopD<S, C, R>
+where
+    S: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    clock: GateToTrigRisingEdge<C>,
+    reset_trig: R,
+    sig: S,
+    state: bool,
+    buf: Vec<bool>,
+}
+
+impl<S, C, R> SigT for FlipFlopD<S, C, R>
+where
+    S: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let sig = self.sig.sample(ctx);
+        let clock = self.clock.sample(ctx);
+        let reset_trig = self.reset_trig.sample(ctx);
+        for (sample, clock, reset_trig) in izip! {
+            sig.iter(),
+            clock.iter(),
+            reset_trig.iter(),
+        } {
+            if reset_trig {
+                self.state = false;
+            } else if clock {
+                self.state = sample;
+            }
+            self.buf.push(self.state);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_vec<T: Clone + Default>(
+        values: Vec<T>,
+    ) -> Sig<impl SigT<Item = T>> {
+        let mut values = values.into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    fn from_str(s: &str) -> Sig<impl SigT<Item = bool>> {
+        from_vec(s.chars().map(|c| c == 'x').collect())
+    }
+
+    fn to_str<S: SigT<Item = bool>>(mut sig: S, num_samples: usize) -> String {
+        let ctx = SigCtx {
+            sample_rate_hz: 1000.0,
+            batch_index: 0,
+            num_samples,
+        };
+        sig.sample(&ctx)
+            .iter()
+            .map(|b| if b { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn toggle_on_each_rising_edge() {
+        let sig = from_str("x.xxx..x..").filter(flip_flop_toggle());
+        assert_eq!(to_str(sig, 10), "xx.....xxx");
+    }
+
+    #[test]
+    fn toggle_reset_turns_output_off() {
+        let sig = from_str("x.........")
+            .filter(flip_flop_toggle().reset_trig(from_str("....x.....")));
+        assert_eq!(to_str(sig, 10), "xxxx......");
+    }
+
+    #[test]
+    fn d_latches_input_on_clock() {
+        let clock = from_str("x..x..x..x");
+        let sig = from_str(".xxx.....x").filter(flip_flop_d(clock));
+        assert_eq!(to_str(sig, 10), "...xxx...x");
+    }
+
+    #[test]
+    fn d_reset_turns_output_off() {
+        let clock = from_str("x.........");
+        let sig = from_str("xxxxxxxxxx")
+            .filter(flip_flop_d(clock).reset_trig(from_str(".....x....")));
+        assert_eq!(to_str(sig, 10), "xxxxx.....");
+    }
+}
@@ -0,0 +1,369 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, ConstBuf, Sig, SigCtx, SigT, Stereo, StereoPair};
+use itertools::izip;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::f32::consts::PI;
+
+/// The envelope applied to the amplitude of each grain over its lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GrainWindow {
+    #[default]
+    Hann,
+    Triangle,
+    Gaussian,
+    /// Flat in the middle with short raised-cosine fades at either end
+    Tukey,
+    Rectangle,
+}
+
+impl GrainWindow {
+    fn amplitude(self, t_01: f32) -> f32 {
+        match self {
+            Self::Hann => 0.5 - (0.5 * (2.0 * PI * t_01).cos()),
+            Self::Triangle => 1.0 - ((2.0 * t_01) - 1.0).abs(),
+            Self::Gaussian => {
+                const SIGMA: f32 = 0.15;
+                let x = (t_01 - 0.5) / SIGMA;
+                (-0.5 * x * x).exp()
+            }
+            Self::Tukey => {
+                const FADE_01: f32 = 0.1;
+                let edge_01 = t_01.min(1.0 - t_01);
+                if edge_01 >= FADE_01 {
+                    1.0
+                } else {
+                    0.5 - (0.5 * (PI * edge_01 / FADE_01).cos())
+                }
+            }
+            Self::Rectangle => 1.0,
+        }
+    }
+}
+
+impl SigT for GrainWindow {
+    type Item = Self;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        ConstBuf {
+            value: *self,
+            count: ctx.num_samples,
+        }
+    }
+}
+
+struct Grain {
+    /// Index into the sample buffer of the next sample of this grain
+    index: f64,
+    /// How far the index advances each sample
+    rate: f64,
+    age_samples: u32,
+    length_samples: u32,
+    window: GrainWindow,
+    gain: StereoPair<f32>,
+}
+
+impl Grain {
+    fn is_finished(&self) -> bool {
+        self.age_samples >= self.length_samples
+    }
+
+    fn next_sample(&mut self, sample_buffer: &[f32]) -> StereoPair<f32> {
+        let len = sample_buffer.len();
+        let index = self.index.rem_euclid(len as f64);
+        let i0 = index as usize % len;
+        let i1 = (i0 + 1) % len;
+        let frac = index.fract() as f32;
+        let value = sample_buffer[i0]
+            + ((sample_buffer[i1] - sample_buffer[i0]) * frac);
+        let amplitude = self
+            .window
+            .amplitude(self.age_samples as f32 / self.length_samples as f32);
+        self.index += self.rate;
+        self.age_samples += 1;
+        let value = value * amplitude;
+        Stereo::new(value * self.gain.left, value * self.gain.right)
+    }
+}
+
+pub struct Granular<P, J, Z, D, R, N, W, S, F>
+where
+    P: SigT<Item = f32>,
+    J: SigT<Item = f32>,
+    Z: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    N: SigT<Item = f32>,
+    W: SigT<Item = GrainWindow>,
+    S: SigT<Item = f32>,
+    F: SigT<Item = bool>,
+{
+    sample_buffer: Vec<f32>,
+    position_01: P,
+    position_jitter_01: J,
+    grain_size_s: Z,
+    density_hz: D,
+    pitch_ratio: R,
+    pan_spread_01: N,
+    window: W,
+    scan_speed: S,
+    freeze: F,
+    max_grains: usize,
+    grains: Vec<Grain>,
+    /// Position of the playhead which moves through the buffer according to `scan_speed`
+    scan_position_01: f64,
+    /// The position at the moment the engine was frozen
+    frozen_position_01: Option<f64>,
+    /// Accumulates towards 1 between the spawning of grains
+    spawn_phase_01: f64,
+    rng: StdRng,
+    buf: Vec<StereoPair<f32>>,
+}
+
+impl<P, J, Z, D, R, N, W, S, F> Granular<P, J, Z, D, R, N, W, S, F>
+where
+    P: SigT<Item = f32>,
+    J: SigT<Item = f32>,
+    Z: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    N: SigT<Item = f32>,
+    W: SigT<Item = GrainWindow>,
+    S: SigT<Item = f32>,
+    F: SigT<Item = bool>,
+{
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        sample_buffer: Vec<f32>,
+        position_01: P,
+        position_jitter_01: J,
+        grain_size_s: Z,
+        density_hz: D,
+        pitch_ratio: R,
+        pan_spread_01: N,
+        window: W,
+        scan_speed: S,
+        freeze: F,
+        max_grains: usize,
+    ) -> Sig<Self> {
+        Sig(Self {
+            sample_buffer,
+            position_01,
+            position_jitter_01,
+            grain_size_s,
+            density_hz,
+            pitch_ratio,
+            pan_spread_01,
+            window,
+            scan_speed,
+            freeze,
+            max_grains,
+            grains: Vec::with_capacity(max_grains),
+            scan_position_01: 0.0,
+            frozen_position_01: None,
+            spawn_phase_01: 1.0,
+            rng: StdRng::from_os_rng(),
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl<P, J, Z, D, R, N, W, S, F> SigT for Granular<P, J, Z, D, R, N, W, S, F>
+where
+    P: SigT<Item = f32>,
+    J: SigT<Item = f32>,
+    Z: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    N: SigT<Item = f32>,
+    W: SigT<Item = GrainWindow>,
+    S: SigT<Item = f32>,
+    F: SigT<Item = bool>,
+{
+    type Item = StereoPair<f32>;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let position_01 = self.position_01.sample(ctx);
+        let position_jitter_01 = self.position_jitter_01.sample(ctx);
+        let grain_size_s = self.grain_size_s.sample(ctx);
+        let density_hz = self.density_hz.sample(ctx);
+        let pitch_ratio = self.pitch_ratio.sample(ctx);
+        let pan_spread_01 = self.pan_spread_01.sample(ctx);
+        let window = self.window.sample(ctx);
+        let scan_speed = self.scan_speed.sample(ctx);
+        let freeze = self.freeze.sample(ctx);
+        let len = self.sample_buffer.len();
+        for (
+            position_01,
+            position_jitter_01,
+            grain_size_s,
+            density_hz,
+            pitch_ratio,
+            pan_spread_01,
+            window,
+            scan_speed,
+            freeze,
+        ) in izip! {
+            position_01.iter(),
+            position_jitter_01.iter(),
+            grain_size_s.iter(),
+            density_hz.iter(),
+            pitch_ratio.iter(),
+            pan_spread_01.iter(),
+            window.iter(),
+            scan_speed.iter(),
+            freeze.iter(),
+        } {
+            if len == 0 {
+                self.buf.push(Stereo::new(0.0, 0.0));
+                continue;
+            }
+            let position_01 = if freeze {
+                *self
+                    .frozen_position_01
+                    .get_or_insert(self.scan_position_01 + position_01 as f64)
+            } else {
+                self.frozen_position_01 = None;
+                self.scan_position_01 = (self.scan_position_01
+                    + (scan_speed as f64 / len as f64))
+                    .rem_euclid(1.0);
+                self.scan_position_01 + position_01 as f64
+            };
+            self.spawn_phase_01 +=
+                density_hz.max(0.0) as f64 / ctx.sample_rate_hz as f64;
+            if self.spawn_phase_01 >= 1.0 {
+                self.spawn_phase_01 = self.spawn_phase_01.fract();
+                let length_samples =
+                    (grain_size_s * ctx.sample_rate_hz).round() as u32;
+                if self.grains.len() < self.max_grains && length_samples > 0 {
+                    let jitter_01 = if position_jitter_01 > 0.0 {
+                        self.rng.random_range(
+                            -position_jitter_01..=position_jitter_01,
+                        ) as f64
+                    } else {
+                        0.0
+                    };
+                    let pan = if pan_spread_01 > 0.0 {
+                        let pan_spread_01 = pan_spread_01.min(1.0);
+                        self.rng.random_range(-pan_spread_01..=pan_spread_01)
+                    } else {
+                        0.0
+                    };
+                    // Equal-power panning
+                    let angle = (pan + 1.0) * PI / 4.0;
+                    self.grains.push(Grain {
+                        index: (position_01 + jitter_01).rem_euclid(1.0)
+                            * len as f64,
+                        rate: pitch_ratio as f64,
+                        age_samples: 0,
+                        length_samples,
+                        window,
+                        gain: Stereo::new(angle.cos(), angle.sin()),
+                    });
+                }
+            }
+            let mut out = Stereo::new(0.0, 0.0);
+            for grain in &mut self.grains {
+                let sample = grain.next_sample(&self.sample_buffer);
+                out.left += sample.left;
+                out.right += sample.right;
+            }
+            self.grains.retain(|grain| !grain.is_finished());
+            // Scale the output by the expected number of overlapping grains so the level doesn't
+            // change too much with the density and grain size.
+            let overlap = (density_hz * grain_size_s).max(1.0);
+            let scale = 1.0 / overlap.sqrt();
+            self.buf
+                .push(Stereo::new(out.left * scale, out.right * scale));
+        }
+        &self.buf
+    }
+}
+
+type GranularSig<P, J, Z, D, R, N, W, S, F> =
+    Sig<Granular<P, J, Z, D, R, N, W, S, F>>;
+
+builder! {
+    #[constructor = "granular"]
+    #[constructor_doc = "Play overlapping short grains of audio taken from a buffer"]
+    #[build_fn = "Granular::new"]
+    #[build_ty = "GranularSig<P, J, Z, D, R, N, W, S, F>"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        sample_buffer: Vec<f32>,
+        // Where in the buffer new grains start, where 0 is the start and 1 is the end. This is
+        // added to the position of the scanning playhead and wrapped to the buffer.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.0]
+        position_01: f32,
+        // Grains start at a random offset up to this far either side of the position.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "J"]
+        #[default = 0.0]
+        position_jitter_01: f32,
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "Z"]
+        #[default = 0.1]
+        grain_size_s: f32,
+        // The number of grains started per second
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 20.0]
+        density_hz: f32,
+        // The playback speed of each grain. Negative values play grains backwards.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "R"]
+        #[default = 1.0]
+        pitch_ratio: f32,
+        // Each grain is panned randomly up to this far from the center.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "N"]
+        #[default = 0.0]
+        pan_spread_01: f32,
+        #[generic_with_constraint = "SigT<Item = GrainWindow>"]
+        #[generic_name = "W"]
+        #[default = GrainWindow::Hann]
+        window: GrainWindow,
+        // The speed at which the playhead moves through the buffer, relative to the speed of
+        // regular playback. At the default of 0 the playhead stays still.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "S"]
+        #[default = 0.0]
+        scan_speed: f32,
+        // While this is true the position where grains start is held at its value from the
+        // moment the engine was frozen, and the playhead stops moving. Grains continue to be
+        // spawned so the sound is sustained.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "F"]
+        #[default = false]
+        freeze: bool,
+        // Grains which would start while this many grains are already playing are skipped.
+        #[default = 64]
+        max_grains: usize,
+    }
+}
+
+impl<P, J, Z, D, R, N, W, S, F> Props<P, J, Z, D, R, N, W, S, F>
+where
+    P: SigT<Item = f32>,
+    J: SigT<Item = f32>,
+    Z: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    N: SigT<Item = f32>,
+    W: SigT<Item = GrainWindow>,
+    S: SigT<Item = f32>,
+    F: SigT<Item = bool>,
+{
+    /// Build the granular engine and split its output into a signal for each channel.
+    pub fn stereo(
+        self,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>> {
+        let granular = self.build().shared();
+        Stereo::new(
+            granular.clone().map(|s: StereoPair<f32>| s.left),
+            granular.map(|s: StereoPair<f32>| s.right),
+        )
+    }
+}
@@ -0,0 +1,188 @@
+use crate::delay_line::{DelayInterpolation, DelayLine, DelayTap};
+pub use crate::low_level::physical_model::Excitation;
+use crate::low_level::physical_model::{
+    Exciter, OnePoleLowPass, TwoPointAverage,
+};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Sig, SigCtx, SigT, Triggerable};
+use itertools::izip;
+use std::f32::consts::PI;
+
+builder! {
+    #[constructor = "karplus_strong"]
+    #[constructor_doc = "Plucked string made from a burst of excitation recirculating through a filtered delay line. Plucked each time its trigger signal goes high."]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        freq_hz: _,
+        #[default = Excitation::NoiseBurst]
+        excitation: Excitation,
+        // Only used with `Excitation::External`
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "E"]
+        #[default = 0.0]
+        excitation_signal: f32,
+        // Between 0 and 1. Higher values filter the string more heavily each time around the loop,
+        // so high harmonics die away faster and the sound is darker.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 0.2]
+        damping: f32,
+        // Between 0 and 1. At 0.5 the string decays like the original algorithm. Values further
+        // from 0.5 filter the loop less so the string rings for longer, which stops high notes
+        // from decaying unnaturally quickly.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "S"]
+        #[default = 0.5]
+        stretch: f32,
+        // Where along the string it is plucked, between 0 and 1. Plucking at a fraction `1 / n`
+        // of the length removes every nth harmonic, so values near 0 are bright and values near
+        // 0.5 are hollow. At 0 the excitation isn't shaped at all.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.2]
+        pick_position_01: f32,
+    }
+}
+
+/// The lowest frequency the string can be tuned to. Determines the length of its delay line.
+const MIN_FREQ_HZ: f32 = 20.0;
+
+/// Damping of 1 maps to this loop filter coefficient, which still lets the string ring briefly.
+const MAX_DAMPING_COEFFICIENT: f32 = 0.95;
+
+impl<F, E, D, S, P> Triggerable for Props<F, E, D, S, P>
+where
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn into_sig<T>(self, trig: T) -> impl SigT<Item = Self::Item>
+    where
+        T: SigT<Item = bool>,
+    {
+        KarplusStrong {
+            props: self,
+            trig: Sig(trig).gate_to_trig_rising_edge().0,
+            exciter: Exciter::new(),
+            excitation_history: None,
+            delay_line: None,
+            tap: DelayTap::new(DelayInterpolation::Allpass),
+            average: TwoPointAverage::new(),
+            low_pass: OnePoleLowPass::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct KarplusStrong<T, F, E, D, S, P>
+where
+    T: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    props: Props<F, E, D, S, P>,
+    trig: T,
+    exciter: Exciter,
+    // Recent excitation samples, used to shape the excitation according to the pick position
+    excitation_history: Option<DelayLine>,
+    delay_line: Option<DelayLine>,
+    tap: DelayTap,
+    average: TwoPointAverage,
+    low_pass: OnePoleLowPass,
+    buf: Vec<f32>,
+}
+
+impl<T, F, E, D, S, P> SigT for KarplusStrong<T, F, E, D, S, P>
+where
+    T: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let trig = self.trig.sample(ctx);
+        let freq_hz = self.props.freq_hz.sample(ctx);
+        let excitation_signal = self.props.excitation_signal.sample(ctx);
+        let damping = self.props.damping.sample(ctx);
+        let stretch = self.props.stretch.sample(ctx);
+        let pick_position_01 = self.props.pick_position_01.sample(ctx);
+        let excitation_history =
+            self.excitation_history.get_or_insert_with(|| {
+                DelayLine::new_s(1.0 / MIN_FREQ_HZ, ctx.sample_rate_hz)
+            });
+        let delay_line = self.delay_line.get_or_insert_with(|| {
+            DelayLine::new_s(1.0 / MIN_FREQ_HZ, ctx.sample_rate_hz)
+        });
+        for (
+            out,
+            trig,
+            freq_hz,
+            excitation_signal,
+            damping,
+            stretch,
+            pick_position_01,
+        ) in izip! {
+            self.buf.iter_mut(),
+            trig.iter(),
+            freq_hz.iter(),
+            excitation_signal.iter(),
+            damping.iter(),
+            stretch.iter(),
+            pick_position_01.iter(),
+        } {
+            let freq_hz = freq_hz.clamp(MIN_FREQ_HZ, ctx.sample_rate_hz / 4.0);
+            let period_samples = ctx.sample_rate_hz / freq_hz;
+            if trig {
+                self.exciter.trigger(period_samples.round() as usize);
+            }
+            let excitation = self
+                .exciter
+                .process(self.props.excitation, excitation_signal);
+            excitation_history.write(excitation);
+            // Subtracting a delayed copy of the excitation models the wave reflected from the
+            // nearer end of the string, cancelling the harmonics with a node at the pick point.
+            let pick_delay_samples =
+                (pick_position_01.clamp(0.0, 1.0) * period_samples).round();
+            let excitation = if pick_delay_samples >= 1.0 {
+                excitation - excitation_history.get(pick_delay_samples as usize)
+            } else {
+                excitation
+            };
+            let stretch = stretch.clamp(0.0, 1.0);
+            let coefficient = damping.clamp(0.0, 1.0) * MAX_DAMPING_COEFFICIENT;
+            // The loop filters and the delay line's write each delay the signal, so the delay
+            // line is shortened by the same amount to keep the fundamental in tune.
+            let omega = 2.0 * PI * freq_hz / ctx.sample_rate_hz;
+            let filter_delay_samples =
+                TwoPointAverage::phase_delay(stretch, omega)
+                    + OnePoleLowPass::phase_delay(coefficient, omega)
+                    + 1.0;
+            let delayed = self.tap.read_tuned(
+                delay_line,
+                period_samples - filter_delay_samples,
+                omega,
+            );
+            let filtered = self
+                .low_pass
+                .process(self.average.process(delayed, stretch), coefficient);
+            let sample = filtered + excitation;
+            delay_line.write(sample);
+            *out = sample;
+        }
+        &self.buf
+    }
+}
@@ -78,11 +78,18 @@ pub mod band_pass {
     pub use butterworth as default;
 }
 
+pub mod equalizer;
+pub use equalizer::{EqBandType, EqResponse, equalizer};
+
 pub mod reverb_freeverb;
 pub use reverb_freeverb::reverb_freeverb;
 
+pub mod reverb_plate;
+pub use reverb_plate::reverb_plate;
+
 pub mod reverb {
     pub use super::reverb_freeverb as freeverb;
+    pub use super::reverb_plate as plate;
 
     pub use freeverb as default;
 }
@@ -105,6 +112,15 @@ pub use periodic_gate::periodic_gate_s;
 pub mod delay_s;
 pub use delay_s::delay_s;
 
+pub mod delay_stereo;
+pub use delay_stereo::delay_stereo;
+
+pub mod delay_line;
+pub use delay_line::{DelayInterpolation, DelayLine, DelayTap};
+
+pub mod multi_tap_delay;
+pub use multi_tap_delay::multi_tap_delay;
+
 pub mod delay_trig;
 pub use delay_trig::delay_trig;
 
@@ -114,8 +130,56 @@ pub use compressor::compressor;
 pub mod chorus;
 pub use chorus::{ChorusLfoOffset, chorus};
 
+pub mod phaser;
+pub use phaser::phaser;
+
+pub mod flanger;
+pub use flanger::{FlangerFeedbackPolarity, flanger};
+
 pub mod quantizer;
 pub use quantizer::quantizer;
 
 pub mod down_sample;
 pub use down_sample::down_sample;
+
+pub mod distortion;
+pub use distortion::{DistortionCurve, Oversampling, distortion};
+
+pub mod oversample;
+pub use oversample::oversample;
+
+pub mod wavefolder;
+pub use wavefolder::wavefolder;
+
+pub mod low_pass_gate;
+pub use low_pass_gate::low_pass_gate;
+
+pub mod karplus_strong;
+pub use karplus_strong::{Excitation, karplus_strong};
+
+pub mod waveguide;
+pub use waveguide::{WaveguideMedium, waveguide};
+
+pub mod modal_resonator;
+pub use modal_resonator::{ModalMode, ModalPreset, modal_resonator};
+
+pub mod turing_machine;
+pub use turing_machine::turing_machine;
+
+pub mod bernoulli_gate;
+pub use bernoulli_gate::bernoulli_gate;
+
+pub mod flip_flop;
+pub use flip_flop::{flip_flop_d, flip_flop_toggle};
+
+pub mod counter;
+pub use counter::counter;
+
+pub mod comparator;
+pub use comparator::comparator;
+
+pub mod scale_quantizer;
+pub use scale_quantizer::scale_quantizer;
+
+pub mod granular;
+pub use granular::{GrainWindow, granular};
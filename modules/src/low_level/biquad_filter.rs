@@ -65,6 +65,26 @@ impl FilterType for LowPass {
     }
 }
 
+struct HighPass;
+
+impl FilterType for HighPass {
+    fn normalized_coefficients(
+        params: Params,
+        sample_rate_hz: f64,
+    ) -> NormalizedCoefficients {
+        let Common { cos_omega, alpha } =
+            Common::from_params(params, sample_rate_hz);
+        let a0 = 1.0 + alpha;
+        NormalizedCoefficients {
+            b0: ((1.0 + cos_omega) * 0.5) / a0,
+            b1: -(1.0 + cos_omega) / a0,
+            b2: ((1.0 + cos_omega) * 0.5) / a0,
+            a1: (-2.0 * cos_omega) / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 struct State {
     s1: f64,
@@ -137,3 +157,189 @@ impl BiquadFilterLowPass {
         self.0.process(sample, cutoff_hz, resonance, sample_rate_hz)
     }
 }
+
+pub struct BiquadFilterHighPass(BiquadFilter<HighPass>);
+
+impl BiquadFilterHighPass {
+    pub fn new() -> Self {
+        Self(BiquadFilter {
+            filter_type: PhantomData,
+            state: State::default(),
+            normalized_coefficients: NormalizedCoefficients::default(),
+            params: Params::default(),
+        })
+    }
+
+    pub fn process(
+        &mut self,
+        sample: f64,
+        cutoff_hz: f64,
+        resonance: f64,
+        sample_rate_hz: f64,
+    ) -> f64 {
+        self.0.process(sample, cutoff_hz, resonance, sample_rate_hz)
+    }
+}
+
+/// The shape of a single band of an equalizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EqBandType {
+    /// Boost or cut frequencies below the band frequency
+    LowShelf,
+    /// Boost or cut frequencies above the band frequency
+    HighShelf,
+    /// Boost or cut frequencies around the band frequency
+    Peak,
+    /// Remove a narrow range of frequencies around the band frequency. Ignores gain.
+    Notch,
+    /// Remove frequencies below the band frequency. Ignores gain.
+    LowCut,
+    /// Remove frequencies above the band frequency. Ignores gain.
+    HighCut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBandParams {
+    pub band_type: EqBandType,
+    pub freq_hz: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl NormalizedCoefficients {
+    fn from_eq_band_params(
+        EqBandParams {
+            band_type,
+            freq_hz,
+            gain_db,
+            q,
+        }: EqBandParams,
+        sample_rate_hz: f64,
+    ) -> Self {
+        let Common { cos_omega, alpha } = Common::from_params(
+            Params {
+                cutoff_hz: freq_hz,
+                resonance: q.max(0.01),
+            },
+            sample_rate_hz,
+        );
+        let a = 10_f64.powf(gain_db / 40.0);
+        let sqrt_a_alpha_2 = 2.0 * a.sqrt() * alpha;
+        let (b0, b1, b2, a0, a1, a2) = match band_type {
+            EqBandType::LowShelf => (
+                a * ((a + 1.0) - ((a - 1.0) * cos_omega) + sqrt_a_alpha_2),
+                2.0 * a * ((a - 1.0) - ((a + 1.0) * cos_omega)),
+                a * ((a + 1.0) - ((a - 1.0) * cos_omega) - sqrt_a_alpha_2),
+                (a + 1.0) + ((a - 1.0) * cos_omega) + sqrt_a_alpha_2,
+                -2.0 * ((a - 1.0) + ((a + 1.0) * cos_omega)),
+                (a + 1.0) + ((a - 1.0) * cos_omega) - sqrt_a_alpha_2,
+            ),
+            EqBandType::HighShelf => (
+                a * ((a + 1.0) + ((a - 1.0) * cos_omega) + sqrt_a_alpha_2),
+                -2.0 * a * ((a - 1.0) + ((a + 1.0) * cos_omega)),
+                a * ((a + 1.0) + ((a - 1.0) * cos_omega) - sqrt_a_alpha_2),
+                (a + 1.0) - ((a - 1.0) * cos_omega) + sqrt_a_alpha_2,
+                2.0 * ((a - 1.0) - ((a + 1.0) * cos_omega)),
+                (a + 1.0) - ((a - 1.0) * cos_omega) - sqrt_a_alpha_2,
+            ),
+            EqBandType::Peak => (
+                1.0 + (alpha * a),
+                -2.0 * cos_omega,
+                1.0 - (alpha * a),
+                1.0 + (alpha / a),
+                -2.0 * cos_omega,
+                1.0 - (alpha / a),
+            ),
+            EqBandType::Notch => (
+                1.0,
+                -2.0 * cos_omega,
+                1.0,
+                1.0 + alpha,
+                -2.0 * cos_omega,
+                1.0 - alpha,
+            ),
+            EqBandType::LowCut => (
+                (1.0 + cos_omega) * 0.5,
+                -(1.0 + cos_omega),
+                (1.0 + cos_omega) * 0.5,
+                1.0 + alpha,
+                -2.0 * cos_omega,
+                1.0 - alpha,
+            ),
+            EqBandType::HighCut => (
+                (1.0 - cos_omega) * 0.5,
+                1.0 - cos_omega,
+                (1.0 - cos_omega) * 0.5,
+                1.0 + alpha,
+                -2.0 * cos_omega,
+                1.0 - alpha,
+            ),
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// The factor by which the filter scales the amplitude of a sine wave at the given frequency
+    fn magnitude(&self, freq_hz: f64, sample_rate_hz: f64) -> f64 {
+        let omega = (f64::consts::PI * 2.0 * freq_hz) / sample_rate_hz;
+        let (sin_1, cos_1) = omega.sin_cos();
+        let (sin_2, cos_2) = (2.0 * omega).sin_cos();
+        // Evaluate the numerator and denominator of the transfer function at z = e^(i * omega).
+        let num_re = self.b0 + (self.b1 * cos_1) + (self.b2 * cos_2);
+        let num_im = -(self.b1 * sin_1) - (self.b2 * sin_2);
+        let den_re = 1.0 + (self.a1 * cos_1) + (self.a2 * cos_2);
+        let den_im = -(self.a1 * sin_1) - (self.a2 * sin_2);
+        ((num_re * num_re) + (num_im * num_im)).sqrt()
+            / ((den_re * den_re) + (den_im * den_im)).sqrt()
+    }
+}
+
+/// The gain in dB applied by a single band of an equalizer to a sine wave at `freq_hz`.
+pub fn eq_band_gain_db(
+    params: EqBandParams,
+    freq_hz: f64,
+    sample_rate_hz: f64,
+) -> f64 {
+    let magnitude =
+        NormalizedCoefficients::from_eq_band_params(params, sample_rate_hz)
+            .magnitude(freq_hz, sample_rate_hz);
+    20.0 * magnitude.max(1e-12).log10()
+}
+
+pub struct BiquadFilterEq {
+    state: State,
+    normalized_coefficients: NormalizedCoefficients,
+    params: Option<EqBandParams>,
+}
+
+impl BiquadFilterEq {
+    pub fn new() -> Self {
+        Self {
+            state: State::default(),
+            normalized_coefficients: NormalizedCoefficients::default(),
+            params: None,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        sample: f64,
+        params: EqBandParams,
+        sample_rate_hz: f64,
+    ) -> f64 {
+        if self.params != Some(params) {
+            self.params = Some(params);
+            self.normalized_coefficients =
+                NormalizedCoefficients::from_eq_band_params(
+                    params,
+                    sample_rate_hz,
+                );
+        }
+        self.state.process(sample, &self.normalized_coefficients)
+    }
+}
@@ -0,0 +1,222 @@
+// Half-band low-pass filters for changing the sample rate of a signal by a factor of 2. A
+// half-band filter has its cutoff at a quarter of the sample rate, which makes every second
+// coefficient zero except the center coefficient of 0.5. The resamplers below exploit this by only
+// computing the polyphase branch with nonzero coefficients.
+
+use std::f64::consts::PI;
+
+/// Half of the length of the filter, excluding the center tap. Must be odd so the nonzero
+/// coefficients fall an odd distance from the center.
+const HALF_LENGTH: usize = 23;
+
+/// Number of nonzero coefficients on each side of the center tap
+const NUM_BRANCH_COEFFICIENTS: usize = HALF_LENGTH.div_ceil(2);
+
+/// The nonzero coefficients of a windowed-sinc half-band filter on one side of the center, where
+/// index `k` is the coefficient `2k + 1` taps from the center. The coefficients are symmetric.
+fn branch_coefficients() -> [f32; NUM_BRANCH_COEFFICIENTS] {
+    let length = (2 * HALF_LENGTH) + 1;
+    let mut coefficients = [0.0; NUM_BRANCH_COEFFICIENTS];
+    for (k, coefficient) in coefficients.iter_mut().enumerate() {
+        let offset = ((2 * k) + 1) as f64;
+        let sinc = (PI * offset / 2.0).sin() / (PI * offset);
+        // Blackman window
+        let n = HALF_LENGTH as f64 + offset;
+        let m = (length - 1) as f64;
+        let window = 0.42 - (0.5 * ((2.0 * PI * n) / m).cos())
+            + (0.08 * ((4.0 * PI * n) / m).cos());
+        *coefficient = (sinc * window) as f32;
+    }
+    // Normalize so the filter has unity gain at DC. The center tap contributes 0.5, so the
+    // remaining coefficients (counting both sides) must also sum to 0.5.
+    let sum = coefficients.iter().sum::<f32>() * 2.0;
+    for coefficient in &mut coefficients {
+        *coefficient *= 0.5 / sum;
+    }
+    coefficients
+}
+
+/// Doubles the sample rate of a signal, removing the images above the original Nyquist frequency.
+pub struct HalfBandUpsampler {
+    coefficients: [f32; NUM_BRANCH_COEFFICIENTS],
+    // The most recent input samples, where index 0 is the most recent
+    history: [f32; HALF_LENGTH + 1],
+}
+
+impl HalfBandUpsampler {
+    pub fn new() -> Self {
+        Self {
+            coefficients: branch_coefficients(),
+            history: [0.0; HALF_LENGTH + 1],
+        }
+    }
+
+    /// Returns the two output samples corresponding to a single input sample, in order.
+    pub fn process(&mut self, sample: f32) -> [f32; 2] {
+        self.history.copy_within(0..HALF_LENGTH, 1);
+        self.history[0] = sample;
+        // Zero-stuffing halves the level of the signal, so the filter gain is doubled.
+        let filtered = 2.0
+            * self
+                .coefficients
+                .iter()
+                .enumerate()
+                .map(|(k, coefficient)| {
+                    coefficient
+                        * (self.history[NUM_BRANCH_COEFFICIENTS - 1 - k]
+                            + self.history[NUM_BRANCH_COEFFICIENTS + k])
+                })
+                .sum::<f32>();
+        // The only nonzero coefficient in the other branch is the center tap.
+        let center = self.history[(HALF_LENGTH - 1) / 2];
+        [filtered, center]
+    }
+}
+
+/// Halves the sample rate of a signal, removing frequencies above the new Nyquist frequency first
+/// so they don't alias.
+pub struct HalfBandDownsampler {
+    coefficients: [f32; NUM_BRANCH_COEFFICIENTS],
+    // The most recent input samples, where index 0 is the most recent
+    history: [f32; (2 * HALF_LENGTH) + 1],
+}
+
+impl HalfBandDownsampler {
+    pub fn new() -> Self {
+        Self {
+            coefficients: branch_coefficients(),
+            history: [0.0; (2 * HALF_LENGTH) + 1],
+        }
+    }
+
+    /// Takes two consecutive input samples in order and returns a single output sample.
+    pub fn process(&mut self, samples: [f32; 2]) -> f32 {
+        self.history.copy_within(0..((2 * HALF_LENGTH) - 1), 2);
+        self.history[1] = samples[0];
+        self.history[0] = samples[1];
+        let branch = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .map(|(k, coefficient)| {
+                coefficient
+                    * (self.history[HALF_LENGTH - ((2 * k) + 1)]
+                        + self.history[HALF_LENGTH + (2 * k) + 1])
+            })
+            .sum::<f32>();
+        (0.5 * self.history[HALF_LENGTH]) + branch
+    }
+}
+
+/// How many times higher than the signal's sample rate a process is run at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+    None,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub fn factor(self) -> usize {
+        1 << self.num_stages()
+    }
+
+    fn num_stages(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::X2 => 1,
+            Self::X4 => 2,
+            Self::X8 => 3,
+        }
+    }
+}
+
+const MAX_FACTOR: usize = 8;
+
+/// Runs a per-sample process at a multiple of the sample rate by upsampling its input and
+/// downsampling its output with a cascade of half-band filters.
+pub struct Oversampler {
+    upsamplers: Vec<HalfBandUpsampler>,
+    downsamplers: Vec<HalfBandDownsampler>,
+    scratch: Vec<f32>,
+}
+
+impl Oversampler {
+    pub fn new(oversampling: Oversampling) -> Self {
+        let num_stages = oversampling.num_stages();
+        Self {
+            upsamplers: (0..num_stages)
+                .map(|_| HalfBandUpsampler::new())
+                .collect(),
+            downsamplers: (0..num_stages)
+                .map(|_| HalfBandDownsampler::new())
+                .collect(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Pass a sample through `f` at the oversampled rate, returning the result at the original
+    /// sample rate.
+    pub fn process(
+        &mut self,
+        sample: f32,
+        mut f: impl FnMut(f32) -> f32,
+    ) -> f32 {
+        let mut buf = [0.0; MAX_FACTOR];
+        let mut upsampled = [0.0; MAX_FACTOR];
+        buf[0] = sample;
+        let mut len = 1;
+        for upsampler in &mut self.upsamplers {
+            for i in 0..len {
+                let [a, b] = upsampler.process(buf[i]);
+                upsampled[2 * i] = a;
+                upsampled[(2 * i) + 1] = b;
+            }
+            len *= 2;
+            buf[0..len].copy_from_slice(&upsampled[0..len]);
+        }
+        for sample in &mut buf[0..len] {
+            *sample = f(*sample);
+        }
+        // The last upsampler runs at the highest rate, so its corresponding downsampler runs
+        // first.
+        for downsampler in self.downsamplers.iter_mut().rev() {
+            len /= 2;
+            for i in 0..len {
+                buf[i] = downsampler.process([buf[2 * i], buf[(2 * i) + 1]]);
+            }
+        }
+        buf[0]
+    }
+
+    /// Upsample a block of samples, replacing the contents of `output`. The upsampler keeps
+    /// state between calls, so consecutive blocks of a signal should be passed in order.
+    pub fn upsample_block(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend_from_slice(input);
+        for upsampler in &mut self.upsamplers {
+            self.scratch.clear();
+            self.scratch.extend_from_slice(output);
+            output.clear();
+            for &sample in &self.scratch {
+                output.extend(upsampler.process(sample));
+            }
+        }
+    }
+
+    /// Downsample a block of samples, replacing the contents of `output`. The length of the input
+    /// must be a multiple of the oversampling factor.
+    pub fn downsample_block(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend_from_slice(input);
+        for downsampler in self.downsamplers.iter_mut().rev() {
+            let len = output.len() / 2;
+            for i in 0..len {
+                output[i] =
+                    downsampler.process([output[2 * i], output[(2 * i) + 1]]);
+            }
+            output.truncate(len);
+        }
+    }
+}
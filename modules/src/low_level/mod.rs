@@ -2,6 +2,8 @@ pub mod biquad_filter;
 pub mod butterworth_chebyshev;
 pub mod diode_ladder;
 pub mod freeverb;
+pub mod half_band;
 pub mod linearly_interpolating_ring_buffer;
 pub mod moog_ladder;
 pub mod moog_ladder_oberheim;
+pub mod physical_model;
@@ -0,0 +1,120 @@
+// Building blocks shared by physical models made from delay lines in a feedback loop, such as
+// plucked strings and waveguides. The filters in the loop delay the signal as well as shaping it,
+// so each filter can report its phase delay at the fundamental frequency, which is subtracted from
+// the length of the delay line to keep the model in tune.
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+/// The phase delay in samples at `omega` radians per sample of the first order filter
+/// `y[n] = b0 x[n] + b1 x[n - 1] + a1 y[n - 1]`.
+fn first_order_phase_delay(b0: f32, b1: f32, a1: f32, omega: f32) -> f32 {
+    let (sin, cos) = omega.sin_cos();
+    let numerator_phase = (-b1 * sin).atan2(b0 + (b1 * cos));
+    let denominator_phase = (a1 * sin).atan2(1.0 - (a1 * cos));
+    (denominator_phase - numerator_phase) / omega
+}
+
+/// One-pole low-pass filter with unity gain at DC. A coefficient of 0 passes the signal through
+/// unchanged and values approaching 1 filter it more heavily.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OnePoleLowPass {
+    prev_output: f32,
+}
+
+impl OnePoleLowPass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, sample: f32, coefficient: f32) -> f32 {
+        let output =
+            ((1.0 - coefficient) * sample) + (coefficient * self.prev_output);
+        self.prev_output = output;
+        output
+    }
+
+    pub fn phase_delay(coefficient: f32, omega: f32) -> f32 {
+        first_order_phase_delay(1.0 - coefficient, 0.0, coefficient, omega)
+    }
+}
+
+/// Weighted average of the current and previous sample. At a weight of 0.5 this is the loss
+/// filter from the original Karplus-Strong algorithm. Lower weights filter high frequencies less,
+/// so they decay more slowly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TwoPointAverage {
+    prev_input: f32,
+}
+
+impl TwoPointAverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, sample: f32, weight: f32) -> f32 {
+        let output = ((1.0 - weight) * sample) + (weight * self.prev_input);
+        self.prev_input = sample;
+        output
+    }
+
+    pub fn phase_delay(weight: f32, omega: f32) -> f32 {
+        first_order_phase_delay(1.0 - weight, weight, 0.0, omega)
+    }
+}
+
+/// How energy is put into a physical model when it's triggered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Excitation {
+    /// A burst of white noise lasting one period of the fundamental, for a bright pluck
+    #[default]
+    NoiseBurst,
+    /// A single sample, for a pure tone with all harmonics at equal level
+    Impulse,
+    /// The model's excitation signal, passed through for one period of the fundamental after each
+    /// trigger. Useful for plucking a string with a sample or another oscillator.
+    External,
+}
+
+/// Produces the excitation for a physical model after each trigger.
+pub struct Exciter {
+    rng: StdRng,
+    remaining_samples: usize,
+    is_first_sample: bool,
+}
+
+impl Exciter {
+    pub fn new() -> Self {
+        Self {
+            rng: StdRng::from_os_rng(),
+            remaining_samples: 0,
+            is_first_sample: false,
+        }
+    }
+
+    /// Start a new excitation lasting `length_samples`.
+    pub fn trigger(&mut self, length_samples: usize) {
+        self.remaining_samples = length_samples.max(1);
+        self.is_first_sample = true;
+    }
+
+    /// The next sample of the excitation. `external` is only used by `Excitation::External`.
+    pub fn process(&mut self, excitation: Excitation, external: f32) -> f32 {
+        if self.remaining_samples == 0 {
+            return 0.0;
+        }
+        self.remaining_samples -= 1;
+        let is_first_sample = self.is_first_sample;
+        self.is_first_sample = false;
+        match excitation {
+            Excitation::NoiseBurst => self.rng.random_range(-1.0..1.0),
+            Excitation::Impulse => {
+                if is_first_sample {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Excitation::External => external,
+        }
+    }
+}
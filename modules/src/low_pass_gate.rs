@@ -0,0 +1,152 @@
+use crate::low_level::biquad_filter::BiquadFilterLowPass;
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+
+builder! {
+    #[constructor = "low_pass_gate"]
+    #[constructor_doc = "Combined amplifier and low pass filter driven by a single control through a model of a vactrol, so sounds get darker as they get quieter"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // Between 0 (closed) and 1 (open). A short pulse "pings" the gate, producing a
+        // percussive sound whose tail is shaped by the vactrol's slow decay.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "C"]
+        control: _,
+        // Time taken for the vactrol to respond to the control increasing
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "A"]
+        #[default = 0.005]
+        attack_s: f32,
+        // Time taken for the vactrol to respond to the control decreasing. The decay slows down
+        // as the vactrol gets darker, so the tail lasts longer than this.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 0.2]
+        decay_s: f32,
+        // The filter cutoff when the gate is fully open. The cutoff falls exponentially as the
+        // gate closes.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "H"]
+        #[default = 12_000.0]
+        max_cutoff_hz: f32,
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "R"]
+        #[default = 0.0]
+        resonance: f32,
+    }
+}
+
+/// The filter cutoff when the gate is fully closed
+const MIN_CUTOFF_HZ: f32 = 20.0;
+
+/// How much slower the vactrol decays when it is dark compared to when it is bright
+const DARK_DECAY_SLOWDOWN: f32 = 3.0;
+
+impl<C, A, D, H, R> Filter for Props<C, A, D, H, R>
+where
+    C: SigT<Item = f32>,
+    A: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = LowPassGate<S, C, A, D, H, R>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        LowPassGate {
+            props: self,
+            sig,
+            vactrol_level: 0.0,
+            low_pass: BiquadFilterLowPass::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct LowPassGate<S, C, A, D, H, R>
+where
+    S: SigT<Item = f32>,
+    C: SigT<Item = f32>,
+    A: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+{
+    props: Props<C, A, D, H, R>,
+    sig: S,
+    // The brightness of the light inside the vactrol, between 0 and 1
+    vactrol_level: f32,
+    low_pass: BiquadFilterLowPass,
+    buf: Vec<f32>,
+}
+
+impl<S, C, A, D, H, R> SigT for LowPassGate<S, C, A, D, H, R>
+where
+    S: SigT<Item = f32>,
+    C: SigT<Item = f32>,
+    A: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    H: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let sig = self.sig.sample(ctx);
+        let control = self.props.control.sample(ctx);
+        let attack_s = self.props.attack_s.sample(ctx);
+        let decay_s = self.props.decay_s.sample(ctx);
+        let max_cutoff_hz = self.props.max_cutoff_hz.sample(ctx);
+        let resonance = self.props.resonance.sample(ctx);
+        for (
+            out,
+            sample,
+            control,
+            attack_s,
+            decay_s,
+            max_cutoff_hz,
+            resonance,
+        ) in izip! {
+            self.buf.iter_mut(),
+            sig.iter(),
+            control.iter(),
+            attack_s.iter(),
+            decay_s.iter(),
+            max_cutoff_hz.iter(),
+            resonance.iter(),
+        } {
+            let control = control.clamp(0.0, 1.0);
+            let time_s = if control > self.vactrol_level {
+                attack_s
+            } else {
+                decay_s
+                    * (1.0 + (DARK_DECAY_SLOWDOWN * (1.0 - self.vactrol_level)))
+            };
+            // One-pole smoothing towards the control
+            let coefficient =
+                1.0 - (-1.0 / (time_s.max(1e-4) * ctx.sample_rate_hz)).exp();
+            self.vactrol_level += (control - self.vactrol_level) * coefficient;
+            let cutoff_hz = MIN_CUTOFF_HZ
+                * (max_cutoff_hz.max(MIN_CUTOFF_HZ) / MIN_CUTOFF_HZ)
+                    .powf(self.vactrol_level);
+            let filtered = self.low_pass.process(
+                sample as f64,
+                cutoff_hz as f64,
+                resonance as f64,
+                ctx.sample_rate_hz as f64,
+            ) as f32;
+            *out = filtered * self.vactrol_level;
+        }
+        &self.buf
+    }
+}
@@ -0,0 +1,264 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, Sig, SigCtx, SigT, Triggerable};
+use itertools::izip;
+use std::f32::consts::PI;
+
+/// A single resonant mode of vibration of an object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModalMode {
+    /// The frequency of the mode relative to the resonator's frequency
+    pub freq_ratio: f32,
+    /// The level of the mode when it's excited by an impulse
+    pub amplitude: f32,
+    /// Time taken for the mode to decay by 60dB when undamped
+    pub decay_s: f32,
+}
+
+impl ModalMode {
+    pub const fn new(freq_ratio: f32, amplitude: f32, decay_s: f32) -> Self {
+        Self {
+            freq_ratio,
+            amplitude,
+            decay_s,
+        }
+    }
+}
+
+/// Free-free bar, like a glockenspiel or xylophone bar
+const BAR: &[ModalMode] = &[
+    ModalMode::new(1.0, 0.5, 1.5),
+    ModalMode::new(2.756, 0.25, 0.8),
+    ModalMode::new(5.404, 0.13, 0.5),
+    ModalMode::new(8.933, 0.07, 0.3),
+    ModalMode::new(13.345, 0.05, 0.2),
+];
+
+/// Thin circular plate, like a gong without a raised center
+const PLATE: &[ModalMode] = &[
+    ModalMode::new(1.0, 0.3, 2.5),
+    ModalMode::new(1.73, 0.2, 2.0),
+    ModalMode::new(2.33, 0.15, 1.8),
+    ModalMode::new(3.91, 0.12, 1.4),
+    ModalMode::new(4.47, 0.1, 1.2),
+    ModalMode::new(6.71, 0.07, 0.9),
+    ModalMode::new(8.36, 0.06, 0.7),
+];
+
+/// Church bell. The strongest partials are tuned to the hum an octave below the resonator's
+/// frequency, the fundamental, a minor third, a fifth and an octave above it.
+const BELL: &[ModalMode] = &[
+    ModalMode::new(0.5, 0.2, 6.0),
+    ModalMode::new(1.0, 0.25, 4.0),
+    ModalMode::new(1.183, 0.15, 3.0),
+    ModalMode::new(1.506, 0.1, 2.5),
+    ModalMode::new(2.0, 0.12, 2.0),
+    ModalMode::new(2.514, 0.06, 1.5),
+    ModalMode::new(2.662, 0.05, 1.2),
+    ModalMode::new(3.011, 0.04, 1.0),
+    ModalMode::new(4.166, 0.03, 0.8),
+];
+
+/// Tubular bell or wind chime. The modes are those of a bar, but the lowest ones are weak so the
+/// pitch is heard from the higher ones.
+const TUBE: &[ModalMode] = &[
+    ModalMode::new(1.0, 0.05, 3.0),
+    ModalMode::new(2.756, 0.15, 3.0),
+    ModalMode::new(5.404, 0.25, 2.5),
+    ModalMode::new(8.933, 0.25, 2.0),
+    ModalMode::new(13.345, 0.2, 1.5),
+    ModalMode::new(18.64, 0.1, 1.0),
+];
+
+/// The set of modes of a modal resonator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModalPreset {
+    Bar,
+    Plate,
+    Bell,
+    Tube,
+    Custom(Vec<ModalMode>),
+}
+
+impl ModalPreset {
+    pub fn modes(&self) -> &[ModalMode] {
+        match self {
+            Self::Bar => BAR,
+            Self::Plate => PLATE,
+            Self::Bell => BELL,
+            Self::Tube => TUBE,
+            Self::Custom(modes) => modes,
+        }
+    }
+}
+
+builder! {
+    #[constructor = "modal_resonator"]
+    #[constructor_doc = "Bank of tuned, decaying resonators modelling the modes of vibration of a struck object. Can be excited by an input signal or by a trigger, which strikes it with an impulse."]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // The frequency of modes with a ratio of 1
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        freq_hz: _,
+        #[default = ModalPreset::Bar]
+        preset: ModalPreset,
+        // Between 0 and 1. At 0.5 the modes have the levels given by the preset. Higher values
+        // emphasize higher modes and lower values emphasize lower modes.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "B"]
+        #[default = 0.5]
+        brightness: f32,
+        // Between 0 and 1. Shortens the decay of all modes, and of higher modes more than lower
+        // modes, like an object being muted by touching it.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 0.0]
+        damping: f32,
+        // Where the object is struck, between 0 (the edge) and 0.5 (the center). Values outside
+        // this range are clamped to it. Modes are quiet when struck near one of their nodes, so
+        // striking the center silences every second mode.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.2]
+        position_01: f32,
+    }
+}
+
+/// At full damping the decay of each mode is divided by this multiplied by its frequency ratio,
+/// plus 1.
+const DAMPING_SCALE: f32 = 10.0;
+
+/// Modes above this fraction of the sample rate are silenced as they can't be represented.
+const MAX_MODE_FREQ_RATIO: f32 = 0.45;
+
+impl<F, B, D, P> Filter for Props<F, B, D, P>
+where
+    F: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = ModalResonator<S, F, B, D, P>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let states = vec![ModeState::default(); self.preset.modes().len()];
+        ModalResonator {
+            props: self,
+            sig,
+            states,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<F, B, D, P> Triggerable for Props<F, B, D, P>
+where
+    F: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn into_sig<T>(self, trig: T) -> impl SigT<Item = Self::Item>
+    where
+        T: SigT<Item = bool>,
+    {
+        let impulse = Sig(trig)
+            .gate_to_trig_rising_edge()
+            .map(|trig| if trig { 1.0 } else { 0.0 });
+        Filter::into_sig(self, impulse.0)
+    }
+}
+
+/// The two most recent outputs of the resonator for a single mode
+#[derive(Debug, Clone, Copy, Default)]
+struct ModeState {
+    y1: f32,
+    y2: f32,
+}
+
+pub struct ModalResonator<S, F, B, D, P>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    props: Props<F, B, D, P>,
+    sig: S,
+    states: Vec<ModeState>,
+    buf: Vec<f32>,
+}
+
+impl<S, F, B, D, P> SigT for ModalResonator<S, F, B, D, P>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    B: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let sig = self.sig.sample(ctx);
+        let freq_hz = self.props.freq_hz.sample(ctx);
+        let brightness = self.props.brightness.sample(ctx);
+        let damping = self.props.damping.sample(ctx);
+        let position_01 = self.props.position_01.sample(ctx);
+        let modes = self.props.preset.modes();
+        for (out, sample, freq_hz, brightness, damping, position_01) in izip! {
+            self.buf.iter_mut(),
+            sig.iter(),
+            freq_hz.iter(),
+            brightness.iter(),
+            damping.iter(),
+            position_01.iter(),
+        } {
+            let brightness = brightness.clamp(0.0, 1.0);
+            let damping = damping.clamp(0.0, 1.0);
+            let position_01 = position_01.clamp(0.0, 0.5);
+            *out = 0.0;
+            for (i, (mode, state)) in
+                modes.iter().zip(self.states.iter_mut()).enumerate()
+            {
+                let mode_freq_hz = freq_hz * mode.freq_ratio;
+                if mode_freq_hz <= 0.0
+                    || mode_freq_hz >= MAX_MODE_FREQ_RATIO * ctx.sample_rate_hz
+                {
+                    *state = ModeState::default();
+                    continue;
+                }
+                let amplitude = mode.amplitude
+                    * mode.freq_ratio.powf((2.0 * brightness) - 1.0)
+                    * ((i as f32) * PI * position_01).cos();
+                let decay_s = mode.decay_s
+                    / (1.0 + (damping * DAMPING_SCALE * mode.freq_ratio));
+                // Two-pole resonator whose impulse response is a sine wave at the mode's
+                // frequency, starting at the mode's amplitude and decaying by 60dB over its decay
+                // time.
+                let omega = 2.0 * PI * mode_freq_hz / ctx.sample_rate_hz;
+                let r = 0.001_f32
+                    .powf(1.0 / (decay_s.max(1e-3) * ctx.sample_rate_hz));
+                let y = (amplitude * omega.sin() * sample)
+                    + (2.0 * r * omega.cos() * state.y1)
+                    - (r * r * state.y2);
+                state.y2 = state.y1;
+                state.y1 = y;
+                *out += y;
+            }
+        }
+        &self.buf
+    }
+}
@@ -0,0 +1,228 @@
+use crate::delay_line::{DelayInterpolation, DelayLine, DelayTap};
+use caw_builder_proc_macros::builder;
+use caw_core::{
+    Buf, Filter, Sig, SigBoxed, SigCtx, SigShared, SigT, Stereo, StereoPair,
+};
+use itertools::izip;
+use std::f32::consts::PI;
+
+/// A single read position of a multi-tap delay.
+pub struct MultiTapDelayTap {
+    time_s: Sig<SigBoxed<f32>>,
+    gain: Sig<SigBoxed<f32>>,
+    pan: Sig<SigBoxed<f32>>,
+    reader: DelayTap,
+    time_s_buf: Vec<f32>,
+    gain_buf: Vec<f32>,
+    pan_buf: Vec<f32>,
+}
+
+builder! {
+    #[constructor = "multi_tap_delay"]
+    #[constructor_doc = "Delay line with any number of read taps, each with its own time, gain and pan"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // ratio of the sum of all taps fed back into the delay line
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 0.0]
+        feedback_ratio: f32,
+        // 0 is dry signal, 1 is all delay
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "M"]
+        #[default = 0.5]
+        mix_01: f32,
+        #[default = DelayInterpolation::CubicHermite]
+        interpolation: DelayInterpolation,
+        // The longest delay time of any tap. Tap times are clamped to this value.
+        #[default = 4.0]
+        max_delay_s: f32,
+        #[default = Vec::new()]
+        taps: Vec<MultiTapDelayTap>,
+    }
+}
+
+impl<F, M> Props<F, M>
+where
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    /// Add a tap which reads the delay line `time_s` seconds in the past. Its output is scaled by
+    /// `gain` and panned by `pan` where -1 is hard left and 1 is hard right.
+    pub fn tap<T, G, P>(mut self, time_s: T, gain: G, pan: P) -> Self
+    where
+        T: SigT<Item = f32> + Send + Sync + 'static,
+        G: SigT<Item = f32> + Send + Sync + 'static,
+        P: SigT<Item = f32> + Send + Sync + 'static,
+    {
+        self.taps.push(MultiTapDelayTap {
+            time_s: Sig(time_s).boxed(),
+            gain: Sig(gain).boxed(),
+            pan: Sig(pan).boxed(),
+            reader: DelayTap::default(),
+            time_s_buf: Vec::new(),
+            gain_buf: Vec::new(),
+            pan_buf: Vec::new(),
+        });
+        self
+    }
+
+    /// Apply the delay to a mono signal, producing a stereo signal with each tap panned
+    /// according to its pan signal.
+    pub fn mono<S>(
+        self,
+        sig: S,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>>
+    where
+        S: SigT<Item = f32>,
+    {
+        let delay: Sig<SigShared<_>> =
+            Sig(self.into_delay(sig, false)).shared();
+        Stereo::new(
+            delay.clone().map(|s: StereoPair<f32>| s.left),
+            delay.map(|s: StereoPair<f32>| s.right),
+        )
+    }
+
+    fn into_delay<S>(mut self, sig: S, mono: bool) -> MultiTapDelay<S, F, M>
+    where
+        S: SigT<Item = f32>,
+    {
+        for tap in &mut self.taps {
+            tap.reader = DelayTap::new(self.interpolation);
+        }
+        MultiTapDelay {
+            props: self,
+            sig,
+            mono,
+            delay_line: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Filtering a signal with a multi-tap delay produces the mono sum of all the taps, ignoring
+/// their pan signals.
+impl<F, M> Filter for Props<F, M>
+where
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = MultiTapDelayMono<S, F, M>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        MultiTapDelayMono {
+            delay: self.into_delay(sig, true),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct MultiTapDelay<S, F, M>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    props: Props<F, M>,
+    sig: S,
+    // Ignore the pan of each tap and output the mono sum of the taps on both channels.
+    mono: bool,
+    // Created on the first frame as the size of the delay line depends on the sample rate.
+    delay_line: Option<DelayLine>,
+    buf: Vec<StereoPair<f32>>,
+}
+
+impl<S, F, M> SigT for MultiTapDelay<S, F, M>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type Item = StereoPair<f32>;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let delay_line = self.delay_line.get_or_insert_with(|| {
+            DelayLine::new_s(self.props.max_delay_s, ctx.sample_rate_hz)
+        });
+        for tap in &mut self.props.taps {
+            tap.time_s.sample(ctx).clone_to_vec(&mut tap.time_s_buf);
+            tap.gain.sample(ctx).clone_to_vec(&mut tap.gain_buf);
+            tap.pan.sample(ctx).clone_to_vec(&mut tap.pan_buf);
+        }
+        let sig = self.sig.sample(ctx);
+        let feedback_ratio = self.props.feedback_ratio.sample(ctx);
+        let mix_01 = self.props.mix_01.sample(ctx);
+        for (i, (sample, feedback_ratio, mix_01)) in izip! {
+            sig.iter(),
+            feedback_ratio.iter(),
+            mix_01.iter(),
+        }
+        .enumerate()
+        {
+            let mut wet = Stereo::new(0.0, 0.0);
+            let mut wet_mono = 0.0;
+            for tap in &mut self.props.taps {
+                // The delay line is read before the current sample is written, so the most
+                // recent sample in the delay line is already one sample old.
+                let delay_samples =
+                    (tap.time_s_buf[i] * ctx.sample_rate_hz) - 1.0;
+                let value = tap.reader.read(delay_line, delay_samples)
+                    * tap.gain_buf[i];
+                // Equal-power panning
+                let angle = (tap.pan_buf[i].clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+                wet.left += value * angle.cos();
+                wet.right += value * angle.sin();
+                wet_mono += value;
+            }
+            delay_line.write(sample + (wet_mono * feedback_ratio));
+            let dry = sample * (1.0 - mix_01);
+            if self.mono {
+                let out = dry + (wet_mono * mix_01);
+                self.buf.push(Stereo::new(out, out));
+            } else {
+                self.buf.push(Stereo::new(
+                    dry + (wet.left * mix_01),
+                    dry + (wet.right * mix_01),
+                ));
+            }
+        }
+        &self.buf
+    }
+}
+
+pub struct MultiTapDelayMono<S, F, M>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    delay: MultiTapDelay<S, F, M>,
+    buf: Vec<f32>,
+}
+
+impl<S, F, M> SigT for MultiTapDelayMono<S, F, M>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        // In mono mode both channels are the same.
+        self.buf
+            .extend(self.delay.sample(ctx).iter().map(|sample| sample.left));
+        &self.buf
+    }
+}
@@ -0,0 +1,116 @@
+use crate::low_level::half_band::{Oversampler, Oversampling};
+use caw_core::{Buf, Filter, Sig, SigCtx, SigT};
+use std::sync::{Arc, RwLock};
+
+/// Run a sub-graph at a multiple of the sample rate of its input signal. This reduces aliasing
+/// in nonlinear processes such as distortion and ladder filters. The closure `f` is passed the
+/// upsampled input signal and returns the sub-graph. The sub-graph is sampled with a context whose
+/// sample rate and number of samples are scaled up by the oversampling factor, and its output is
+/// decimated back to the original rate with half-band filters.
+///
+/// Any signals created outside the closure and used in the sub-graph will also be sampled at the
+/// higher rate, so they must not be shared with parts of the graph outside the sub-graph.
+pub fn oversample<F, T>(oversampling: Oversampling, f: F) -> Props<F>
+where
+    F: FnOnce(Sig<OversampledInput>) -> T,
+    T: SigT<Item = f32>,
+{
+    Props { oversampling, f }
+}
+
+pub struct Props<F> {
+    oversampling: Oversampling,
+    f: F,
+}
+
+impl<F, T> Filter for Props<F>
+where
+    F: FnOnce(Sig<OversampledInput>) -> T,
+    T: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Oversample<S, T>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let input = Arc::new(RwLock::new(Vec::new()));
+        let sub_graph = (self.f)(Sig(OversampledInput {
+            input: Arc::clone(&input),
+            buf: Vec::new(),
+        }));
+        Oversample {
+            sig,
+            sub_graph,
+            factor: self.oversampling.factor(),
+            oversampler: Oversampler::new(self.oversampling),
+            input,
+            sig_buf: Vec::new(),
+            sub_graph_buf: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// The input to an oversampled sub-graph. This signal is only valid when sampled by the
+/// sub-graph.
+pub struct OversampledInput {
+    input: Arc<RwLock<Vec<f32>>>,
+    buf: Vec<f32>,
+}
+
+impl SigT for OversampledInput {
+    type Item = f32;
+
+    fn sample(&mut self, _ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        self.buf.extend_from_slice(&self.input.read().unwrap());
+        &self.buf
+    }
+}
+
+pub struct Oversample<S, T>
+where
+    S: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+{
+    sig: S,
+    sub_graph: T,
+    factor: usize,
+    oversampler: Oversampler,
+    // The upsampled input for the current frame, read by the sub-graph
+    input: Arc<RwLock<Vec<f32>>>,
+    sig_buf: Vec<f32>,
+    sub_graph_buf: Vec<f32>,
+    buf: Vec<f32>,
+}
+
+impl<S, T> SigT for Oversample<S, T>
+where
+    S: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.sig.sample(ctx).clone_to_vec(&mut self.sig_buf);
+        self.oversampler
+            .upsample_block(&self.sig_buf, &mut self.input.write().unwrap());
+        let oversampled_ctx = SigCtx {
+            sample_rate_hz: ctx.sample_rate_hz * self.factor as f32,
+            num_samples: ctx.num_samples * self.factor,
+            ..*ctx
+        };
+        self.sub_graph
+            .sample(&oversampled_ctx)
+            .clone_to_vec(&mut self.sub_graph_buf);
+        self.oversampler
+            .downsample_block(&self.sub_graph_buf, &mut self.buf);
+        &self.buf
+    }
+}
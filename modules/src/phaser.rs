@@ -0,0 +1,184 @@
+use crate::{
+    ChorusLfoOffset, Sine, Waveform,
+    oscillator::{Oscillator, oscillator},
+};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+use std::f32::consts::PI;
+
+builder! {
+    #[constructor = "phaser"]
+    #[constructor_doc = "Mix the signal with a copy passed through a chain of swept allpass filters to produce moving notches"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "R"]
+        #[default = 0.5]
+        lfo_rate_hz: f32,
+        #[generic_with_constraint = "Waveform"]
+        #[generic_name = "W"]
+        #[default = Sine]
+        lfo_waveform: Sine,
+        #[default = ChorusLfoOffset::None]
+        lfo_offset: ChorusLfoOffset,
+        // The LFO phase offset of the right channel when `lfo_offset` interleaves the right
+        // channel.
+        #[default = 0.25]
+        lfo_stereo_offset_01: f32,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "T"]
+        #[default = false]
+        lfo_reset_trig: bool,
+        // The break frequency of the allpass filters at the center of the sweep
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "C"]
+        #[default = 800.0]
+        center_hz: f32,
+        // How far the break frequency sweeps either side of the center
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 2.0]
+        depth_octaves: f32,
+        // ratio of the output of the allpass chain fed back into its input. Negative values
+        // move the notches.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 0.5]
+        feedback_ratio: f32,
+        // 0 is dry signal, 1 is all phase-shifted signal. The notches are deepest at 0.5.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "M"]
+        #[default = 0.5]
+        mix_01: f32,
+        // Each pair of stages adds a notch.
+        #[default = 4]
+        num_stages: usize,
+    }
+}
+
+type Osc<W, R, T> = Oscillator<W, R, f32, f32, T>;
+
+#[derive(Default, Clone, Copy)]
+struct AllpassStage {
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl AllpassStage {
+    fn process(&mut self, sample: f32, coefficient: f32) -> f32 {
+        let output = (coefficient * sample) + self.prev_input
+            - (coefficient * self.prev_output);
+        self.prev_input = sample;
+        self.prev_output = output;
+        output
+    }
+}
+
+pub struct Phaser<S, R, W, T, C, D, F, M>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    C: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    sig: S,
+    lfo: Osc<W, R, T>,
+    center_hz: C,
+    depth_octaves: D,
+    feedback_ratio: F,
+    mix_01: M,
+    stages: Vec<AllpassStage>,
+    prev_output: f32,
+    buf: Vec<f32>,
+}
+
+impl<R, W, T, C, D, F, M> Filter for Props<R, W, T, C, D, F, M>
+where
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    C: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Phaser<S, R, W, T, C, D, F, M>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let lfo = oscillator(self.lfo_waveform, self.lfo_rate_hz)
+            .reset_offset_01(
+                self.lfo_offset.offset_01(self.lfo_stereo_offset_01),
+            )
+            .reset_trig(self.lfo_reset_trig)
+            .build()
+            .0;
+        Phaser {
+            sig,
+            lfo,
+            center_hz: self.center_hz,
+            depth_octaves: self.depth_octaves,
+            feedback_ratio: self.feedback_ratio,
+            mix_01: self.mix_01,
+            stages: vec![AllpassStage::default(); self.num_stages],
+            prev_output: 0.0,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<S, R, W, T, C, D, F, M> SigT for Phaser<S, R, W, T, C, D, F, M>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = f32>,
+    W: Waveform,
+    T: SigT<Item = bool>,
+    C: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    M: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let sig = self.sig.sample(ctx);
+        let lfo = self.lfo.sample(ctx);
+        let center_hz = self.center_hz.sample(ctx);
+        let depth_octaves = self.depth_octaves.sample(ctx);
+        let feedback_ratio = self.feedback_ratio.sample(ctx);
+        let mix_01 = self.mix_01.sample(ctx);
+        for (sample, lfo, center_hz, depth_octaves, feedback_ratio, mix_01) in izip! {
+            sig.iter(),
+            lfo.iter(),
+            center_hz.iter(),
+            depth_octaves.iter(),
+            feedback_ratio.iter(),
+            mix_01.iter(),
+        } {
+            let break_hz = (center_hz * (lfo * depth_octaves).exp2())
+                .clamp(1.0, ctx.sample_rate_hz * 0.49);
+            let tan = (PI * break_hz / ctx.sample_rate_hz).tan();
+            let coefficient = (tan - 1.0) / (tan + 1.0);
+            let mut phased = sample + (self.prev_output * feedback_ratio);
+            for stage in &mut self.stages {
+                phased = stage.process(phased, coefficient);
+            }
+            self.prev_output = phased;
+            self.buf.push((sample * (1.0 - mix_01)) + (phased * mix_01));
+        }
+        &self.buf
+    }
+}
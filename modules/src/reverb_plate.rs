@@ -0,0 +1,547 @@
+// This implementation is based on the plate reverb described in "Effect Design Part 1: Reverberator
+// and Other Filters" by Jon Dattorro: https://ccrma.stanford.edu/~dattorro/EffectDesignPart1.pdf
+// The delay lengths and output taps in the paper are given for a sample rate of 29761Hz and are
+// scaled to the sample rate of the signal.
+
+use crate::delay_line::{DelayInterpolation, DelayLine};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, Sig, SigCtx, SigShared, SigT, Stereo, StereoPair};
+use itertools::izip;
+use std::f32::consts::PI;
+
+builder! {
+    #[constructor = "reverb_plate"]
+    #[constructor_doc = "Plate reverb with a modulated tank, producing a smooth stereo tail"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // Delay before the input reaches the reverb
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.01]
+        pre_delay_s: f32,
+        // Time taken for the tail to decay by 60dB
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DC"]
+        #[default = 2.0]
+        decay_s: f32,
+        // removes high end of the tail, so higher frequencies decay faster
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DA"]
+        #[default = 0.3]
+        damping: f32,
+        // How quickly echos are smeared into a dense tail. Low values give distinct early echos.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "DF"]
+        #[default = 0.75]
+        diffusion_01: f32,
+        // How far the delays inside the tank are swept by the modulation, which breaks up the
+        // metallic resonances of the tail
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "MD"]
+        #[default = 0.0005]
+        mod_depth_s: f32,
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "MR"]
+        #[default = 1.0]
+        mod_rate_hz: f32,
+        // 0 is mono, 1 is full stereo
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "W"]
+        #[default = 1.0]
+        width_01: f32,
+        // While true, the input is ignored and the current tail sustains indefinitely
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "FR"]
+        #[default = false]
+        freeze: bool,
+        // 0 is dry signal, 1 is all reverb
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "M"]
+        #[default = 0.5]
+        mix_01: f32,
+    }
+}
+
+impl<P, DC, DA, DF, MD, MR, W, FR, M> Props<P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    /// Apply the reverb to a mono signal, producing a stereo signal.
+    pub fn mono<S>(
+        self,
+        sig: S,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>>
+    where
+        S: SigT<Item = f32>,
+    {
+        let reverb: Sig<SigShared<_>> = Sig(self.into_reverb(sig)).shared();
+        Stereo::new(
+            reverb.clone().map(|s: StereoPair<f32>| s.left),
+            reverb.map(|s: StereoPair<f32>| s.right),
+        )
+    }
+
+    fn into_reverb<S>(
+        self,
+        sig: S,
+    ) -> ReverbPlate<S, P, DC, DA, DF, MD, MR, W, FR, M>
+    where
+        S: SigT<Item = f32>,
+    {
+        ReverbPlate {
+            props: self,
+            sig,
+            state: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Filtering a signal with a plate reverb produces the mono sum of both channels of the reverb.
+impl<P, DC, DA, DF, MD, MR, W, FR, M> Filter
+    for Props<P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = ReverbPlateMono<S, P, DC, DA, DF, MD, MR, W, FR, M>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        ReverbPlateMono {
+            reverb: self.into_reverb(sig),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// The sample rate at which the delay lengths in the paper are specified.
+const REFERENCE_SAMPLE_RATE_HZ: f32 = 29761.0;
+
+const MAX_PRE_DELAY_S: f32 = 1.0;
+const MAX_MOD_DEPTH_S: f32 = 0.005;
+
+const INPUT_DIFFUSER_LENGTHS: [usize; 4] = [142, 107, 379, 277];
+
+/// Coefficients of the allpass filters at full diffusion
+const INPUT_DIFFUSION_1: f32 = 0.75;
+const INPUT_DIFFUSION_2: f32 = 0.625;
+const DECAY_DIFFUSION_1: f32 = 0.7;
+const DECAY_DIFFUSION_2: f32 = 0.5;
+
+struct TankHalfLengths {
+    modulated_allpass: usize,
+    delay_1: usize,
+    allpass: usize,
+    delay_2: usize,
+}
+
+const TANK_LEFT_LENGTHS: TankHalfLengths = TankHalfLengths {
+    modulated_allpass: 672,
+    delay_1: 4453,
+    allpass: 1800,
+    delay_2: 3720,
+};
+
+const TANK_RIGHT_LENGTHS: TankHalfLengths = TankHalfLengths {
+    modulated_allpass: 908,
+    delay_1: 4217,
+    allpass: 2656,
+    delay_2: 3163,
+};
+
+/// Offsets into the tank from which an output channel is read. The first four taps are read from
+/// one half of the tank and the remaining three from the other half.
+type OutputTaps = [usize; 7];
+
+/// Taps for the left output, the first four of which are read from the right half of the tank.
+const OUTPUT_TAPS_LEFT: OutputTaps = [266, 2974, 1913, 1996, 1990, 187, 1066];
+
+/// Taps for the right output, the first four of which are read from the left half of the tank.
+const OUTPUT_TAPS_RIGHT: OutputTaps = [353, 3627, 1228, 2673, 2111, 335, 121];
+
+const OUTPUT_GAIN: f32 = 0.6;
+
+/// Reads a delay line of a fixed length, returning the sample leaving the delay line.
+fn delay(delay_line: &mut DelayLine, length: usize, sample: f32) -> f32 {
+    // The delay line is read before the current sample is written, so the most recent sample in
+    // the delay line is already one sample old.
+    let output = delay_line.get(length - 1);
+    delay_line.write(sample);
+    output
+}
+
+struct Allpass {
+    delay_line: DelayLine,
+    length: usize,
+}
+
+impl Allpass {
+    fn new(length: usize, max_excursion: usize) -> Self {
+        Self {
+            delay_line: DelayLine::new(length + max_excursion),
+            length,
+        }
+    }
+
+    fn process(&mut self, sample: f32, coefficient: f32) -> f32 {
+        let delayed = self.delay_line.get(self.length - 1);
+        self.process_delayed(sample, delayed, coefficient)
+    }
+
+    fn process_modulated(
+        &mut self,
+        sample: f32,
+        coefficient: f32,
+        excursion: f32,
+    ) -> f32 {
+        let delayed = self.delay_line.read(
+            self.length as f32 + excursion - 1.0,
+            DelayInterpolation::CubicHermite,
+        );
+        self.process_delayed(sample, delayed, coefficient)
+    }
+
+    fn process_delayed(
+        &mut self,
+        sample: f32,
+        delayed: f32,
+        coefficient: f32,
+    ) -> f32 {
+        let to_delay = sample + (coefficient * delayed);
+        self.delay_line.write(to_delay);
+        delayed - (coefficient * to_delay)
+    }
+}
+
+struct TankHalf {
+    modulated_allpass: Allpass,
+    delay_1: DelayLine,
+    delay_1_length: usize,
+    damping_state: f32,
+    allpass: Allpass,
+    delay_2: DelayLine,
+    delay_2_length: usize,
+    output: f32,
+}
+
+impl TankHalf {
+    fn new(
+        lengths: &TankHalfLengths,
+        scale: f32,
+        max_excursion: usize,
+    ) -> Self {
+        let delay_1_length = scaled_length(lengths.delay_1, scale);
+        let delay_2_length = scaled_length(lengths.delay_2, scale);
+        Self {
+            modulated_allpass: Allpass::new(
+                scaled_length(lengths.modulated_allpass, scale),
+                max_excursion,
+            ),
+            delay_1: DelayLine::new(delay_1_length),
+            delay_1_length,
+            damping_state: 0.0,
+            allpass: Allpass::new(scaled_length(lengths.allpass, scale), 0),
+            delay_2: DelayLine::new(delay_2_length),
+            delay_2_length,
+            output: 0.0,
+        }
+    }
+
+    /// Number of samples taken for a signal to pass through this half of the tank
+    fn length(&self) -> usize {
+        self.modulated_allpass.length
+            + self.delay_1_length
+            + self.allpass.length
+            + self.delay_2_length
+    }
+
+    fn process(
+        &mut self,
+        sample: f32,
+        excursion: f32,
+        diffusion_01: f32,
+        damping: f32,
+        decay: f32,
+    ) {
+        let sample = self.modulated_allpass.process_modulated(
+            sample,
+            -DECAY_DIFFUSION_1 * diffusion_01,
+            excursion,
+        );
+        let sample = delay(&mut self.delay_1, self.delay_1_length, sample);
+        self.damping_state =
+            (sample * (1.0 - damping)) + (self.damping_state * damping);
+        let sample = self.allpass.process(
+            self.damping_state * decay,
+            DECAY_DIFFUSION_2 * diffusion_01,
+        );
+        self.output =
+            delay(&mut self.delay_2, self.delay_2_length, sample) * decay;
+    }
+}
+
+fn scaled_length(length: usize, scale: f32) -> usize {
+    ((length as f32 * scale).round() as usize).max(1)
+}
+
+/// Combine taps from both halves of the tank into a single output channel.
+fn tank_output(main: &TankHalf, other: &TankHalf, taps: &OutputTaps) -> f32 {
+    let [t0, t1, t2, t3, t4, t5, t6] = *taps;
+    OUTPUT_GAIN
+        * (main.delay_1.get(t0) + main.delay_1.get(t1)
+            - main.allpass.delay_line.get(t2)
+            + main.delay_2.get(t3)
+            - other.delay_1.get(t4)
+            - other.allpass.delay_line.get(t5)
+            - other.delay_2.get(t6))
+}
+
+struct State {
+    pre_delay: DelayLine,
+    input_diffusers: [Allpass; 4],
+    tank: StereoPair<TankHalf>,
+    output_taps: StereoPair<OutputTaps>,
+    // average time taken for a signal to pass through one half of the tank
+    tank_half_s: f32,
+    max_excursion: f32,
+    mod_phase_01: f32,
+}
+
+impl State {
+    fn new(sample_rate_hz: f32) -> Self {
+        let scale = sample_rate_hz / REFERENCE_SAMPLE_RATE_HZ;
+        let max_excursion = (MAX_MOD_DEPTH_S * sample_rate_hz).ceil() as usize;
+        let tank = Stereo::new(
+            TankHalf::new(&TANK_LEFT_LENGTHS, scale, max_excursion),
+            TankHalf::new(&TANK_RIGHT_LENGTHS, scale, max_excursion),
+        );
+        let tank_half_s = (tank.left.length() + tank.right.length()) as f32
+            / (2.0 * sample_rate_hz);
+        Self {
+            pre_delay: DelayLine::new_s(MAX_PRE_DELAY_S, sample_rate_hz),
+            input_diffusers: INPUT_DIFFUSER_LENGTHS
+                .map(|length| Allpass::new(scaled_length(length, scale), 0)),
+            tank,
+            output_taps: Stereo::new(
+                OUTPUT_TAPS_LEFT.map(|tap| scaled_length(tap, scale)),
+                OUTPUT_TAPS_RIGHT.map(|tap| scaled_length(tap, scale)),
+            ),
+            tank_half_s,
+            max_excursion: max_excursion as f32,
+            mod_phase_01: 0.0,
+        }
+    }
+}
+
+pub struct ReverbPlate<S, P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    props: Props<P, DC, DA, DF, MD, MR, W, FR, M>,
+    sig: S,
+    // Created on the first frame as the size of the delays depends on the sample rate.
+    state: Option<State>,
+    buf: Vec<StereoPair<f32>>,
+}
+
+impl<S, P, DC, DA, DF, MD, MR, W, FR, M> SigT
+    for ReverbPlate<S, P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    type Item = StereoPair<f32>;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let state = self
+            .state
+            .get_or_insert_with(|| State::new(ctx.sample_rate_hz));
+        let sig = self.sig.sample(ctx);
+        let pre_delay_s = self.props.pre_delay_s.sample(ctx);
+        let decay_s = self.props.decay_s.sample(ctx);
+        let damping = self.props.damping.sample(ctx);
+        let diffusion_01 = self.props.diffusion_01.sample(ctx);
+        let mod_depth_s = self.props.mod_depth_s.sample(ctx);
+        let mod_rate_hz = self.props.mod_rate_hz.sample(ctx);
+        let width_01 = self.props.width_01.sample(ctx);
+        let freeze = self.props.freeze.sample(ctx);
+        let mix_01 = self.props.mix_01.sample(ctx);
+        for (
+            sample,
+            pre_delay_s,
+            decay_s,
+            damping,
+            diffusion_01,
+            mod_depth_s,
+            mod_rate_hz,
+            width_01,
+            freeze,
+            mix_01,
+        ) in izip! {
+            sig.iter(),
+            pre_delay_s.iter(),
+            decay_s.iter(),
+            damping.iter(),
+            diffusion_01.iter(),
+            mod_depth_s.iter(),
+            mod_rate_hz.iter(),
+            width_01.iter(),
+            freeze.iter(),
+            mix_01.iter(),
+        } {
+            let diffusion_01 = diffusion_01.clamp(0.0, 1.0);
+            // The decay is applied twice in each half of the tank, and the tail should decay by
+            // 60dB after `decay_s` seconds.
+            let (input, decay, damping) = if freeze {
+                (0.0, 1.0, 0.0)
+            } else {
+                let decay = 0.001f32
+                    .powf(state.tank_half_s / (2.0 * decay_s.max(0.001)));
+                (sample, decay, damping.clamp(0.0, 1.0))
+            };
+            state.pre_delay.write(input);
+            let mut diffused = state.pre_delay.read(
+                pre_delay_s * ctx.sample_rate_hz,
+                DelayInterpolation::Linear,
+            );
+            for (i, diffuser) in state.input_diffusers.iter_mut().enumerate() {
+                let coefficient = if i < 2 {
+                    INPUT_DIFFUSION_1
+                } else {
+                    INPUT_DIFFUSION_2
+                };
+                diffused =
+                    diffuser.process(diffused, coefficient * diffusion_01);
+            }
+            let excursion = (mod_depth_s * ctx.sample_rate_hz)
+                .clamp(0.0, state.max_excursion);
+            let mod_angle = state.mod_phase_01 * 2.0 * PI;
+            state.mod_phase_01 = (state.mod_phase_01
+                + (mod_rate_hz / ctx.sample_rate_hz))
+                .rem_euclid(1.0);
+            // Each half of the tank is fed by the output of the other half.
+            let feedback =
+                Stereo::new(state.tank.right.output, state.tank.left.output);
+            state.tank.left.process(
+                diffused + feedback.left,
+                excursion * mod_angle.sin(),
+                diffusion_01,
+                damping,
+                decay,
+            );
+            state.tank.right.process(
+                diffused + feedback.right,
+                excursion * mod_angle.cos(),
+                diffusion_01,
+                damping,
+                decay,
+            );
+            let wet = Stereo::new(
+                tank_output(
+                    &state.tank.right,
+                    &state.tank.left,
+                    &state.output_taps.left,
+                ),
+                tank_output(
+                    &state.tank.left,
+                    &state.tank.right,
+                    &state.output_taps.right,
+                ),
+            );
+            let mid = (wet.left + wet.right) / 2.0;
+            let side = (wet.left - wet.right) * width_01.clamp(0.0, 1.0) / 2.0;
+            let dry = sample * (1.0 - mix_01);
+            self.buf.push(Stereo::new(
+                dry + ((mid + side) * mix_01),
+                dry + ((mid - side) * mix_01),
+            ));
+        }
+        &self.buf
+    }
+}
+
+pub struct ReverbPlateMono<S, P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    reverb: ReverbPlate<S, P, DC, DA, DF, MD, MR, W, FR, M>,
+    buf: Vec<f32>,
+}
+
+impl<S, P, DC, DA, DF, MD, MR, W, FR, M> SigT
+    for ReverbPlateMono<S, P, DC, DA, DF, MD, MR, W, FR, M>
+where
+    S: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+    DC: SigT<Item = f32>,
+    DA: SigT<Item = f32>,
+    DF: SigT<Item = f32>,
+    MD: SigT<Item = f32>,
+    MR: SigT<Item = f32>,
+    W: SigT<Item = f32>,
+    FR: SigT<Item = bool>,
+    M: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        self.buf.extend(
+            self.reverb
+                .sample(ctx)
+                .iter()
+                .map(|sample| (sample.left + sample.right) / 2.0),
+        );
+        &self.buf
+    }
+}
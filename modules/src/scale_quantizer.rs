@@ -0,0 +1,162 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, Sig, SigCtx, SigT};
+use caw_keyboard::{Note, Scale};
+use itertools::izip;
+
+/// The unit of the pitch signal being quantized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PitchUnit {
+    /// Semitones, where 0 is the lowest midi note and 69 is A4. Fractional values are allowed.
+    #[default]
+    MidiIndex,
+    FreqHz,
+}
+
+impl PitchUnit {
+    fn to_midi_index(self, value: f32) -> f32 {
+        match self {
+            Self::MidiIndex => value,
+            Self::FreqHz => {
+                69.0 + (12.0 * (value.max(f32::MIN_POSITIVE) / 440.0).log2())
+            }
+        }
+    }
+
+    /// The pitch of a note in this unit.
+    pub fn note_to_pitch(self, note: Note) -> f32 {
+        match self {
+            Self::MidiIndex => note.to_midi_index() as f32,
+            Self::FreqHz => note.freq_hz(),
+        }
+    }
+}
+
+builder! {
+    #[constructor = "scale_quantizer"]
+    #[constructor_doc = "Snap a pitch signal to the nearest note of a musical scale"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = Scale>"]
+        #[generic_name = "C"]
+        scale: _,
+        // How far (in semitones) the input must move past the midpoint between the current note
+        // and a neighbouring note before the output changes. This prevents noisy or slowly moving
+        // inputs from flickering between adjacent notes.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "H"]
+        #[default = 0.0]
+        hysteresis: f32,
+        // The output is only updated on samples where this is true. Pass a trigger to turn the
+        // quantizer into a quantizing sample and hold.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "U"]
+        #[default = true]
+        update: bool,
+        #[default = PitchUnit::MidiIndex]
+        unit: PitchUnit,
+    }
+}
+
+impl<C, H, U> Props<C, H, U>
+where
+    C: SigT<Item = Scale>,
+    H: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+{
+    /// Quantize `sig`, returning the snapped pitch in the same unit as `sig` rather than as a
+    /// note.
+    pub fn pitch<S>(self, sig: S) -> Sig<impl SigT<Item = f32>>
+    where
+        S: SigT<Item = f32>,
+    {
+        let unit = self.unit;
+        Sig(self.into_sig(sig)).map(move |note| unit.note_to_pitch(note))
+    }
+}
+
+impl<C, H, U> Filter for Props<C, H, U>
+where
+    C: SigT<Item = Scale>,
+    H: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = ScaleQuantizer<S, C, H, U>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        ScaleQuantizer {
+            props: self,
+            sig,
+            note: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct ScaleQuantizer<S, C, H, U>
+where
+    S: SigT<Item = f32>,
+    C: SigT<Item = Scale>,
+    H: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+{
+    props: Props<C, H, U>,
+    sig: S,
+    note: Option<Note>,
+    buf: Vec<Note>,
+}
+
+impl<S, C, H, U> SigT for ScaleQuantizer<S, C, H, U>
+where
+    S: SigT<Item = f32>,
+    C: SigT<Item = Scale>,
+    H: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+{
+    type Item = Note;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let sig = self.sig.sample(ctx);
+        let scale = self.props.scale.sample(ctx);
+        let hysteresis = self.props.hysteresis.sample(ctx);
+        let update = self.props.update.sample(ctx);
+        for (sample, scale, hysteresis, update) in izip! {
+            sig.iter(),
+            scale.iter(),
+            hysteresis.iter(),
+            update.iter(),
+        } {
+            if update || self.note.is_none() {
+                let midi_index = self.props.unit.to_midi_index(sample);
+                let nearest = scale.nearest_note(midi_index);
+                self.note = Some(match self.note {
+                    Some(current) if scale.contains(current) => {
+                        let distance = |note: Note| {
+                            (note.to_midi_index() as f32 - midi_index).abs()
+                        };
+                        // Moving past the midpoint between two notes by some amount changes
+                        // the difference in their distances by twice that amount.
+                        if distance(current) - distance(nearest)
+                            > 2.0 * hysteresis
+                        {
+                            nearest
+                        } else {
+                            current
+                        }
+                    }
+                    _ => nearest,
+                });
+            }
+            self.buf.push(self.note.unwrap_or_default());
+        }
+        &self.buf
+    }
+}
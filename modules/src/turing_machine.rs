@@ -0,0 +1,120 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, GateToTrigRisingEdge, Sig, SigCtx, SigT};
+use itertools::izip;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+builder! {
+    #[constructor = "turing_machine"]
+    #[constructor_doc = "Shift register sequencer which slowly mutates a looping random sequence"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // The probability that the bit shifted out of the end of the register is fed back
+        // unchanged. 1 repeats the sequence forever, 0.5 produces a new random sequence every
+        // step, and 0 repeats a sequence of twice the length with the second half inverted.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "L"]
+        #[default = 0.9]
+        lock_01: f32,
+        // The number of bits in the loop (between 1 and 32)
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "N"]
+        #[default = 16]
+        length: u32,
+    }
+}
+
+impl<L, N> Props<L, N>
+where
+    L: SigT<Item = f32>,
+    N: SigT<Item = u32>,
+{
+    /// A gate which is high on clock pulses when the newest bit of the register is 1.
+    pub fn gate<S>(self, clock: S) -> Sig<impl SigT<Item = bool>>
+    where
+        S: SigT<Item = bool>,
+    {
+        let mut turing_machine = self.into_sig(clock);
+        Sig::from_buf_fn(move |ctx, buf: &mut Vec<bool>| {
+            buf.clear();
+            turing_machine.sample(ctx);
+            buf.extend_from_slice(&turing_machine.gate_buf);
+        })
+    }
+}
+
+impl<L, N> Filter for Props<L, N>
+where
+    L: SigT<Item = f32>,
+    N: SigT<Item = u32>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = TuringMachine<S, L, N>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let mut rng = StdRng::from_os_rng();
+        TuringMachine {
+            register: rng.random(),
+            rng,
+            props: self,
+            clock: Sig(sig).gate_to_trig_rising_edge().0,
+            buf: Vec::new(),
+            gate_buf: Vec::new(),
+        }
+    }
+}
+
+pub struct TuringMachine<S, L, N>
+where
+    S: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    N: SigT<Item = u32>,
+{
+    props: Props<L, N>,
+    clock: GateToTrigRisingEdge<S>,
+    register: u32,
+    rng: StdRng,
+    buf: Vec<f32>,
+    gate_buf: Vec<bool>,
+}
+
+impl<S, L, N> SigT for TuringMachine<S, L, N>
+where
+    S: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    N: SigT<Item = u32>,
+{
+    type Item = f32;
+
+    /// Yields the lowest 8 bits of the register scaled to between 0 and 1.
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        self.gate_buf.clear();
+        let clock = self.clock.sample(ctx);
+        let lock_01 = self.props.lock_01.sample(ctx);
+        let length = self.props.length.sample(ctx);
+        for (clock, lock_01, length) in izip! {
+            clock.iter(),
+            lock_01.iter(),
+            length.iter(),
+        } {
+            if clock {
+                let length = length.clamp(1, 32);
+                let mut bit = (self.register >> (length - 1)) & 1;
+                if self.rng.random::<f32>() >= lock_01 {
+                    bit ^= 1;
+                }
+                self.register = (self.register << 1) | bit;
+            }
+            self.gate_buf.push(clock && (self.register & 1 != 0));
+            self.buf.push((self.register & 0xFF) as f32 / 255.0);
+        }
+        &self.buf
+    }
+}
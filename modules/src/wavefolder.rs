@@ -0,0 +1,135 @@
+use crate::low_level::half_band::{Oversampler, Oversampling};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use itertools::izip;
+use std::f32::consts::PI;
+
+builder! {
+    #[constructor = "wavefolder"]
+    #[constructor_doc = "Multi-stage wavefolder which reflects the peaks of a signal back on themselves, adding bright harmonics"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // At 0 a signal between -1 and 1 is only gently saturated. Each increase of 2 adds
+        // another fold to its peaks.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 1.0]
+        fold: f32,
+        // Offset added to the signal before folding, between -1 and 1. Values other than 0 fold
+        // the two halves of the signal differently, adding even harmonics.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "Y"]
+        #[default = 0.0]
+        symmetry: f32,
+        // The number of folding stages. Past the last stage the signal is softly clipped rather
+        // than folded.
+        #[default = 5]
+        num_stages: usize,
+        #[default = Oversampling::X4]
+        oversampling: Oversampling,
+    }
+}
+
+/// How rounded the corners of each fold are, like the soft knees of the transistor stages in an
+/// analog folder
+const SMOOTHNESS: f32 = 0.1;
+
+const DC_BLOCK_CUTOFF_HZ: f32 = 10.0;
+
+/// Like `max(x, 0)` but with a rounded corner
+fn soft_rectify(x: f32) -> f32 {
+    x.max(0.0) + (SMOOTHNESS * (-x.abs() / SMOOTHNESS).exp().ln_1p())
+}
+
+/// Each stage reflects the part of the signal beyond its threshold back towards zero. Stage `k`
+/// starts folding at `2k + 1` so that a signal which has already been folded by the previous
+/// stages is folded again each time it passes -1 or 1.
+fn fold(x: f32, num_stages: usize) -> f32 {
+    let mut y = x;
+    let mut sign = -2.0;
+    for k in 0..num_stages {
+        let threshold = ((2 * k) + 1) as f32;
+        y += sign * x.signum() * soft_rectify(x.abs() - threshold);
+        sign = -sign;
+    }
+    const OUTPUT_SATURATION: f32 = 1.5;
+    (y * OUTPUT_SATURATION).tanh() / OUTPUT_SATURATION.tanh()
+}
+
+impl<F, Y> Filter for Props<F, Y>
+where
+    F: SigT<Item = f32>,
+    Y: SigT<Item = f32>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = Wavefolder<S, F, Y>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Wavefolder {
+            oversampler: Oversampler::new(self.oversampling),
+            props: self,
+            sig,
+            dc_block_input: 0.0,
+            dc_block_output: 0.0,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Wavefolder<S, F, Y>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    Y: SigT<Item = f32>,
+{
+    props: Props<F, Y>,
+    sig: S,
+    oversampler: Oversampler,
+    dc_block_input: f32,
+    dc_block_output: f32,
+    buf: Vec<f32>,
+}
+
+impl<S, F, Y> SigT for Wavefolder<S, F, Y>
+where
+    S: SigT<Item = f32>,
+    F: SigT<Item = f32>,
+    Y: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let sig = self.sig.sample(ctx);
+        let fold_amount = self.props.fold.sample(ctx);
+        let symmetry = self.props.symmetry.sample(ctx);
+        let num_stages = self.props.num_stages;
+        for (out, sample, fold_amount, symmetry) in izip! {
+            self.buf.iter_mut(),
+            sig.iter(),
+            fold_amount.iter(),
+            symmetry.iter(),
+        } {
+            let gain = 1.0 + fold_amount.max(0.0);
+            let symmetry = symmetry.clamp(-1.0, 1.0);
+            let folded = self
+                .oversampler
+                .process(sample, |x| fold((x * gain) + symmetry, num_stages));
+            // Remove the DC offset introduced by the symmetry control.
+            let dc_block_coefficient =
+                1.0 - (2.0 * PI * DC_BLOCK_CUTOFF_HZ / ctx.sample_rate_hz);
+            self.dc_block_output = folded - self.dc_block_input
+                + (dc_block_coefficient * self.dc_block_output);
+            self.dc_block_input = folded;
+            *out = self.dc_block_output;
+        }
+        &self.buf
+    }
+}
@@ -0,0 +1,262 @@
+use crate::delay_line::{DelayInterpolation, DelayLine, DelayTap};
+pub use crate::low_level::physical_model::Excitation;
+use crate::low_level::physical_model::{Exciter, OnePoleLowPass};
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Sig, SigCtx, SigT, Triggerable};
+use itertools::izip;
+use std::f32::consts::PI;
+
+/// The kind of resonator modelled by a waveguide, which determines how waves are reflected at
+/// either end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaveguideMedium {
+    /// A string fixed at both ends. Waves are inverted at both ends, so the string has all
+    /// harmonics of its fundamental.
+    #[default]
+    String,
+    /// A tube closed at one end and open at the other, like a clarinet. Waves are only inverted
+    /// at the open end so they must travel the length of the tube four times per period, and
+    /// only odd harmonics are present.
+    Tube,
+}
+
+builder! {
+    #[constructor = "waveguide"]
+    #[constructor_doc = "Digital waveguide model of a string or tube, made from a pair of delay lines carrying waves in opposite directions. Excited each time its trigger signal goes high."]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        freq_hz: _,
+        #[default = WaveguideMedium::String]
+        medium: WaveguideMedium,
+        #[default = Excitation::NoiseBurst]
+        excitation: Excitation,
+        // Only used with `Excitation::External`
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "E"]
+        #[default = 0.0]
+        excitation_signal: f32,
+        // Time taken for low frequencies to decay by 60dB. Damping shortens the decay of high
+        // harmonics, and of the fundamental of high notes.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "T"]
+        #[default = 2.0]
+        decay_s: f32,
+        // Between 0 and 1. Higher values make high harmonics die away faster than the
+        // fundamental, so the sound is darker.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "D"]
+        #[default = 0.2]
+        damping: f32,
+        // Where the excitation is applied, between 0 (the nut of the string or the closed end of
+        // the tube) and 1 (the bridge or the open end). The sound is taken from the bridge or
+        // open end.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "P"]
+        #[default = 0.2]
+        excitation_position_01: f32,
+    }
+}
+
+/// The lowest frequency the waveguide can be tuned to. Determines the length of its delay lines.
+const MIN_FREQ_HZ: f32 = 20.0;
+
+/// Damping of 1 maps to this loop filter coefficient.
+const MAX_DAMPING_COEFFICIENT: f32 = 0.95;
+
+/// The loss applied each time waves travel back and forth along the waveguide is chosen so that
+/// low frequencies decay by this ratio over the decay time.
+const DECAY_RATIO: f32 = 0.001;
+
+/// The shortest delay in samples that a segment can be read at. This limits how high the
+/// waveguide can be tuned: a string stays in tune up to about a sixth of the sample rate, and a
+/// tube up to about a twelfth.
+const MIN_SEGMENT_SAMPLES: f32 = 1.5;
+
+/// A section of a waveguide carrying waves in one direction.
+struct Segment {
+    delay_line: DelayLine,
+    tap: DelayTap,
+}
+
+impl Segment {
+    fn new(max_delay_s: f32, sample_rate_hz: f32) -> Self {
+        Self {
+            delay_line: DelayLine::new_s(max_delay_s, sample_rate_hz),
+            tap: DelayTap::new(DelayInterpolation::Allpass),
+        }
+    }
+
+    /// The sample leaving the segment. Must be called before `write` on each sample.
+    fn read(&mut self, delay_samples: f32, omega: f32) -> f32 {
+        // Writing a sample delays it by 1 sample by the time it's read.
+        self.tap
+            .read_tuned(&self.delay_line, delay_samples - 1.0, omega)
+    }
+
+    fn write(&mut self, sample: f32) {
+        self.delay_line.write(sample);
+    }
+}
+
+/// The two pairs of segments either side of the excitation point
+struct Segments {
+    nut_to_excitation: Segment,
+    excitation_to_bridge: Segment,
+    bridge_to_excitation: Segment,
+    excitation_to_nut: Segment,
+}
+
+impl Segments {
+    fn new(sample_rate_hz: f32) -> Self {
+        // Waves travel the length of the waveguide twice per period of a string, so each
+        // segment is at most half of the longest period long.
+        let max_delay_s = 0.5 / MIN_FREQ_HZ;
+        Self {
+            nut_to_excitation: Segment::new(max_delay_s, sample_rate_hz),
+            excitation_to_bridge: Segment::new(max_delay_s, sample_rate_hz),
+            bridge_to_excitation: Segment::new(max_delay_s, sample_rate_hz),
+            excitation_to_nut: Segment::new(max_delay_s, sample_rate_hz),
+        }
+    }
+}
+
+impl<F, E, T, D, P> Triggerable for Props<F, E, T, D, P>
+where
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn into_sig<G>(self, trig: G) -> impl SigT<Item = Self::Item>
+    where
+        G: SigT<Item = bool>,
+    {
+        Waveguide {
+            props: self,
+            trig: Sig(trig).gate_to_trig_rising_edge().0,
+            exciter: Exciter::new(),
+            segments: None,
+            low_pass: OnePoleLowPass::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Waveguide<G, F, E, T, D, P>
+where
+    G: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    props: Props<F, E, T, D, P>,
+    trig: G,
+    exciter: Exciter,
+    segments: Option<Segments>,
+    // Loss filter applied where waves reflect from the bridge or open end
+    low_pass: OnePoleLowPass,
+    buf: Vec<f32>,
+}
+
+impl<G, F, E, T, D, P> SigT for Waveguide<G, F, E, T, D, P>
+where
+    G: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    E: SigT<Item = f32>,
+    T: SigT<Item = f32>,
+    D: SigT<Item = f32>,
+    P: SigT<Item = f32>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize(ctx.num_samples, 0.0);
+        let trig = self.trig.sample(ctx);
+        let freq_hz = self.props.freq_hz.sample(ctx);
+        let excitation_signal = self.props.excitation_signal.sample(ctx);
+        let decay_s = self.props.decay_s.sample(ctx);
+        let damping = self.props.damping.sample(ctx);
+        let excitation_position_01 =
+            self.props.excitation_position_01.sample(ctx);
+        let segments = self
+            .segments
+            .get_or_insert_with(|| Segments::new(ctx.sample_rate_hz));
+        let (trips_per_period, nut_reflection) = match self.props.medium {
+            WaveguideMedium::String => (2.0, -1.0),
+            WaveguideMedium::Tube => (4.0, 1.0),
+        };
+        for (
+            out,
+            trig,
+            freq_hz,
+            excitation_signal,
+            decay_s,
+            damping,
+            excitation_position_01,
+        ) in izip! {
+            self.buf.iter_mut(),
+            trig.iter(),
+            freq_hz.iter(),
+            excitation_signal.iter(),
+            decay_s.iter(),
+            damping.iter(),
+            excitation_position_01.iter(),
+        } {
+            let freq_hz = freq_hz.clamp(MIN_FREQ_HZ, ctx.sample_rate_hz / 4.0);
+            let period_samples = ctx.sample_rate_hz / freq_hz;
+            if trig {
+                self.exciter.trigger(period_samples.round() as usize);
+            }
+            let excitation = self
+                .exciter
+                .process(self.props.excitation, excitation_signal);
+            let coefficient = damping.clamp(0.0, 1.0) * MAX_DAMPING_COEFFICIENT;
+            let omega = 2.0 * PI * freq_hz / ctx.sample_rate_hz;
+            // The length of the waveguide in samples, shortened to account for the delay of the
+            // loss filter so the fundamental stays in tune.
+            let length_samples = ((2.0 * period_samples / trips_per_period)
+                - OnePoleLowPass::phase_delay(coefficient, omega))
+                / 2.0;
+            let round_trip_s =
+                2.0 * period_samples / (trips_per_period * ctx.sample_rate_hz);
+            let gain = DECAY_RATIO.powf(round_trip_s / decay_s.max(1e-3));
+            // Keep the segments long enough to be read at the delays required to stay in tune.
+            let min_position = (MIN_SEGMENT_SAMPLES / length_samples).min(0.5);
+            let position =
+                excitation_position_01.clamp(min_position, 1.0 - min_position);
+            let nut_delay_samples = position * length_samples;
+            let bridge_delay_samples = (1.0 - position) * length_samples;
+            let arriving_at_excitation_from_nut =
+                segments.nut_to_excitation.read(nut_delay_samples, omega);
+            let arriving_at_bridge = segments
+                .excitation_to_bridge
+                .read(bridge_delay_samples, omega);
+            let arriving_at_excitation_from_bridge = segments
+                .bridge_to_excitation
+                .read(bridge_delay_samples, omega);
+            let arriving_at_nut =
+                segments.excitation_to_nut.read(nut_delay_samples, omega);
+            let reflected_from_bridge =
+                -gain * self.low_pass.process(arriving_at_bridge, coefficient);
+            segments
+                .nut_to_excitation
+                .write(nut_reflection * arriving_at_nut);
+            segments
+                .excitation_to_bridge
+                .write(arriving_at_excitation_from_nut + excitation);
+            segments.bridge_to_excitation.write(reflected_from_bridge);
+            segments
+                .excitation_to_nut
+                .write(arriving_at_excitation_from_bridge + excitation);
+            *out = arriving_at_bridge;
+        }
+        &self.buf
+    }
+}
@@ -0,0 +1,1023 @@
+use crate::transport::TimeSignature;
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, Sig, SigCtx, SigShared, SigT, Stereo, StereoPair};
+use itertools::izip;
+use std::{
+    collections::VecDeque,
+    mem,
+    ops::{Add, Mul},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+/// A type of sample which can be recorded by the audio looper.
+pub trait LoopSample:
+    Copy + Default + Add<Output = Self> + Mul<f32, Output = Self> + Send + 'static
+{
+    fn write_wav(
+        path: impl AsRef<Path>,
+        sample_rate_hz: u32,
+        samples: &[Self],
+    ) -> anyhow::Result<()>;
+}
+
+impl LoopSample for f32 {
+    fn write_wav(
+        path: impl AsRef<Path>,
+        sample_rate_hz: u32,
+        samples: &[Self],
+    ) -> anyhow::Result<()> {
+        caw_audio_file::write_wav_mono(path, sample_rate_hz, samples)
+    }
+}
+
+impl LoopSample for StereoPair<f32> {
+    fn write_wav(
+        path: impl AsRef<Path>,
+        sample_rate_hz: u32,
+        samples: &[Self],
+    ) -> anyhow::Result<()> {
+        caw_audio_file::write_wav_stereo(
+            path,
+            sample_rate_hz,
+            samples.iter().cloned(),
+        )
+    }
+}
+
+builder! {
+    #[constructor = "audio_looper"]
+    #[constructor_doc = "Record a signal into a buffer and play it back in a loop with overdubbing"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // The first trigger starts recording. If `length_s` is 0 then the second trigger ends the
+        // recording and starts playing it in a loop. Triggering while a loop is playing discards
+        // the loop and starts a new recording.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "T"]
+        record_trig: _,
+        // When positive, recording ends automatically after this many seconds.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "L"]
+        #[default = 0.0]
+        length_s: f32,
+        // The longest loop that can be recorded. Recording stops when the loop reaches this
+        // length. Memory for 3 loops of this length is allocated when the looper is created: the
+        // loop itself, the previous recording (so re-recording can be undone) and the samples
+        // replaced by overdubbing, plus a fourth if `wav_path` is set. At 48kHz the default of
+        // 30s is about 17MB for a mono looper and 35MB for a stereo looper.
+        #[default = 30.0]
+        max_length_s: f32,
+        // Used with `max_length_s` to size the looper's buffers when it's created so no memory
+        // is allocated on the audio thread. If the actual sample rate differs, the longest loop
+        // that can be recorded is scaled by the ratio of the two rates.
+        #[default = 48_000.0]
+        sample_rate_hz: f32,
+        // While this is true the input is mixed into the loop as it plays.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "O"]
+        #[default = false]
+        overdub: bool,
+        // The existing contents of the loop are scaled by this much when overdubbing.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "F"]
+        #[default = 1.0]
+        feedback_01: f32,
+        // Restore the loop to how it was before the most recent overdub layer or recording.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "U"]
+        #[default = false]
+        undo_trig: bool,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "C"]
+        #[default = false]
+        clear_trig: bool,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        #[default = false]
+        reverse: bool,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "H"]
+        #[default = false]
+        half_speed: bool,
+        // Write the current loop to `wav_path` (if it's set).
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "W"]
+        #[default = false]
+        save_trig: bool,
+        #[default = None]
+        wav_path: Option<PathBuf>,
+        // The number of overdub layers and recordings which can be undone. Undo history is also
+        // limited by memory: overdub layers can only be undone while the samples they replaced
+        // fit in a buffer of length `max_length_s`, and only the most recent recording can be
+        // undone.
+        #[default = 8]
+        max_undo: usize,
+    }
+}
+
+impl<T, L, O, F, U, C, R, H, W> Props<T, L, O, F, U, C, R, H, W>
+where
+    T: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    O: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    H: SigT<Item = bool>,
+    W: SigT<Item = bool>,
+{
+    /// Set the location where the loop will be saved when `save_trig` is triggered.
+    pub fn with_wav_path(self, wav_path: impl AsRef<Path>) -> Self {
+        Self {
+            wav_path: Some(wav_path.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    /// Set the length of the loop to a number of bars at a given tempo in quarter notes per
+    /// minute. The length of a bar depends on both parts of the time signature, so a bar of 6/8
+    /// lasts for 3 quarter notes.
+    pub fn length_bars(
+        self,
+        bars: f32,
+        bpm: f32,
+        time_signature: TimeSignature,
+    ) -> Props<T, f32, O, F, U, C, R, H, W> {
+        let Self {
+            record_trig,
+            max_length_s,
+            sample_rate_hz,
+            overdub,
+            feedback_01,
+            undo_trig,
+            clear_trig,
+            reverse,
+            half_speed,
+            save_trig,
+            wav_path,
+            max_undo,
+            ..
+        } = self;
+        let quarter_notes_per_bar = time_signature.beats_per_bar as f32 * 4.0
+            / time_signature.beat_unit.max(1) as f32;
+        Props {
+            record_trig,
+            length_s: bars * quarter_notes_per_bar * 60.0 / bpm,
+            max_length_s,
+            sample_rate_hz,
+            overdub,
+            feedback_01,
+            undo_trig,
+            clear_trig,
+            reverse,
+            half_speed,
+            save_trig,
+            wav_path,
+            max_undo,
+        }
+    }
+
+    fn into_looper<X, S>(
+        self,
+        sig: S,
+    ) -> AudioLooper<X, S, T, L, O, F, U, C, R, H, W>
+    where
+        X: LoopSample,
+        S: SigT<Item = X>,
+    {
+        let capacity = ((self.max_length_s.max(0.0)
+            * self.sample_rate_hz.max(0.0)) as usize)
+            .max(1);
+        AudioLooper {
+            wav_writer: self
+                .wav_path
+                .clone()
+                .map(|wav_path| WavWriter::new(wav_path, capacity)),
+            state: State::new(capacity, self.max_undo),
+            props: self,
+            sig,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Loop a stereo signal. Both channels share a single loop.
+    pub fn stereo<SL, SR>(
+        self,
+        stereo: Stereo<SL, SR>,
+    ) -> Stereo<Sig<impl SigT<Item = f32>>, Sig<impl SigT<Item = f32>>>
+    where
+        SL: SigT<Item = f32>,
+        SR: SigT<Item = f32>,
+    {
+        let sig = Sig(stereo.left)
+            .zip(stereo.right)
+            .map(|(left, right)| Stereo::new(left, right));
+        let looper: Sig<SigShared<_>> = Sig(self.into_looper(sig)).shared();
+        Stereo::new(
+            looper.clone().map(|s: StereoPair<f32>| s.left),
+            looper.map(|s: StereoPair<f32>| s.right),
+        )
+    }
+}
+
+impl<T, L, O, F, U, C, R, H, W> Filter for Props<T, L, O, F, U, C, R, H, W>
+where
+    T: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    O: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    H: SigT<Item = bool>,
+    W: SigT<Item = bool>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = AudioLooper<f32, S, T, L, O, F, U, C, R, H, W>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        self.into_looper(sig)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Empty,
+    Recording,
+    Playing,
+}
+
+/// A buffer of samples whose memory is allocated when it's created, so it never allocates while
+/// recording.
+struct LoopBuffer<X: LoopSample> {
+    samples: Vec<X>,
+    len: usize,
+}
+
+impl<X: LoopSample> LoopBuffer<X> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: vec![X::default(); capacity],
+            len: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[X] {
+        &self.samples[0..self.len]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= self.samples.len()
+    }
+
+    /// Returns `false` without adding the sample if the buffer is full.
+    fn push(&mut self, sample: X) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.samples[self.len] = sample;
+        self.len += 1;
+        true
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// An action which can be undone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UndoEntry {
+    /// The loop was replaced by a new recording. The previous loop is in `State::previous_loop`.
+    Recording,
+    /// A layer was overdubbed onto `len` consecutive samples of the loop, starting at `start` and
+    /// moving backwards if `reverse` is set. The samples they replaced were appended to the
+    /// journal starting at `journal_start`.
+    Overdub {
+        start: usize,
+        reverse: bool,
+        len: usize,
+        journal_start: u64,
+    },
+}
+
+/// Ring buffer of the samples replaced by overdubbing, which is shared by all overdub layers so
+/// undo history only takes as much memory as the samples that were actually overdubbed.
+struct Journal<X: LoopSample> {
+    samples: Vec<X>,
+    /// The position after the most recently written sample, counting from the first sample
+    /// ever written.
+    total_written: u64,
+    /// The position of the oldest sample which hasn't been overwritten
+    oldest_intact: u64,
+}
+
+impl<X: LoopSample> Journal<X> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: vec![X::default(); capacity],
+            total_written: 0,
+            oldest_intact: 0,
+        }
+    }
+
+    fn push(&mut self, sample: X) {
+        let index = (self.total_written % self.samples.len() as u64) as usize;
+        self.samples[index] = sample;
+        self.total_written += 1;
+        self.oldest_intact = self
+            .oldest_intact
+            .max(self.total_written.saturating_sub(self.samples.len() as u64));
+    }
+
+    fn get(&self, i: u64) -> X {
+        self.samples[(i % self.samples.len() as u64) as usize]
+    }
+
+    /// Whether the samples written since `start` are all still present.
+    fn contains_since(&self, start: u64) -> bool {
+        start >= self.oldest_intact
+    }
+
+    /// Discard the samples written since `start` so their space can be reused.
+    fn truncate(&mut self, start: u64) {
+        self.total_written = start;
+    }
+}
+
+struct State<X: LoopSample> {
+    mode: Mode,
+    loop_buffer: LoopBuffer<X>,
+    /// The loop from before the most recent recording
+    previous_loop: LoopBuffer<X>,
+    journal: Journal<X>,
+    /// Undo history with the most recent entry at the back. Never grows past `max_undo`.
+    undo_entries: VecDeque<UndoEntry>,
+    max_undo: usize,
+    /// Position of the playhead in samples
+    position: f64,
+    overdubbing: bool,
+}
+
+impl<X: LoopSample> State<X> {
+    fn new(capacity: usize, max_undo: usize) -> Self {
+        Self {
+            mode: Mode::Empty,
+            loop_buffer: LoopBuffer::new(capacity),
+            previous_loop: LoopBuffer::new(capacity),
+            journal: Journal::new(capacity),
+            undo_entries: VecDeque::with_capacity(max_undo),
+            max_undo,
+            position: 0.0,
+            overdubbing: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.mode = Mode::Empty;
+        self.loop_buffer.clear();
+        self.undo_entries.clear();
+        self.position = 0.0;
+        self.overdubbing = false;
+    }
+
+    fn push_undo(&mut self, entry: UndoEntry) {
+        if self.max_undo == 0 {
+            return;
+        }
+        if self.undo_entries.len() >= self.max_undo {
+            self.undo_entries.pop_front();
+        }
+        self.undo_entries.push_back(entry);
+    }
+
+    fn undo(&mut self) {
+        let Some(entry) = self.undo_entries.pop_back() else {
+            return;
+        };
+        match entry {
+            UndoEntry::Recording => {
+                mem::swap(&mut self.previous_loop, &mut self.loop_buffer)
+            }
+            UndoEntry::Overdub {
+                start,
+                reverse,
+                len,
+                journal_start,
+            } => {
+                if !self.journal.contains_since(journal_start) {
+                    // The replaced samples have been overwritten by more recent overdubs, so
+                    // this layer and all older entries can't be undone.
+                    self.undo_entries.clear();
+                    return;
+                }
+                let loop_len = self.loop_buffer.len;
+                for i in 0..len {
+                    let index = if reverse {
+                        (start + loop_len - (i % loop_len)) % loop_len
+                    } else {
+                        (start + i) % loop_len
+                    };
+                    self.loop_buffer.samples[index] =
+                        self.journal.get(journal_start + i as u64);
+                }
+                // The journal space used by this layer is no longer needed.
+                self.journal.truncate(journal_start);
+            }
+        }
+        if self.position >= self.loop_buffer.len as f64 {
+            self.position = 0.0;
+        }
+        self.overdubbing = false;
+        self.mode = if self.loop_buffer.is_empty() {
+            Mode::Empty
+        } else {
+            Mode::Playing
+        };
+    }
+
+    fn start_recording(&mut self) {
+        if self.mode == Mode::Playing {
+            // Only one previous loop is kept, so entries from before the last recording can no
+            // longer be undone.
+            if let Some(i) = self
+                .undo_entries
+                .iter()
+                .rposition(|entry| *entry == UndoEntry::Recording)
+            {
+                self.undo_entries.drain(0..=i);
+            }
+            mem::swap(&mut self.previous_loop, &mut self.loop_buffer);
+            self.push_undo(UndoEntry::Recording);
+        }
+        self.loop_buffer.clear();
+        self.mode = Mode::Recording;
+        self.overdubbing = false;
+    }
+
+    fn finish_recording(&mut self) {
+        self.position = 0.0;
+        self.mode = if self.loop_buffer.is_empty() {
+            Mode::Empty
+        } else {
+            Mode::Playing
+        };
+    }
+
+    /// Add a sample to the recording, finishing the recording if the loop is full.
+    fn record(&mut self, sample: X, length_samples: usize) {
+        self.loop_buffer.push(sample);
+        if self.loop_buffer.is_full()
+            || (length_samples > 0 && self.loop_buffer.len >= length_samples)
+        {
+            self.finish_recording();
+        }
+    }
+
+    /// Mix a sample into the loop at `index`, saving the previous value so it can be undone.
+    fn overdub(
+        &mut self,
+        index: usize,
+        sample: X,
+        feedback_01: f32,
+        reverse: bool,
+    ) {
+        let previous = self.loop_buffer.samples[index];
+        // Layers end when the playhead wraps around or changes direction, so the samples
+        // overdubbed during a layer are consecutive.
+        let continues_layer = self.overdubbing
+            && matches!(
+                self.undo_entries.back(),
+                Some(UndoEntry::Overdub { reverse: r, .. }) if *r == reverse
+            );
+        if !continues_layer {
+            self.push_undo(UndoEntry::Overdub {
+                start: index,
+                reverse,
+                len: 0,
+                journal_start: self.journal.total_written,
+            });
+        }
+        self.overdubbing = true;
+        if let Some(UndoEntry::Overdub { len, .. }) =
+            self.undo_entries.back_mut()
+        {
+            *len += 1;
+            self.journal.push(previous);
+        }
+        self.loop_buffer.samples[index] = (previous * feedback_01) + sample;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn tick(
+        &mut self,
+        sample: X,
+        record_trig: bool,
+        length_samples: usize,
+        overdub: bool,
+        feedback_01: f32,
+        reverse: bool,
+        half_speed: bool,
+    ) -> X {
+        match self.mode {
+            Mode::Empty => {
+                if record_trig {
+                    self.start_recording();
+                    self.record(sample, length_samples);
+                }
+                sample
+            }
+            Mode::Recording => {
+                if record_trig && length_samples == 0 {
+                    self.finish_recording();
+                    return self.tick(
+                        sample,
+                        false,
+                        length_samples,
+                        overdub,
+                        feedback_01,
+                        reverse,
+                        half_speed,
+                    );
+                }
+                self.record(sample, length_samples);
+                sample
+            }
+            Mode::Playing => {
+                if record_trig {
+                    self.start_recording();
+                    self.record(sample, length_samples);
+                    return sample;
+                }
+                let len = self.loop_buffer.len;
+                if !overdub {
+                    self.overdubbing = false;
+                }
+                let index = self.position.floor() as usize % len;
+                let next_index = (index + 1) % len;
+                let frac = self.position.fract() as f32;
+                let loop_samples = self.loop_buffer.as_slice();
+                let out = (loop_samples[index] * (1.0 - frac))
+                    + (loop_samples[next_index] * frac);
+                // When playing at half speed the input is only written at whole sample positions
+                // so each position in the loop receives a single input sample.
+                if overdub && frac == 0.0 {
+                    self.overdub(index, sample, feedback_01, reverse);
+                }
+                let step = if half_speed { 0.5 } else { 1.0 };
+                self.position += if reverse { -step } else { step };
+                if self.position < 0.0 || self.position >= len as f64 {
+                    self.position = self.position.rem_euclid(len as f64);
+                    // Each pass through the loop while overdubbing is a separate layer.
+                    self.overdubbing = false;
+                }
+                sample + out
+            }
+        }
+    }
+}
+
+/// Writes loops to a wav file on a background thread so the audio thread never waits for file
+/// IO. Loops are copied into a buffer which is passed back and forth between the threads, so
+/// saving doesn't allocate once the buffer has grown to the size of the loop.
+struct WavWriter<X: LoopSample> {
+    sender: mpsc::Sender<(u32, Vec<X>)>,
+    returned_buffer_receiver: mpsc::Receiver<Vec<X>>,
+    /// `None` while the writer thread is saving a loop
+    buffer: Option<Vec<X>>,
+}
+
+impl<X: LoopSample> WavWriter<X> {
+    fn new(wav_path: PathBuf, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<(u32, Vec<X>)>();
+        let (returned_buffer_sender, returned_buffer_receiver) =
+            mpsc::channel();
+        thread::spawn(move || {
+            for (sample_rate_hz, samples) in receiver {
+                if let Err(e) =
+                    X::write_wav(&wav_path, sample_rate_hz, &samples)
+                {
+                    log::warn!(
+                        "Failed to save loop to {}: {}",
+                        wav_path.display(),
+                        e
+                    );
+                }
+                if returned_buffer_sender.send(samples).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            sender,
+            returned_buffer_receiver,
+            buffer: Some(Vec::with_capacity(capacity)),
+        }
+    }
+
+    fn save(&mut self, samples: &[X], sample_rate_hz: u32) {
+        if self.buffer.is_none() {
+            self.buffer = self.returned_buffer_receiver.try_recv().ok();
+        }
+        let Some(mut buffer) = self.buffer.take() else {
+            log::warn!("Still saving the previous loop. Not saving.");
+            return;
+        };
+        buffer.clear();
+        buffer.extend_from_slice(samples);
+        if self.sender.send((sample_rate_hz, buffer)).is_err() {
+            log::warn!("Loop writer thread has stopped. Not saving.");
+        }
+    }
+}
+
+pub struct AudioLooper<X, S, T, L, O, F, U, C, R, H, W>
+where
+    X: LoopSample,
+    S: SigT<Item = X>,
+    T: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    O: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    H: SigT<Item = bool>,
+    W: SigT<Item = bool>,
+{
+    props: Props<T, L, O, F, U, C, R, H, W>,
+    sig: S,
+    state: State<X>,
+    wav_writer: Option<WavWriter<X>>,
+    buf: Vec<X>,
+}
+
+impl<X, S, T, L, O, F, U, C, R, H, W> SigT
+    for AudioLooper<X, S, T, L, O, F, U, C, R, H, W>
+where
+    X: LoopSample,
+    S: SigT<Item = X>,
+    T: SigT<Item = bool>,
+    L: SigT<Item = f32>,
+    O: SigT<Item = bool>,
+    F: SigT<Item = f32>,
+    U: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    H: SigT<Item = bool>,
+    W: SigT<Item = bool>,
+{
+    type Item = X;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.resize_with(ctx.num_samples, Default::default);
+        let sig = self.sig.sample(ctx);
+        let record_trig = self.props.record_trig.sample(ctx);
+        let length_s = self.props.length_s.sample(ctx);
+        let overdub = self.props.overdub.sample(ctx);
+        let feedback_01 = self.props.feedback_01.sample(ctx);
+        let undo_trig = self.props.undo_trig.sample(ctx);
+        let clear_trig = self.props.clear_trig.sample(ctx);
+        let reverse = self.props.reverse.sample(ctx);
+        let half_speed = self.props.half_speed.sample(ctx);
+        let save_trig = self.props.save_trig.sample(ctx);
+        let state = &mut self.state;
+        for (
+            out,
+            sample,
+            record_trig,
+            length_s,
+            overdub,
+            feedback_01,
+            undo_trig,
+            clear_trig,
+            reverse,
+            half_speed,
+            save_trig,
+        ) in izip! {
+            self.buf.iter_mut(),
+            sig.iter(),
+            record_trig.iter(),
+            length_s.iter(),
+            overdub.iter(),
+            feedback_01.iter(),
+            undo_trig.iter(),
+            clear_trig.iter(),
+            reverse.iter(),
+            half_speed.iter(),
+            save_trig.iter(),
+        } {
+            if clear_trig {
+                state.clear();
+            }
+            if undo_trig {
+                state.undo();
+            }
+            let length_samples =
+                (length_s.max(0.0) * ctx.sample_rate_hz) as usize;
+            *out = state.tick(
+                sample,
+                record_trig,
+                length_samples,
+                overdub,
+                feedback_01,
+                reverse,
+                half_speed,
+            );
+            if save_trig && let Some(wav_writer) = self.wav_writer.as_mut() {
+                wav_writer.save(
+                    state.loop_buffer.as_slice(),
+                    ctx.sample_rate_hz as u32,
+                );
+            }
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Inputs to `State::tick` which stay the same between samples
+    #[derive(Clone, Copy, Default)]
+    struct Controls {
+        length_samples: usize,
+        overdub: bool,
+        feedback_01: f32,
+        reverse: bool,
+        half_speed: bool,
+    }
+
+    impl Controls {
+        fn new() -> Self {
+            Self {
+                feedback_01: 1.0,
+                ..Default::default()
+            }
+        }
+    }
+
+    fn tick(
+        state: &mut State<f32>,
+        sample: f32,
+        record_trig: bool,
+        controls: Controls,
+    ) -> f32 {
+        state.tick(
+            sample,
+            record_trig,
+            controls.length_samples,
+            controls.overdub,
+            controls.feedback_01,
+            controls.reverse,
+            controls.half_speed,
+        )
+    }
+
+    /// Record `samples` as a loop, triggering the start and end of the recording.
+    fn record(state: &mut State<f32>, samples: &[f32]) {
+        for (i, &sample) in samples.iter().enumerate() {
+            tick(state, sample, i == 0, Controls::new());
+        }
+        tick(state, 0.0, true, Controls::new());
+    }
+
+    /// The output of the looper with silent input for `num_samples` samples.
+    fn play(
+        state: &mut State<f32>,
+        num_samples: usize,
+        controls: Controls,
+    ) -> Vec<f32> {
+        (0..num_samples)
+            .map(|_| tick(state, 0.0, false, controls))
+            .collect()
+    }
+
+    #[test]
+    fn record_and_play() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0, 3.0]);
+        assert_eq!(state.mode, Mode::Playing);
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 2.0, 3.0]);
+        // Playback starts on the sample which stops the recording, so rewind to the start.
+        state.position = 0.0;
+        assert_eq!(
+            play(&mut state, 6, Controls::new()),
+            vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn recording_stops_after_length() {
+        let mut state = State::new(16, 4);
+        let controls = Controls {
+            length_samples: 2,
+            ..Controls::new()
+        };
+        tick(&mut state, 1.0, true, controls);
+        tick(&mut state, 2.0, false, controls);
+        assert_eq!(state.mode, Mode::Playing);
+        assert_eq!(play(&mut state, 3, controls), vec![1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn recording_stops_at_max_length() {
+        let mut state = State::new(3, 4);
+        for i in 0..5 {
+            tick(&mut state, i as f32, i == 0, Controls::new());
+        }
+        assert_eq!(state.mode, Mode::Playing);
+        assert_eq!(state.loop_buffer.as_slice(), &[0.0, 1.0, 2.0]);
+        assert_eq!(state.loop_buffer.samples.len(), 3);
+    }
+
+    #[test]
+    fn overdub_mixes_with_feedback() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0]);
+        state.position = 0.0;
+        let controls = Controls {
+            overdub: true,
+            feedback_01: 0.5,
+            ..Controls::new()
+        };
+        // The input is heard along with the loop before it's overdubbed.
+        assert_eq!(tick(&mut state, 10.0, false, controls), 11.0);
+        assert_eq!(tick(&mut state, 20.0, false, controls), 22.0);
+        assert_eq!(state.loop_buffer.as_slice(), &[10.5, 21.0]);
+    }
+
+    #[test]
+    fn undo_overdub_layers() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0]);
+        state.position = 0.0;
+        let controls = Controls {
+            overdub: true,
+            ..Controls::new()
+        };
+        // Each pass through the loop is a separate layer.
+        for _ in 0..2 {
+            tick(&mut state, 1.0, false, controls);
+            tick(&mut state, 1.0, false, controls);
+        }
+        assert_eq!(state.loop_buffer.as_slice(), &[3.0, 4.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[2.0, 3.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 2.0]);
+        assert_eq!(state.mode, Mode::Playing);
+        // Nothing left to undo
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn undo_recording() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0]);
+        record(&mut state, &[3.0, 4.0, 5.0]);
+        assert_eq!(state.loop_buffer.as_slice(), &[3.0, 4.0, 5.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 2.0]);
+        assert_eq!(state.mode, Mode::Playing);
+    }
+
+    #[test]
+    fn only_the_most_recent_recording_can_be_undone() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0]);
+        record(&mut state, &[2.0]);
+        record(&mut state, &[3.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[2.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[2.0]);
+    }
+
+    /// Overdub a layer of `value` over one pass of a loop of length `len`.
+    fn overdub_pass(state: &mut State<f32>, len: usize, value: f32) {
+        let controls = Controls {
+            overdub: true,
+            ..Controls::new()
+        };
+        for _ in 0..len {
+            tick(state, value, false, controls);
+        }
+    }
+
+    #[test]
+    fn undo_forgets_oldest_layers() {
+        let mut state = State::new(16, 2);
+        record(&mut state, &[0.0, 0.0]);
+        state.position = 0.0;
+        for _ in 0..3 {
+            overdub_pass(&mut state, 2, 1.0);
+        }
+        assert_eq!(state.loop_buffer.as_slice(), &[3.0, 3.0]);
+        state.undo();
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 1.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn undo_is_limited_by_journal_length() {
+        // The journal holds 4 samples so only the 2 most recent passes over a loop of length 2
+        // can be undone, even though the undo history is longer.
+        let mut state = State::new(4, 8);
+        record(&mut state, &[0.0, 0.0]);
+        state.position = 0.0;
+        for _ in 0..3 {
+            overdub_pass(&mut state, 2, 1.0);
+        }
+        state.undo();
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 1.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 1.0]);
+        assert!(state.undo_entries.is_empty());
+    }
+
+    #[test]
+    fn memory_is_allocated_up_front() {
+        let looper = audio_looper(false)
+            .max_length_s(0.5)
+            .sample_rate_hz(100.0)
+            .into_looper(Sig::from_fn(|_| 0.0f32));
+        assert_eq!(looper.state.loop_buffer.samples.len(), 50);
+        assert_eq!(looper.state.previous_loop.samples.len(), 50);
+        assert_eq!(looper.state.journal.samples.len(), 50);
+    }
+
+    #[test]
+    fn reverse_playback() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0, 3.0]);
+        state.position = 0.0;
+        let controls = Controls {
+            reverse: true,
+            ..Controls::new()
+        };
+        assert_eq!(
+            play(&mut state, 6, controls),
+            vec![1.0, 3.0, 2.0, 1.0, 3.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn reverse_overdub_can_be_undone() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0, 3.0]);
+        state.position = 2.0;
+        let controls = Controls {
+            overdub: true,
+            reverse: true,
+            ..Controls::new()
+        };
+        tick(&mut state, 10.0, false, controls);
+        tick(&mut state, 10.0, false, controls);
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 12.0, 13.0]);
+        state.undo();
+        assert_eq!(state.loop_buffer.as_slice(), &[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn half_speed_playback() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[0.0, 2.0]);
+        state.position = 0.0;
+        let controls = Controls {
+            half_speed: true,
+            ..Controls::new()
+        };
+        assert_eq!(play(&mut state, 4, controls), vec![0.0, 1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn clear_keeps_allocation() {
+        let mut state = State::new(16, 4);
+        record(&mut state, &[1.0, 2.0]);
+        state.clear();
+        assert_eq!(state.mode, Mode::Empty);
+        assert!(state.loop_buffer.is_empty());
+        assert_eq!(state.loop_buffer.samples.len(), 16);
+        assert_eq!(tick(&mut state, 5.0, false, Controls::new()), 5.0);
+    }
+}
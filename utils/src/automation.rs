@@ -0,0 +1,424 @@
+use crate::transport::Transport;
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, SigCtx, SigT};
+use caw_persist::PersistData;
+use itertools::izip;
+use serde::{Deserialize, Serialize};
+
+/// How to compute values between recorded points during playback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutomationInterpolation {
+    /// Hold each point until the next one
+    Step,
+    #[default]
+    Linear,
+    /// Ease in and out of each point with a cosine curve
+    Cosine,
+}
+
+impl AutomationInterpolation {
+    fn interpolate(self, a: f32, b: f32, t_01: f32) -> f32 {
+        let t_01 = match self {
+            Self::Step => 0.0,
+            Self::Linear => t_01,
+            Self::Cosine => (1.0 - (t_01 * std::f32::consts::PI).cos()) / 2.0,
+        };
+        a + ((b - a) * t_01)
+    }
+}
+
+/// A recording of a continuous control, stored as points taken at a fixed rate.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AutomationData {
+    /// The number of points per second
+    pub resolution_hz: f32,
+    pub points: Vec<f32>,
+}
+
+impl AutomationData {
+    fn new(resolution_hz: f32) -> Self {
+        Self {
+            resolution_hz: resolution_hz.max(f32::MIN_POSITIVE),
+            points: Vec::new(),
+        }
+    }
+
+    /// The index of the point whose time slot contains the given time, and how far through that
+    /// slot the time is, from 0 to 1.
+    fn slot_at(&self, position_s: f64) -> (usize, f32) {
+        let index_f = position_s * self.resolution_hz as f64;
+        // Allow for rounding errors accumulated while advancing the position one sample at a time.
+        let index = (index_f + 1e-6) as usize;
+        (index, (index_f - index as f64).max(0.0) as f32)
+    }
+
+    fn index_at(&self, position_s: f64) -> usize {
+        self.slot_at(position_s).0
+    }
+
+    /// Record a value into the point at `index`. Only the first value recorded within each point's time
+    /// slot is kept, so the points are taken at regular intervals regardless of the sample rate.
+    /// Any gap between the end of the recording and the new point is filled with `value`.
+    fn record(&mut self, index: usize, value: f32, new_slot: bool) {
+        if index >= self.points.len() {
+            self.points.resize(index + 1, value);
+        } else if new_slot {
+            self.points[index] = value;
+        }
+    }
+
+    fn play(
+        &self,
+        position_s: f64,
+        interpolation: AutomationInterpolation,
+    ) -> Option<f32> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let (index, t_01) = self.slot_at(position_s);
+        let index = index % self.points.len();
+        // The final point is interpolated towards the first point so the loop wraps smoothly.
+        let next_index = (index + 1) % self.points.len();
+        Some(interpolation.interpolate(
+            self.points[index],
+            self.points[next_index],
+            t_01,
+        ))
+    }
+}
+
+impl PersistData for AutomationData {
+    const NAME: &'static str = "automation";
+}
+
+builder! {
+    #[constructor = "automation_lane"]
+    #[constructor_doc = "Record the movement of a continuous control and play it back in a loop"]
+    #[generic_setter_type_name = "X"]
+    pub struct Props {
+        // While this is true the input is passed through and recorded, replacing the previous
+        // recording over the same part of the loop. Recording past the end of the loop extends it.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        recording: _,
+        // Return to the start of the loop. If this never triggers then the loop restarts after
+        // reaching the end of the recording.
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "L"]
+        #[default = false]
+        loop_trig: bool,
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "C"]
+        #[default = false]
+        clear_trig: bool,
+        // The number of points recorded per second when starting a new recording.
+        #[default = 100.0]
+        resolution_hz: f32,
+        #[default = AutomationInterpolation::Linear]
+        interpolation: AutomationInterpolation,
+        #[default = None]
+        persist_name: Option<String>,
+    }
+}
+
+impl<R, L, C> Props<R, L, C>
+where
+    R: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+{
+    /// Load the recording saved under `name` and save the recording under `name` each time
+    /// recording stops or the recording is cleared.
+    pub fn persist_with_name(self, name: impl AsRef<str>) -> Self {
+        Self {
+            persist_name: Some(format!("automation_lane_{}", name.as_ref())),
+            ..self
+        }
+    }
+
+    /// Restart the loop every `length_beats` beats of a transport.
+    pub fn loop_with_transport<B>(
+        self,
+        transport: &Transport<B>,
+        length_beats: f64,
+    ) -> Props<R, impl SigT<Item = bool>, C>
+    where
+        B: SigT<Item = f32>,
+    {
+        self.loop_trig(transport.trig_every_beats(length_beats))
+    }
+}
+
+impl<R, L, C> Filter for Props<R, L, C>
+where
+    R: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+{
+    type ItemIn = f32;
+
+    type Out<S>
+        = AutomationLane<S, R, L, C>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        let data = self
+            .persist_name
+            .as_ref()
+            .and_then(|name| {
+                // A missing file just means nothing has been recorded yet.
+                AutomationData::load(name).ok()
+            })
+            .unwrap_or_else(|| AutomationData::new(self.resolution_hz));
+        AutomationLane {
+            props: self,
+            sig,
+            state: State {
+                data,
+                position_s: 0.0,
+                prev_index: None,
+                was_recording: false,
+            },
+            buf: Vec::new(),
+        }
+    }
+}
+
+struct State {
+    data: AutomationData,
+    /// Time since the start of the loop
+    position_s: f64,
+    /// Index of the point slot which was recorded into during the previous sample
+    prev_index: Option<usize>,
+    was_recording: bool,
+}
+
+impl State {
+    fn save(&self, persist_name: Option<&String>) {
+        if let Some(persist_name) = persist_name {
+            self.data.save_(persist_name);
+        }
+    }
+}
+
+pub struct AutomationLane<S, R, L, C>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+{
+    props: Props<R, L, C>,
+    sig: S,
+    state: State,
+    buf: Vec<f32>,
+}
+
+impl<S, R, L, C> SigT for AutomationLane<S, R, L, C>
+where
+    S: SigT<Item = f32>,
+    R: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    C: SigT<Item = bool>,
+{
+    type Item = f32;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let sig = self.sig.sample(ctx);
+        let recording = self.props.recording.sample(ctx);
+        let loop_trig = self.props.loop_trig.sample(ctx);
+        let clear_trig = self.props.clear_trig.sample(ctx);
+        let sample_period_s = 1.0 / ctx.sample_rate_hz as f64;
+        for (sample, recording, loop_trig, clear_trig) in izip! {
+            sig.iter(),
+            recording.iter(),
+            loop_trig.iter(),
+            clear_trig.iter(),
+        } {
+            let state = &mut self.state;
+            if clear_trig {
+                state.data = AutomationData::new(self.props.resolution_hz);
+                state.position_s = 0.0;
+                state.save(self.props.persist_name.as_ref());
+            }
+            if loop_trig
+                || (!recording
+                    && state.data.index_at(state.position_s)
+                        >= state.data.points.len())
+            {
+                state.position_s = 0.0;
+            }
+            let out = if recording {
+                let index = state.data.index_at(state.position_s);
+                state.data.record(
+                    index,
+                    sample,
+                    state.prev_index != Some(index),
+                );
+                state.prev_index = Some(index);
+                sample
+            } else {
+                state.prev_index = None;
+                if state.was_recording {
+                    state.save(self.props.persist_name.as_ref());
+                }
+                state
+                    .data
+                    .play(state.position_s, self.props.interpolation)
+                    .unwrap_or(sample)
+            };
+            state.was_recording = recording;
+            state.position_s += sample_period_s;
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use caw_core::Sig;
+
+    const SAMPLE_RATE_HZ: f32 = 100.0;
+
+    /// At 10 points per second each point covers 10 samples.
+    const RESOLUTION_HZ: f32 = 10.0;
+
+    fn from_vec<T: Clone + Default>(
+        values: Vec<T>,
+    ) -> Sig<impl SigT<Item = T>> {
+        let mut values = values.into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    /// A signal which is true for the samples in `range`.
+    fn gate(
+        range: std::ops::Range<usize>,
+        num_samples: usize,
+    ) -> Sig<impl SigT<Item = bool>> {
+        from_vec((0..num_samples).map(|i| range.contains(&i)).collect())
+    }
+
+    fn run<S: SigT<Item = f32>>(mut sig: S, num_samples: usize) -> Vec<f32> {
+        let ctx = SigCtx {
+            sample_rate_hz: SAMPLE_RATE_HZ,
+            batch_index: 0,
+            num_samples,
+        };
+        sig.sample(&ctx).iter().collect()
+    }
+
+    /// The sample index as the input signal, recorded for the first `record_samples` samples.
+    fn lane(
+        record_samples: usize,
+        num_samples: usize,
+        interpolation: AutomationInterpolation,
+    ) -> Props<
+        impl SigT<Item = bool>,
+        impl SigT<Item = bool>,
+        impl SigT<Item = bool>,
+    > {
+        automation_lane(gate(0..record_samples, num_samples))
+            .resolution_hz(RESOLUTION_HZ)
+            .interpolation(interpolation)
+    }
+
+    fn ramp(num_samples: usize) -> Sig<impl SigT<Item = f32>> {
+        from_vec((0..num_samples).map(|i| i as f32).collect())
+    }
+
+    #[test]
+    fn record_then_play() {
+        let sig =
+            ramp(60).filter(lane(20, 60, AutomationInterpolation::Linear));
+        let out = run(sig, 60);
+        // The input passes through while recording.
+        assert_eq!(&out[0..20], (0..20).map(|i| i as f32).collect::<Vec<_>>());
+        // One point is kept from each slot, at samples 0 and 10.
+        assert_eq!(out[20], 0.0);
+        assert_eq!(out[25], 5.0);
+        assert_eq!(out[30], 10.0);
+        // The last point is interpolated towards the first.
+        assert_eq!(out[35], 5.0);
+    }
+
+    #[test]
+    fn loops_after_end_of_recording() {
+        let sig = ramp(90).filter(lane(30, 90, AutomationInterpolation::Step));
+        let out = run(sig, 90);
+        let expected = [0.0, 10.0, 20.0]
+            .into_iter()
+            .flat_map(|point| [point; 10])
+            .collect::<Vec<_>>();
+        assert_eq!(&out[30..60], expected);
+        assert_eq!(&out[60..90], expected);
+    }
+
+    #[test]
+    fn step_changes_on_slot_boundaries() {
+        // Rounding errors in the position must not delay the change to the next point.
+        let sig =
+            ramp(200).filter(lane(100, 200, AutomationInterpolation::Step));
+        let out = run(sig, 200);
+        for i in 100..200 {
+            assert_eq!(out[i], ((i - 100) / 10 * 10) as f32, "sample {i}");
+        }
+    }
+
+    #[test]
+    fn cosine_eases_between_points() {
+        let sig =
+            ramp(40).filter(lane(20, 40, AutomationInterpolation::Cosine));
+        let out = run(sig, 40);
+        assert_eq!(out[20], 0.0);
+        assert!((out[22] - 0.955).abs() < 0.001, "{}", out[22]);
+        assert!((out[25] - 5.0).abs() < 0.001, "{}", out[25]);
+        assert!((out[28] - 9.045).abs() < 0.001, "{}", out[28]);
+        assert_eq!(out[30], 10.0);
+    }
+
+    #[test]
+    fn loop_trig_restarts_playback() {
+        let loop_trig = from_vec((0..60).map(|i| i == 45).collect());
+        let sig = ramp(60).filter(
+            lane(30, 60, AutomationInterpolation::Step).loop_trig(loop_trig),
+        );
+        let out = run(sig, 60);
+        assert_eq!(&out[40..45], [10.0; 5]);
+        assert_eq!(&out[45..55], [0.0; 10]);
+        assert_eq!(out[55], 10.0);
+    }
+
+    #[test]
+    fn clear_trig_discards_recording() {
+        let clear_trig = from_vec((0..40).map(|i| i == 30).collect());
+        let sig = ramp(40).filter(
+            lane(20, 40, AutomationInterpolation::Step).clear_trig(clear_trig),
+        );
+        let out = run(sig, 40);
+        assert_eq!(&out[20..30], [0.0; 10]);
+        // With nothing recorded the input passes through.
+        assert_eq!(
+            &out[30..40],
+            (30..40).map(|i| i as f32).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn record_fills_gaps_with_new_value() {
+        let mut data = AutomationData::new(RESOLUTION_HZ);
+        data.record(0, 1.0, true);
+        data.record(3, 2.0, true);
+        assert_eq!(data.points, [1.0, 2.0, 2.0, 2.0]);
+        // Only the first value in each slot is kept.
+        data.record(3, 3.0, false);
+        data.record(1, 4.0, true);
+        assert_eq!(data.points, [1.0, 4.0, 2.0, 2.0]);
+    }
+}
@@ -4,4 +4,16 @@ pub mod noise;
 pub mod sequencer;
 pub use sequencer::value_sequencer;
 pub mod looper;
-pub use looper::{key_looper, value_looper};
+pub use looper::{LooperSnapshot, key_looper, value_looper};
+pub mod step_sequencer;
+pub use step_sequencer::step_sequencer;
+pub mod transport;
+pub use transport::{TimeSignature, Transport, transport};
+pub mod audio_looper;
+pub use audio_looper::audio_looper;
+pub mod automation;
+pub use automation::automation_lane;
+pub mod mini_notation;
+pub use mini_notation::mini_notation;
+pub mod rhythm;
+pub use rhythm::{clock_multiply, euclidean, humanize, swing};
@@ -1,10 +1,14 @@
 use caw_builder_proc_macros::builder;
-use caw_core::{Buf, Sig, SigCtx, SigT};
+use caw_core::{Buf, ConstBuf, Sig, SigCtx, SigT};
 use caw_persist::PersistData;
 use itertools::izip;
 use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Arc};
 
-#[derive(Default, Serialize, Deserialize)]
+/// The maximum number of passes which can be undone.
+const MAX_UNDO: usize = 16;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Sequence<T> {
     sequence: Vec<T>,
     index: usize,
@@ -49,10 +53,85 @@ where
     const NAME: &'static str = "sequence";
 }
 
-/// Driver for saving and loading a sequence state to a file.
+/// The complete state of a looper. A looper is made up of a stack of layers, each of which is a
+/// sequence with its own length. Layers with different lengths drift in and out of phase with
+/// each other, allowing polymetric loops. New values are always recorded into the top layer.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LooperState<T> {
+    layers: Vec<Sequence<T>>,
+}
+
+impl<T> LooperState<T> {
+    fn top_layer(&self) -> &Sequence<T> {
+        // There is always at least one layer.
+        self.layers.last().unwrap()
+    }
+
+    fn top_layer_mut(&mut self) -> &mut Sequence<T> {
+        // There is always at least one layer.
+        self.layers.last_mut().unwrap()
+    }
+
+    fn tick(&mut self) {
+        for layer in &mut self.layers {
+            layer.tick();
+        }
+    }
+}
+
+impl<T> LooperState<Option<T>>
+where
+    T: Clone,
+{
+    /// The current value of the highest layer with a value at its current step.
+    fn current(&self) -> Option<T> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.current().clone())
+    }
+}
+
+impl<T> From<Sequence<T>> for LooperState<T> {
+    fn from(sequence: Sequence<T>) -> Self {
+        Self {
+            layers: vec![sequence],
+        }
+    }
+}
+
+impl<T> PersistData for LooperState<Option<T>>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    const NAME: &'static str = "looper_state";
+}
+
+/// Driver for saving and loading a looper's state to a file.
 pub trait LooperIo<T> {
     fn load(&self) -> Sequence<Option<T>>;
     fn save(&self, sequence: &Sequence<Option<T>>);
+
+    /// Load all the layers of a looper. Implementations which don't override this load a single
+    /// layer with `load`.
+    fn load_state(&self) -> LooperState<Option<T>> {
+        LooperState::from(self.load())
+    }
+
+    /// Save all the layers of a looper. Implementations which don't override this only save the
+    /// top layer with `save`.
+    fn save_state(&self, state: &LooperState<Option<T>>) {
+        self.save(state.top_layer())
+    }
+
+    /// Save a copy of the state under a name so it can be restored later with `load_snapshot`.
+    fn save_snapshot(&self, _name: &str, _state: &LooperState<Option<T>>) {}
+
+    /// Load a state previously saved with `save_snapshot`, returning `None` if there is no
+    /// snapshot with the given name.
+    fn load_snapshot(&self, _name: &str) -> Option<LooperState<Option<T>>> {
+        None
+    }
 }
 
 /// Implementation of `LooperIo` which doesn't actually save or load any data.
@@ -63,10 +142,20 @@ impl<T> LooperIo<T> for LooperIoNull {
     }
 
     fn save(&self, _sequence: &Sequence<Option<T>>) {}
+
+    fn save_state(&self, _state: &LooperState<Option<T>>) {}
 }
 
-/// Implementation of `LooperIo` which saves state into a file of a given name.
+/// Implementation of `LooperIo` which saves state into a file of a given name. Snapshots are
+/// saved in separate files whose names are derived from the given name.
 pub struct LooperIoWithName(pub String);
+
+impl LooperIoWithName {
+    fn snapshot_title(&self, name: &str) -> String {
+        format!("{}_snapshot_{}", self.0, name)
+    }
+}
+
 impl<T> LooperIo<T> for LooperIoWithName
 where
     T: Serialize + for<'a> Deserialize<'a>,
@@ -82,34 +171,229 @@ where
     fn save(&self, sequence: &Sequence<Option<T>>) {
         sequence.save_(&self.0)
     }
+
+    fn load_state(&self) -> LooperState<Option<T>> {
+        if let Ok(state) = LooperState::load(&self.0) {
+            state
+        } else {
+            // Loopers used to only save a single sequence.
+            LooperState::from(LooperIo::<T>::load(self))
+        }
+    }
+
+    fn save_state(&self, state: &LooperState<Option<T>>) {
+        state.save_(&self.0)
+    }
+
+    fn save_snapshot(&self, name: &str, state: &LooperState<Option<T>>) {
+        state.save_(self.snapshot_title(name))
+    }
+
+    fn load_snapshot(&self, name: &str) -> Option<LooperState<Option<T>>> {
+        LooperState::load_(self.snapshot_title(name))
+    }
 }
 
-pub struct KeyLooper<X, S, T, C, N, I>
+/// Command for saving or restoring a named snapshot of a looper's state. A command is carried out
+/// on the sample where the signal changes to that command, so holding a constant command only
+/// acts once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LooperSnapshot {
+    #[default]
+    None,
+    Save(Arc<str>),
+    Load(Arc<str>),
+}
+
+impl LooperSnapshot {
+    pub fn save(name: impl Into<Arc<str>>) -> Self {
+        Self::Save(name.into())
+    }
+
+    pub fn load(name: impl Into<Arc<str>>) -> Self {
+        Self::Load(name.into())
+    }
+}
+
+impl SigT for LooperSnapshot {
+    type Item = LooperSnapshot;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        ConstBuf {
+            count: ctx.num_samples,
+            value: self.clone(),
+        }
+    }
+}
+
+/// State shared by all loopers for managing layers, undo history and snapshots.
+struct LooperCore<X: Clone> {
+    state: LooperState<Option<X>>,
+    /// Each entry is the state before a pass in which the top layer was modified.
+    undo_stack: VecDeque<LooperState<Option<X>>>,
+    /// Whether the top layer has been modified since it last returned to its first step.
+    modified_this_pass: bool,
+    prev_snapshot: LooperSnapshot,
+    /// Number of samples since the most recent tick, and the number of samples between the two
+    /// most recent ticks. Used to quantize input to the step grid. Both are `None` until there
+    /// have been enough ticks to measure them.
+    samples_since_tick: Option<u64>,
+    step_period: Option<u64>,
+}
+
+impl<X: Clone> LooperCore<X> {
+    fn new(state: LooperState<Option<X>>) -> Self {
+        Self {
+            state,
+            undo_stack: VecDeque::new(),
+            modified_this_pass: false,
+            prev_snapshot: LooperSnapshot::None,
+            samples_since_tick: None,
+            step_period: None,
+        }
+    }
+
+    fn push_undo(&mut self) {
+        if self.undo_stack.len() >= MAX_UNDO {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(self.state.clone());
+    }
+
+    /// Returns true iff there was anything to undo.
+    fn undo(&mut self) -> bool {
+        if let Some(state) = self.undo_stack.pop_back() {
+            self.state = state;
+            self.modified_this_pass = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn new_layer(&mut self, length: u32) {
+        self.push_undo();
+        self.state
+            .layers
+            .push(Sequence::new_with(length as usize, || None));
+        self.modified_this_pass = false;
+    }
+
+    fn tick(&mut self) {
+        if let Some(samples_since_tick) = self.samples_since_tick {
+            self.step_period = Some(samples_since_tick);
+        }
+        self.samples_since_tick = Some(0);
+        self.state.tick();
+        if self.state.top_layer_mut().index == 0 {
+            self.modified_this_pass = false;
+        }
+    }
+
+    /// Call once per sample after handling any tick.
+    fn advance_sample(&mut self) {
+        if let Some(samples_since_tick) = self.samples_since_tick.as_mut() {
+            *samples_since_tick += 1;
+        }
+    }
+
+    /// Returns true iff the current sample is close enough to the next tick that input should be
+    /// applied to the next step rather than the current one.
+    fn is_late_enough_to_quantize(&self, tolerance_01: f32) -> bool {
+        if let (Some(step_period), Some(samples_since_tick)) =
+            (self.step_period, self.samples_since_tick)
+        {
+            let remaining = step_period.saturating_sub(samples_since_tick);
+            (remaining as f32) < (step_period as f32 * tolerance_01)
+        } else {
+            false
+        }
+    }
+
+    /// Set the value at the current step of the top layer, recording an undo point the first
+    /// time the top layer is changed during each pass.
+    fn set_current(&mut self, value: Option<X>) {
+        if !self.modified_this_pass {
+            self.push_undo();
+            self.modified_this_pass = true;
+        }
+        *self.state.top_layer_mut().current_mut() = value;
+    }
+
+    /// Returns true iff the state was changed.
+    fn handle_snapshot<I: LooperIo<X>>(
+        &mut self,
+        snapshot: LooperSnapshot,
+        io: &I,
+    ) -> bool {
+        if snapshot == self.prev_snapshot {
+            return false;
+        }
+        self.prev_snapshot = snapshot.clone();
+        match snapshot {
+            LooperSnapshot::None => false,
+            LooperSnapshot::Save(name) => {
+                io.save_snapshot(&name, &self.state);
+                false
+            }
+            LooperSnapshot::Load(name) => {
+                if let Some(state) = io
+                    .load_snapshot(&name)
+                    .filter(|state| !state.layers.is_empty())
+                {
+                    self.push_undo();
+                    self.state = state;
+                    self.modified_this_pass = false;
+                    return true;
+                }
+                log::warn!("No looper snapshot named \"{}\"", name);
+                false
+            }
+        }
+    }
+}
+
+pub struct KeyLooper<X, S, T, C, N, U, L, Q, P, I>
 where
     X: Clone,
     S: SigT<Item = Option<X>>,
     T: SigT<Item = bool>,
     C: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    Q: SigT<Item = f32>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<X>,
 {
     sig: S,
     last_value: Option<X>,
+    /// Input received shortly before a tick which will be recorded into the next step.
+    pending_value: Option<X>,
     tick: T,
     clearing: C,
     length: N,
-    sequence: Sequence<S::Item>,
+    undo_trig: U,
+    new_layer_trig: L,
+    quantize_tolerance_01: Q,
+    snapshot: P,
+    core: LooperCore<X>,
     buf: Vec<S::Item>,
     io: I,
 }
 
-impl<X, S, T, C, N, I> SigT for KeyLooper<X, S, T, C, N, I>
+impl<X, S, T, C, N, U, L, Q, P, I> SigT
+    for KeyLooper<X, S, T, C, N, U, L, Q, P, I>
 where
     X: Clone,
     S: SigT<Item = Option<X>>,
     T: SigT<Item = bool>,
     C: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    Q: SigT<Item = f32>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<X>,
 {
     type Item = S::Item;
@@ -121,66 +405,132 @@ where
         let tick = self.tick.sample(ctx);
         let clearing = self.clearing.sample(ctx);
         let length = self.length.sample(ctx);
+        let undo_trig = self.undo_trig.sample(ctx);
+        let new_layer_trig = self.new_layer_trig.sample(ctx);
+        let quantize_tolerance_01 = self.quantize_tolerance_01.sample(ctx);
+        let snapshot = self.snapshot.sample(ctx);
         let mut changed_this_frame = false;
-        for (out, sample, tick, clearing, length) in izip! {
+        for (
+            out,
+            sample,
+            tick,
+            clearing,
+            length,
+            undo_trig,
+            new_layer_trig,
+            quantize_tolerance_01,
+            snapshot,
+        ) in izip! {
             self.buf.iter_mut(),
             sig.iter(),
             tick.iter(),
             clearing.iter(),
             length.iter(),
+            undo_trig.iter(),
+            new_layer_trig.iter(),
+            quantize_tolerance_01.iter(),
+            snapshot.iter(),
         } {
-            self.sequence.resize_with(length as usize, || None);
-            if tick {
-                self.sequence.tick();
+            if undo_trig && self.core.undo() {
                 self.last_value = None;
+                self.pending_value = None;
+                changed_this_frame = true;
+            }
+            if new_layer_trig {
+                self.core.new_layer(length);
+                self.last_value = None;
+                changed_this_frame = true;
+            }
+            if self.core.handle_snapshot(snapshot, &self.io) {
+                changed_this_frame = true;
+            }
+            self.core
+                .state
+                .top_layer_mut()
+                .resize_with(length as usize, || None);
+            if tick {
+                self.core.tick();
+                self.last_value = self.pending_value.take();
             } else if sample.is_some() {
-                self.last_value = sample.clone();
+                if self.pending_value.is_some()
+                    || self
+                        .core
+                        .is_late_enough_to_quantize(quantize_tolerance_01)
+                {
+                    self.pending_value = sample.clone();
+                } else {
+                    self.last_value = sample.clone();
+                }
             }
+            self.core.advance_sample();
             if clearing {
-                *self.sequence.current_mut() = None;
+                self.core.set_current(None);
                 changed_this_frame = true;
             } else if self.last_value.is_some() {
-                *self.sequence.current_mut() = self.last_value.clone();
+                self.core.set_current(self.last_value.clone());
                 changed_this_frame = true;
             }
-            *out = self.sequence.current().clone();
+            *out = self.core.state.current();
         }
         if changed_this_frame {
-            self.io.save(&self.sequence);
+            self.io.save_state(&self.core.state);
         }
         &self.buf
     }
 }
 
-impl<X, S, T, C, N, I> KeyLooper<X, S, T, C, N, I>
+impl<X, S, T, C, N, U, L, Q, P, I> KeyLooper<X, S, T, C, N, U, L, Q, P, I>
 where
     X: Clone,
     S: SigT<Item = Option<X>>,
     T: SigT<Item = bool>,
     C: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    Q: SigT<Item = f32>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<X>,
 {
-    fn new(sig: S, tick: T, clearing: C, length: N, io: I) -> Sig<Self> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        sig: S,
+        tick: T,
+        clearing: C,
+        length: N,
+        undo_trig: U,
+        new_layer_trig: L,
+        quantize_tolerance_01: Q,
+        snapshot: P,
+        io: I,
+    ) -> Sig<Self> {
         Sig(KeyLooper {
             sig,
             last_value: None,
+            pending_value: None,
             tick,
             clearing,
             length,
-            sequence: io.load(),
+            undo_trig,
+            new_layer_trig,
+            quantize_tolerance_01,
+            snapshot,
+            core: LooperCore::new(io.load_state()),
             buf: Vec::new(),
             io,
         })
     }
 }
 
+type KeyLooperSig<X, S, T, C, N, U, L, Q, P, I> =
+    Sig<KeyLooper<X, S, T, C, N, U, L, Q, P, I>>;
+
 builder! {
     #[constructor = "key_looper"]
     #[constructor_doc = "A looper for key presses"]
     #[generic_setter_type_name = "X"]
     #[build_fn = "KeyLooper::new"]
-    #[build_ty = "Sig<KeyLooper<V, S, T, C, N, I>>"]
+    #[build_ty = "KeyLooperSig<V, S, T, C, N, U, L, Q, P, I>"]
     #[extra_generic("V", "Clone")]
     pub struct KeyLooperBuilder {
         #[generic_with_constraint = "SigT<Item = Option<V>>"]
@@ -193,10 +543,31 @@ builder! {
         #[generic_name = "C"]
         #[default = false]
         clearing: bool,
+        // The length of the top layer
         #[generic_with_constraint = "SigT<Item = u32>"]
         #[generic_name = "N"]
         #[default = 16]
         length: u32,
+        // Revert the changes made during the most recent pass over the top layer
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "U"]
+        #[default = false]
+        undo_trig: bool,
+        // Add a new empty layer whose length is the current value of `length`
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "L"]
+        #[default = false]
+        new_layer_trig: bool,
+        // Input received within this fraction of a step before a tick is recorded into the next
+        // step rather than the current one
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "Q"]
+        #[default = 0.0]
+        quantize_tolerance_01: f32,
+        #[generic_with_constraint = "SigT<Item = LooperSnapshot>"]
+        #[generic_name = "P"]
+        #[default = LooperSnapshot::None]
+        snapshot: LooperSnapshot,
         #[generic_with_constraint = "LooperIo<V>"]
         #[default = LooperIoNull]
         #[generic_name = "I"]
@@ -204,24 +575,33 @@ builder! {
     }
 }
 
-impl<X, S, T, C, N, I> KeyLooperBuilder<X, S, T, C, N, I>
+impl<X, S, T, C, N, U, L, Q, P, I>
+    KeyLooperBuilder<X, S, T, C, N, U, L, Q, P, I>
 where
     X: Clone + Serialize + for<'a> Deserialize<'a>,
     S: SigT<Item = Option<X>>,
     T: SigT<Item = bool>,
     C: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    Q: SigT<Item = f32>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<X>,
 {
     pub fn persist_with_name(
         self,
         name: impl AsRef<str>,
-    ) -> KeyLooperBuilder<X, S, T, C, N, LooperIoWithName> {
+    ) -> KeyLooperBuilder<X, S, T, C, N, U, L, Q, P, LooperIoWithName> {
         let Self {
             sig,
             trig,
             clearing,
             length,
+            undo_trig,
+            new_layer_trig,
+            quantize_tolerance_01,
+            snapshot,
             ..
         } = self;
         KeyLooperBuilder {
@@ -229,36 +609,49 @@ where
             trig,
             clearing,
             length,
+            undo_trig,
+            new_layer_trig,
+            quantize_tolerance_01,
+            snapshot,
             io: LooperIoWithName(format!("key_looper_{}", name.as_ref())),
         }
     }
 }
 
-pub struct ValueLooper<S, T, R, N, I>
+pub struct ValueLooper<S, T, R, N, U, L, P, I>
 where
     S: SigT,
     S::Item: Clone,
     T: SigT<Item = bool>,
     R: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<S::Item>,
 {
     sig: S,
     tick: T,
     recording: R,
     length: N,
-    sequence: Sequence<Option<S::Item>>,
+    undo_trig: U,
+    new_layer_trig: L,
+    snapshot: P,
+    core: LooperCore<S::Item>,
     buf: Vec<S::Item>,
     io: I,
 }
 
-impl<S, T, R, N, I> SigT for ValueLooper<S, T, R, N, I>
+impl<S, T, R, N, U, L, P, I> SigT for ValueLooper<S, T, R, N, U, L, P, I>
 where
     S: SigT,
     S::Item: Clone,
     T: SigT<Item = bool>,
     R: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<S::Item>,
 {
     type Item = S::Item;
@@ -269,24 +662,50 @@ where
         let tick = self.tick.sample(ctx);
         let recording = self.recording.sample(ctx);
         let length = self.length.sample(ctx);
+        let undo_trig = self.undo_trig.sample(ctx);
+        let new_layer_trig = self.new_layer_trig.sample(ctx);
+        let snapshot = self.snapshot.sample(ctx);
         let mut changed_this_frame = false;
-        for (sample, tick, recording, length) in izip! {
+        for (
+            sample,
+            tick,
+            recording,
+            length,
+            undo_trig,
+            new_layer_trig,
+            snapshot,
+        ) in izip! {
             sig.iter(),
             tick.iter(),
             recording.iter(),
             length.iter(),
+            undo_trig.iter(),
+            new_layer_trig.iter(),
+            snapshot.iter(),
         } {
-            self.sequence.resize_with(length as usize, || None);
+            if undo_trig && self.core.undo() {
+                changed_this_frame = true;
+            }
+            if new_layer_trig {
+                self.core.new_layer(length);
+                changed_this_frame = true;
+            }
+            if self.core.handle_snapshot(snapshot, &self.io) {
+                changed_this_frame = true;
+            }
+            self.core
+                .state
+                .top_layer_mut()
+                .resize_with(length as usize, || None);
             if tick {
-                self.sequence.tick();
+                self.core.tick();
             }
-            let stored = self.sequence.current_mut();
-            let out = match (recording, stored.clone()) {
+            let out = match (recording, self.core.state.current()) {
                 (true, _) | (_, None) => {
                     // Even if we're not recording, still record if there is no current stored
                     // value just so we have something to return.
                     if tick {
-                        *stored = Some(sample.clone());
+                        self.core.set_current(Some(sample.clone()));
                         changed_this_frame = true;
                     }
                     sample
@@ -296,41 +715,60 @@ where
             self.buf.push(out);
         }
         if changed_this_frame {
-            self.io.save(&self.sequence);
+            self.io.save_state(&self.core.state);
         }
         &self.buf
     }
 }
 
-impl<S, T, R, N, I> ValueLooper<S, T, R, N, I>
+impl<S, T, R, N, U, L, P, I> ValueLooper<S, T, R, N, U, L, P, I>
 where
     S: SigT,
     S::Item: Clone,
     T: SigT<Item = bool>,
     R: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<S::Item>,
 {
-    fn new(sig: S, tick: T, recording: R, length: N, io: I) -> Sig<Self> {
-        let sequence = io.load();
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        sig: S,
+        tick: T,
+        recording: R,
+        length: N,
+        undo_trig: U,
+        new_layer_trig: L,
+        snapshot: P,
+        io: I,
+    ) -> Sig<Self> {
+        let core = LooperCore::new(io.load_state());
         Sig(ValueLooper {
             sig,
             tick,
             recording,
             length,
-            sequence,
+            undo_trig,
+            new_layer_trig,
+            snapshot,
+            core,
             buf: Vec::new(),
             io,
         })
     }
 }
 
+type ValueLooperSig<S, T, R, N, U, L, P, I> =
+    Sig<ValueLooper<S, T, R, N, U, L, P, I>>;
+
 builder! {
     #[constructor = "value_looper"]
     #[constructor_doc = "A looper for values such as knob positions"]
     #[generic_setter_type_name = "X"]
     #[build_fn = "ValueLooper::new"]
-    #[build_ty = "Sig<ValueLooper<S, T, R, N, I>>"]
+    #[build_ty = "ValueLooperSig<S, T, R, N, U, L, P, I>"]
     pub struct ValueLooperBuilder {
         #[generic_with_constraint = "SigT"]
         #[generic_name = "S"]
@@ -341,10 +779,25 @@ builder! {
         #[generic_with_constraint = "SigT<Item = bool>"]
         #[generic_name = "R"]
         recording: _,
+        // The length of the top layer
         #[generic_with_constraint = "SigT<Item = u32>"]
         #[generic_name = "N"]
         #[default = 16]
         length: u32,
+        // Revert the changes made during the most recent pass over the top layer
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "U"]
+        #[default = false]
+        undo_trig: bool,
+        // Add a new empty layer whose length is the current value of `length`
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "L"]
+        #[default = false]
+        new_layer_trig: bool,
+        #[generic_with_constraint = "SigT<Item = LooperSnapshot>"]
+        #[generic_name = "P"]
+        #[default = LooperSnapshot::None]
+        snapshot: LooperSnapshot,
         #[generic_with_constraint = "LooperIo<S::Item>"]
         #[default = LooperIoNull]
         #[generic_name = "I"]
@@ -352,24 +805,30 @@ builder! {
     }
 }
 
-impl<S, T, R, N, I> ValueLooperBuilder<S, T, R, N, I>
+impl<S, T, R, N, U, L, P, I> ValueLooperBuilder<S, T, R, N, U, L, P, I>
 where
     S: SigT,
     S::Item: Clone + Serialize + for<'a> Deserialize<'a>,
     T: SigT<Item = bool>,
     R: SigT<Item = bool>,
     N: SigT<Item = u32>,
+    U: SigT<Item = bool>,
+    L: SigT<Item = bool>,
+    P: SigT<Item = LooperSnapshot>,
     I: LooperIo<S::Item>,
 {
     pub fn persist_with_name(
         self,
         name: impl AsRef<str>,
-    ) -> ValueLooperBuilder<S, T, R, N, LooperIoWithName> {
+    ) -> ValueLooperBuilder<S, T, R, N, U, L, P, LooperIoWithName> {
         let Self {
             sig,
             trig,
             recording,
             length,
+            undo_trig,
+            new_layer_trig,
+            snapshot,
             ..
         } = self;
         ValueLooperBuilder {
@@ -377,7 +836,214 @@ where
             trig,
             recording,
             length,
+            undo_trig,
+            new_layer_trig,
+            snapshot,
             io: LooperIoWithName(format!("value_looper_{}", name.as_ref())),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+
+    /// A signal which yields each of `values` in turn on consecutive samples.
+    fn sig_of<T: Clone + Default>(values: Vec<T>) -> impl SigT<Item = T> {
+        let mut values = values.into_iter();
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    /// A signal which is true on every `period`th sample, starting with the first.
+    fn ticks(period: usize, num_samples: usize) -> impl SigT<Item = bool> {
+        sig_of((0..num_samples).map(|i| i % period == 0).collect())
+    }
+
+    fn key_input(
+        num_samples: usize,
+        keys: &[(usize, u32)],
+    ) -> impl SigT<Item = Option<u32>> {
+        let mut values = vec![None; num_samples];
+        for &(i, key) in keys {
+            values[i] = Some(key);
+        }
+        sig_of(values)
+    }
+
+    fn run<S: SigT>(mut sig: S, num_samples: usize) -> Vec<S::Item> {
+        let ctx = SigCtx {
+            sample_rate_hz: 48_000.0,
+            batch_index: 0,
+            num_samples,
+        };
+        sig.sample(&ctx).iter().collect()
+    }
+
+    /// Stores state in memory and only implements the required methods of `LooperIo`.
+    #[derive(Default)]
+    struct MemoryIo {
+        sequence: RefCell<Option<Sequence<Option<u32>>>>,
+        snapshots: RefCell<HashMap<String, LooperState<Option<u32>>>>,
+    }
+
+    impl LooperIo<u32> for &MemoryIo {
+        fn load(&self) -> Sequence<Option<u32>> {
+            self.sequence
+                .borrow()
+                .clone()
+                .unwrap_or_else(|| Sequence::new_with(1, || None))
+        }
+
+        fn save(&self, sequence: &Sequence<Option<u32>>) {
+            *self.sequence.borrow_mut() = Some(sequence.clone());
+        }
+
+        fn save_snapshot(&self, name: &str, state: &LooperState<Option<u32>>) {
+            self.snapshots
+                .borrow_mut()
+                .insert(name.to_string(), state.clone());
+        }
+
+        fn load_snapshot(
+            &self,
+            name: &str,
+        ) -> Option<LooperState<Option<u32>>> {
+            self.snapshots.borrow().get(name).cloned()
+        }
+    }
+
+    #[test]
+    fn key_looper_records_and_plays_back() {
+        let n = 12;
+        // Steps last 2 samples and the loop is 2 steps long. Input is recorded into the step
+        // which starts at sample 2.
+        let looper = key_looper(key_input(n, &[(3, 7)]), ticks(2, n)).length(2);
+        assert_eq!(
+            run(looper.build(), n),
+            vec![
+                None,
+                None,
+                None,
+                Some(7),
+                None,
+                None,
+                Some(7),
+                Some(7),
+                None,
+                None,
+                Some(7),
+                Some(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_looper_clearing() {
+        let n = 8;
+        let clearing = sig_of((0..n).map(|i| i == 6).collect());
+        let looper = key_looper(key_input(n, &[(3, 7)]), ticks(2, n))
+            .length(2)
+            .clearing(clearing);
+        assert_eq!(
+            run(looper.build(), n),
+            vec![None, None, None, Some(7), None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn key_looper_undo() {
+        let n = 12;
+        let undo_trig = sig_of((0..n).map(|i| i == 8).collect());
+        let looper = key_looper(key_input(n, &[(3, 7)]), ticks(2, n))
+            .length(2)
+            .undo_trig(undo_trig);
+        let out = run(looper.build(), n);
+        assert_eq!(&out[6..8], &[Some(7), Some(7)]);
+        assert!(out[8..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn key_looper_quantizes_late_input() {
+        let n = 16;
+        // Steps are 4 samples long. Input in the last half of a step is moved to the next step,
+        // even if an earlier input was already recorded in the current step.
+        let looper = key_looper(key_input(n, &[(5, 1), (7, 2)]), ticks(4, n))
+            .length(16)
+            .quantize_tolerance_01(0.5);
+        let out = run(looper.build(), n);
+        assert_eq!(&out[4..8], &[None, Some(1), Some(1), Some(1)]);
+        assert_eq!(&out[8..12], &[Some(2); 4]);
+    }
+
+    #[test]
+    fn key_looper_measures_steps_from_first_tick() {
+        let n = 12;
+        // The first tick is on sample 2 and steps are 8 samples long. The time before the first
+        // tick isn't a step, so input just after the first tick isn't quantized.
+        let tick = sig_of((0..n).map(|i| i == 2 || i == 10).collect());
+        let looper = key_looper(key_input(n, &[(4, 1)]), tick)
+            .length(16)
+            .quantize_tolerance_01(0.5);
+        let out = run(looper.build(), n);
+        assert_eq!(out[4], Some(1));
+    }
+
+    #[test]
+    fn key_looper_snapshots() {
+        let n = 16;
+        let io = MemoryIo::default();
+        let snapshot = sig_of(
+            (0..n)
+                .map(|i| match i {
+                    4 => LooperSnapshot::save("a"),
+                    12 => LooperSnapshot::load("a"),
+                    _ => LooperSnapshot::None,
+                })
+                .collect(),
+        );
+        let clearing = sig_of((0..n).map(|i| i == 8).collect());
+        let looper = key_looper(key_input(n, &[(1, 7)]), ticks(4, n))
+            .length(1)
+            .clearing(clearing)
+            .snapshot(snapshot)
+            .io(&io);
+        let out = run(looper.build(), n);
+        assert_eq!(&out[4..8], &[Some(7); 4]);
+        assert_eq!(&out[8..12], &[None; 4]);
+        assert_eq!(&out[12..16], &[Some(7); 4]);
+        // Only the top layer is saved by an io which only implements `save`.
+        assert_eq!(
+            io.sequence.borrow().as_ref().unwrap().sequence,
+            vec![Some(7)]
+        );
+    }
+
+    #[test]
+    fn value_looper_records_and_plays_back() {
+        let n = 6;
+        let looper = value_looper(
+            sig_of(vec![1.0, 2.0, 0.0, 0.0, 0.0, 0.0]),
+            ticks(1, n),
+            sig_of((0..n).map(|i| i < 2).collect()),
+        )
+        .length(2);
+        assert_eq!(run(looper.build(), n), vec![1.0, 2.0, 1.0, 2.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn value_looper_only_records_while_recording() {
+        let n = 8;
+        let recording = sig_of((0..n).map(|i| i < 2 || i >= 6).collect());
+        let looper = value_looper(
+            sig_of(vec![1.0, 2.0, 9.0, 9.0, 9.0, 9.0, 3.0, 4.0]),
+            ticks(1, n),
+            recording,
+        )
+        .length(2);
+        assert_eq!(
+            run(looper.build(), n),
+            vec![1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 3.0, 4.0]
+        );
+    }
+}
@@ -0,0 +1,723 @@
+//! A compact notation for rhythms and melodies, based on the mini-notation from TidalCycles.
+//!
+//! A pattern describes what happens during a single cycle. The elements of a sequence divide the
+//! cycle evenly between them. Supported syntax:
+//!  - `bd sd`: a sequence of two events, each lasting half a cycle
+//!  - `~`: a rest
+//!  - `[sd sd]`: a group which subdivides the time of a single step
+//!  - `[c3, e3, g3]`: events which play at the same time
+//!  - `<hh oh>`: alternation, playing one element per cycle
+//!  - `hh*2`: repetition, playing an element multiple times within its step
+//!  - `bd!3`: replication, equivalent to `bd bd bd`
+//!  - `bd(3,8)` or `bd(3,8,2)`: a Euclidean rhythm of 3 pulses spread over 8 steps, optionally
+//!    rotated left by 2 steps
+//!
+//! Patterns are played back by passing them a trigger which marks the start of each cycle. The
+//! time between cycle triggers is measured and used to place events within the next cycle, so
+//! only events at the very start of the first cycle are played.
+
+use crate::rhythm::euclidean_rhythm;
+use caw_core::{Buf, Sig, SigT};
+use caw_keyboard::{KeyEvent, KeyEvents, Note};
+use std::{fmt, str::FromStr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Index of the byte in the pattern string where the error was detected
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Atom(String),
+    Rest,
+    /// Elements which divide the time evenly between them
+    Sequence(Vec<Node>),
+    /// Elements which all play at the same time
+    Stack(Vec<Node>),
+    /// Elements which play on successive cycles
+    Alternation(Vec<Node>),
+    /// An element played multiple times within its time span
+    Fast(Box<Node>, u32),
+    Euclid {
+        node: Box<Node>,
+        pulses: u32,
+        steps: u32,
+        rotation: u32,
+    },
+}
+
+impl Node {
+    fn query(
+        &self,
+        cycle: u64,
+        start_01: f64,
+        duration_01: f64,
+        events: &mut Vec<PatternEvent>,
+    ) {
+        match self {
+            Self::Atom(value) => events.push(PatternEvent {
+                start_01,
+                duration_01,
+                value: value.clone(),
+            }),
+            Self::Rest => (),
+            Self::Sequence(nodes) => {
+                let step_duration_01 = duration_01 / nodes.len() as f64;
+                for (i, node) in nodes.iter().enumerate() {
+                    node.query(
+                        cycle,
+                        start_01 + (i as f64 * step_duration_01),
+                        step_duration_01,
+                        events,
+                    );
+                }
+            }
+            Self::Stack(nodes) => {
+                for node in nodes {
+                    node.query(cycle, start_01, duration_01, events);
+                }
+            }
+            Self::Alternation(nodes) => {
+                let len = nodes.len() as u64;
+                // Nested alternations advance each time they are selected.
+                nodes[(cycle % len) as usize].query(
+                    cycle / len,
+                    start_01,
+                    duration_01,
+                    events,
+                );
+            }
+            Self::Fast(node, n) => {
+                let step_duration_01 = duration_01 / *n as f64;
+                for i in 0..*n {
+                    node.query(
+                        (cycle * *n as u64) + i as u64,
+                        start_01 + (i as f64 * step_duration_01),
+                        step_duration_01,
+                        events,
+                    );
+                }
+            }
+            Self::Euclid {
+                node,
+                pulses,
+                steps,
+                rotation,
+            } => {
+                let rhythm = euclidean_rhythm(*pulses, *steps);
+                let step_duration_01 = duration_01 / *steps as f64;
+                for i in 0..rhythm.len() {
+                    if rhythm[(i + *rotation as usize) % rhythm.len()] {
+                        node.query(
+                            cycle,
+                            start_01 + (i as f64 * step_duration_01),
+                            step_duration_01,
+                            events,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn for_each_atom<F: FnMut(&str)>(&self, f: &mut F) {
+        match self {
+            Self::Atom(value) => f(value),
+            Self::Rest => (),
+            Self::Sequence(nodes)
+            | Self::Stack(nodes)
+            | Self::Alternation(nodes) => {
+                for node in nodes {
+                    node.for_each_atom(f);
+                }
+            }
+            Self::Fast(node, _) | Self::Euclid { node, .. } => {
+                node.for_each_atom(f)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            self.error(format!("Expected '{}'", expected))
+        }
+    }
+
+    fn is_word_char(c: char) -> bool {
+        c.is_alphanumeric() || matches!(c, '#' | '.' | ':' | '-' | '_')
+    }
+
+    fn word(&mut self) -> &'a str {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !Self::is_word_char(c) {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+        &self.s[start..self.position]
+    }
+
+    fn number(&mut self) -> Result<u32, ParseError> {
+        self.skip_whitespace();
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            self.position += 1;
+        }
+        match self.s[start..self.position].parse() {
+            Ok(n) => Ok(n),
+            Err(_) => {
+                self.position = start;
+                self.error("Expected a number")
+            }
+        }
+    }
+
+    /// Parses sequences separated by commas until `end` (or the end of the string if `end` is
+    /// `None`).
+    fn stack(&mut self, end: Option<char>) -> Result<Node, ParseError> {
+        let mut sequences = vec![self.sequence(end)?];
+        while self.peek() == Some(',') {
+            self.position += 1;
+            sequences.push(self.sequence(end)?);
+        }
+        Ok(if sequences.len() == 1 {
+            sequences.pop().unwrap()
+        } else {
+            Node::Stack(sequences)
+        })
+    }
+
+    fn sequence(&mut self, end: Option<char>) -> Result<Node, ParseError> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => break,
+                c if c == end => break,
+                None => return self.error("Unexpected end of pattern"),
+                Some(_) => {
+                    let node = self.step()?;
+                    if self.peek() == Some('!') {
+                        self.position += 1;
+                        let n = self.number()?;
+                        for _ in 0..n {
+                            nodes.push(node.clone());
+                        }
+                    } else {
+                        nodes.push(node);
+                    }
+                }
+            }
+        }
+        match nodes.len() {
+            0 => self.error("Empty sequence"),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Sequence(nodes)),
+        }
+    }
+
+    /// Parses a single step of a sequence along with any modifiers.
+    fn step(&mut self) -> Result<Node, ParseError> {
+        let mut node = match self.peek() {
+            Some('~') => {
+                self.position += 1;
+                Node::Rest
+            }
+            Some('[') => {
+                self.position += 1;
+                let node = self.stack(Some(']'))?;
+                self.expect(']')?;
+                node
+            }
+            Some('<') => {
+                self.position += 1;
+                let node = match self.sequence(Some('>'))? {
+                    Node::Sequence(nodes) => Node::Alternation(nodes),
+                    node => node,
+                };
+                self.expect('>')?;
+                node
+            }
+            Some(c) if Self::is_word_char(c) => {
+                Node::Atom(self.word().to_string())
+            }
+            Some(c) => return self.error(format!("Unexpected '{}'", c)),
+            None => return self.error("Unexpected end of pattern"),
+        };
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.position += 1;
+                    let n = self.number()?;
+                    if n == 0 {
+                        return self.error("Repetition count must be positive");
+                    }
+                    node = Node::Fast(Box::new(node), n);
+                }
+                Some('(') => {
+                    self.position += 1;
+                    let pulses = self.number()?;
+                    self.expect(',')?;
+                    let steps = self.number()?;
+                    if steps == 0 {
+                        return self.error("Number of steps must be positive");
+                    }
+                    self.skip_whitespace();
+                    let rotation = if self.peek() == Some(',') {
+                        self.position += 1;
+                        self.number()?
+                    } else {
+                        0
+                    };
+                    self.expect(')')?;
+                    node = Node::Euclid {
+                        node: Box::new(node),
+                        pulses,
+                        steps,
+                        rotation,
+                    };
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+}
+
+/// An event produced by a pattern during a single cycle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatternEvent {
+    /// When the event starts, as a fraction of the cycle
+    pub start_01: f64,
+    /// How long the event lasts, as a fraction of the cycle
+    pub duration_01: f64,
+    pub value: String,
+}
+
+/// A parsed mini-notation pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    root: Node,
+}
+
+impl FromStr for Pattern {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { s, position: 0 };
+        let root = parser.stack(None)?;
+        if parser.position < s.len() {
+            return parser.error("Unexpected character");
+        }
+        Ok(Self { root })
+    }
+}
+
+/// Parse a pattern written in mini-notation.
+pub fn mini_notation(s: &str) -> Result<Pattern, ParseError> {
+    s.parse()
+}
+
+/// Parse an atom as a note. Notes are written as a letter followed by any number of sharps ('#'
+/// or 's') or flats ('b' or 'f') and an optional octave (defaulting to 4), e.g. "c3", "f#2",
+/// "eb". Notes can also be written as midi indices, or in the format used by `Note`'s `FromStr`
+/// implementation.
+pub fn parse_note(s: &str) -> Option<Note> {
+    if let Ok(midi_index) = s.parse::<u8>() {
+        return (midi_index <= 127).then(|| Note::from_midi_index(midi_index));
+    }
+    if let Ok(note) = s.parse::<Note>() {
+        return Some(note);
+    }
+    let mut chars = s.char_indices().peekable();
+    let (_, name) = chars.next()?;
+    let mut semitones: i32 = match name.to_ascii_lowercase() {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let mut octave_start = s.len();
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            '#' | 's' => semitones += 1,
+            'b' | 'f' => semitones -= 1,
+            _ => {
+                octave_start = i;
+                break;
+            }
+        }
+        chars.next();
+    }
+    let octave: i32 = if octave_start == s.len() {
+        4
+    } else {
+        s[octave_start..].parse().ok()?
+    };
+    let midi_index = ((octave + 1) * 12) + semitones;
+    if (0..=127).contains(&midi_index) {
+        Some(Note::from_midi_index(midi_index as u8))
+    } else {
+        None
+    }
+}
+
+/// Plays back the events of a pattern in time with a cycle trigger.
+struct Player {
+    pattern: Pattern,
+    /// Only play events with this value
+    value: Option<String>,
+    cycle: Option<u64>,
+    samples_since_cycle: u64,
+    /// Number of samples in the most recent complete cycle
+    cycle_period: Option<u64>,
+    events: Vec<PatternEvent>,
+    next_event_index: usize,
+    /// Events which have started but not yet ended, along with the sample on which they end
+    active: Vec<(u64, PatternEvent)>,
+}
+
+impl Player {
+    fn new(pattern: Pattern, value: Option<String>) -> Self {
+        Self {
+            pattern,
+            value,
+            cycle: None,
+            samples_since_cycle: 0,
+            cycle_period: None,
+            events: Vec::new(),
+            next_event_index: 0,
+            active: Vec::new(),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    /// Advance by a single sample, calling `f` with `true` for each event which starts on this
+    /// sample and `false` for each event which ends on this sample. Events are ended before new
+    /// events are started.
+    fn tick<F: FnMut(bool, &PatternEvent)>(
+        &mut self,
+        cycle_trig: bool,
+        mut f: F,
+    ) {
+        if cycle_trig {
+            if self.cycle.is_some() {
+                self.cycle_period = Some(self.samples_since_cycle + 1);
+            }
+            for (_, event) in self.active.drain(..) {
+                f(false, &event);
+            }
+            let cycle = self.cycle.map_or(0, |cycle| cycle + 1);
+            self.cycle = Some(cycle);
+            self.samples_since_cycle = 0;
+            self.events = self.pattern.events(cycle);
+            if let Some(value) = self.value.as_ref() {
+                self.events.retain(|event| &event.value == value);
+            }
+            self.next_event_index = 0;
+        } else if self.cycle.is_some() {
+            self.samples_since_cycle += 1;
+        } else {
+            return;
+        }
+        let now = self.samples_since_cycle;
+        self.active.retain(|(end, event)| {
+            let ended = *end <= now;
+            if ended {
+                f(false, event);
+            }
+            !ended
+        });
+        while let Some(event) = self.events.get(self.next_event_index) {
+            let (start, end) = match self.cycle_period {
+                Some(cycle_period) => {
+                    let cycle_period = cycle_period as f64;
+                    (
+                        (event.start_01 * cycle_period).round() as u64,
+                        ((event.start_01 + event.duration_01) * cycle_period)
+                            .round() as u64,
+                    )
+                }
+                // Until a complete cycle has been observed its duration is unknown, so only
+                // events at the very start of the cycle can be placed.
+                None if event.start_01 == 0.0 => (0, u64::MAX),
+                None => break,
+            };
+            if start > now {
+                break;
+            }
+            f(true, event);
+            self.active.push((end.max(start + 1), event.clone()));
+            self.next_event_index += 1;
+        }
+    }
+}
+
+impl Pattern {
+    /// The events which play during a given cycle, ordered by their start time.
+    pub fn events(&self, cycle: u64) -> Vec<PatternEvent> {
+        let mut events = Vec::new();
+        self.root.query(cycle, 0.0, 1.0, &mut events);
+        events.sort_by(|a, b| a.start_01.total_cmp(&b.start_01));
+        events
+    }
+
+    fn player_sig<T, C, F>(
+        &self,
+        value: Option<&str>,
+        mut cycle_trig: C,
+        mut f: F,
+    ) -> Sig<impl SigT<Item = T>>
+    where
+        T: Clone,
+        C: SigT<Item = bool>,
+        F: FnMut(&mut Player, bool) -> T,
+    {
+        let mut player = Player::new(self.clone(), value.map(String::from));
+        Sig::from_buf_fn(move |ctx, buf| {
+            buf.clear();
+            for cycle_trig in cycle_trig.sample(ctx).iter() {
+                buf.push(f(&mut player, cycle_trig));
+            }
+        })
+    }
+
+    fn trig_inner<C>(
+        &self,
+        value: Option<&str>,
+        cycle_trig: C,
+    ) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.player_sig(value, cycle_trig, |player, cycle_trig| {
+            let mut trig = false;
+            player.tick(cycle_trig, |start, _| trig |= start);
+            trig
+        })
+    }
+
+    fn gate_inner<C>(
+        &self,
+        value: Option<&str>,
+        cycle_trig: C,
+    ) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.player_sig(value, cycle_trig, |player, cycle_trig| {
+            let (mut started, mut ended) = (false, false);
+            player.tick(cycle_trig, |start, _| {
+                if start {
+                    started = true;
+                } else {
+                    ended = true;
+                }
+            });
+            // When one event ends on the same sample that another starts, close the gate for a
+            // sample so the start of the new event can be detected.
+            player.is_active() && !(started && ended)
+        })
+    }
+
+    /// A trigger at the start of each event. `cycle_trig` marks the start of each cycle.
+    pub fn trig<C>(&self, cycle_trig: C) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.trig_inner(None, cycle_trig)
+    }
+
+    /// A trigger at the start of each event with a given value. Use this to get a separate
+    /// trigger for each drum in a drum pattern.
+    pub fn trig_value<C>(
+        &self,
+        value: &str,
+        cycle_trig: C,
+    ) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.trig_inner(Some(value), cycle_trig)
+    }
+
+    /// A gate which is open for the duration of each event.
+    pub fn gate<C>(&self, cycle_trig: C) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.gate_inner(None, cycle_trig)
+    }
+
+    /// A gate which is open for the duration of each event with a given value.
+    pub fn gate_value<C>(
+        &self,
+        value: &str,
+        cycle_trig: C,
+    ) -> Sig<impl SigT<Item = bool>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.gate_inner(Some(value), cycle_trig)
+    }
+
+    /// Key presses and releases for each event, treating the value of each event as a note (see
+    /// `parse_note`). Events whose values aren't notes are ignored.
+    pub fn key_events<C>(
+        &self,
+        cycle_trig: C,
+    ) -> Sig<impl SigT<Item = KeyEvents>>
+    where
+        C: SigT<Item = bool>,
+    {
+        self.root.for_each_atom(&mut |value| {
+            if parse_note(value).is_none() {
+                log::warn!(
+                    "Ignoring pattern value which isn't a note: {}",
+                    value
+                );
+            }
+        });
+        self.player_sig(None, cycle_trig, |player, cycle_trig| {
+            let mut key_events = KeyEvents::empty();
+            player.tick(cycle_trig, |pressed, event| {
+                if let Some(note) = parse_note(&event.value) {
+                    key_events.push(KeyEvent {
+                        note,
+                        pressed,
+                        velocity_01: 1.0,
+                    });
+                }
+            });
+            key_events
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn onsets(pattern: &str, cycle: u64) -> Vec<(f64, String)> {
+        mini_notation(pattern)
+            .unwrap()
+            .events(cycle)
+            .into_iter()
+            .map(|event| (event.start_01, event.value))
+            .collect()
+    }
+
+    fn ons(v: &[(f64, &str)]) -> Vec<(f64, String)> {
+        v.iter().map(|&(t, s)| (t, s.to_string())).collect()
+    }
+
+    #[test]
+    fn subdivision_rest_alternation() {
+        let pattern = "bd ~ [sd sd] <hh oh>";
+        assert_eq!(
+            onsets(pattern, 0),
+            ons(&[(0.0, "bd"), (0.5, "sd"), (0.625, "sd"), (0.75, "hh")])
+        );
+        assert_eq!(onsets(pattern, 1)[3], (0.75, "oh".to_string()));
+    }
+
+    #[test]
+    fn repetition_and_replication() {
+        assert_eq!(
+            onsets("c3 e3 g3*2 ~", 0),
+            ons(&[(0.0, "c3"), (0.25, "e3"), (0.5, "g3"), (0.625, "g3")])
+        );
+        assert_eq!(onsets("a!3 b", 0), onsets("a a a b", 0));
+        assert_eq!(onsets("<a b>*2", 0), ons(&[(0.0, "a"), (0.5, "b")]));
+    }
+
+    #[test]
+    fn euclidean_syntax() {
+        assert_eq!(
+            onsets("bd(3,8)", 0),
+            ons(&[(0.0, "bd"), (0.375, "bd"), (0.75, "bd")])
+        );
+        assert_eq!(
+            onsets("bd(3,8,2)", 0),
+            ons(&[(0.125, "bd"), (0.5, "bd"), (0.75, "bd")])
+        );
+    }
+
+    #[test]
+    fn stack() {
+        assert_eq!(
+            onsets("[c3, e3] g3", 0),
+            ons(&[(0.0, "c3"), (0.0, "e3"), (0.5, "g3")])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(mini_notation("[a b").is_err());
+        assert!(mini_notation("a ]").is_err());
+        assert!(mini_notation("a(3)").is_err());
+        assert!(mini_notation("").is_err());
+        assert_eq!(mini_notation("a $").unwrap_err().position, 2);
+    }
+
+    #[test]
+    fn notes() {
+        assert_eq!(parse_note("c3").unwrap().to_midi_index(), 48);
+        assert_eq!(parse_note("f#2").unwrap().to_midi_index(), 42);
+        assert_eq!(parse_note("eb").unwrap().to_midi_index(), 63);
+        assert_eq!(parse_note("C#:4").unwrap().to_midi_index(), 61);
+        assert_eq!(parse_note("60").unwrap().to_midi_index(), 60);
+        assert!(parse_note("bd").is_none());
+    }
+}
@@ -0,0 +1,564 @@
+//! Generators and processors for trigger signals. Each of these is a filter which is applied to a
+//! clock signal, e.g. `clock.filter(euclidean().pulses(3).steps(8))`. Clock signals may be
+//! triggers or gates, as only their rising edges are considered.
+
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Filter, GateToTrigRisingEdge, Sig, SigCtx, SigT};
+use itertools::izip;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+/// Returns a sequence of `steps` values with `pulses` of them set to true, spread out as evenly
+/// as possible using Bjorklund's algorithm. E.g. 3 pulses in 8 steps gives `x..x..x.`.
+pub fn euclidean_rhythm(pulses: u32, steps: u32) -> Vec<bool> {
+    let pulses = pulses.min(steps) as usize;
+    let steps = steps as usize;
+    let mut a = vec![vec![true]; pulses];
+    let mut b = vec![vec![false]; steps - pulses];
+    while b.len() > 1 && !a.is_empty() {
+        let n = a.len().min(b.len());
+        let remainder = if a.len() > n {
+            a[n..].to_vec()
+        } else {
+            b[n..].to_vec()
+        };
+        a.truncate(n);
+        for (a, b) in a.iter_mut().zip(b.iter()) {
+            a.extend_from_slice(b);
+        }
+        b = remainder;
+    }
+    a.concat().into_iter().chain(b.concat()).collect()
+}
+
+/// Measures the number of samples between consecutive triggers.
+#[derive(Default)]
+struct PeriodMeter {
+    samples_since_trig: Option<u64>,
+    period: Option<u64>,
+}
+
+impl PeriodMeter {
+    /// Call once per sample.
+    fn tick(&mut self, trig: bool) {
+        if trig {
+            if let Some(samples_since_trig) = self.samples_since_trig {
+                self.period = Some(samples_since_trig + 1);
+            }
+            self.samples_since_trig = Some(0);
+        } else if let Some(samples_since_trig) =
+            self.samples_since_trig.as_mut()
+        {
+            *samples_since_trig += 1;
+        }
+    }
+}
+
+builder! {
+    #[constructor = "euclidean"]
+    #[constructor_doc = "On each clock pulse advance through a Euclidean rhythm, triggering on its pulses"]
+    #[generic_setter_type_name = "X"]
+    pub struct EuclideanBuilder {
+        // The number of clock pulses in each repetition of the rhythm
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "N"]
+        #[default = 16]
+        steps: u32,
+        // The number of triggers in each repetition of the rhythm
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "P"]
+        #[default = 4]
+        pulses: u32,
+        // Rotate the rhythm to the left by this many steps
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "R"]
+        #[default = 0]
+        rotation: u32,
+        // Return to the first step of the rhythm
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "T"]
+        #[default = false]
+        reset_trig: bool,
+    }
+}
+
+impl<N, P, R, T> Filter for EuclideanBuilder<N, P, R, T>
+where
+    N: SigT<Item = u32>,
+    P: SigT<Item = u32>,
+    R: SigT<Item = u32>,
+    T: SigT<Item = bool>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = Euclidean<S, N, P, R, T>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Euclidean {
+            props: self,
+            clock: Sig(sig).gate_to_trig_rising_edge().0,
+            rhythm: Vec::new(),
+            pulses: 0,
+            step_index: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Euclidean<S, N, P, R, T>
+where
+    S: SigT<Item = bool>,
+    N: SigT<Item = u32>,
+    P: SigT<Item = u32>,
+    R: SigT<Item = u32>,
+    T: SigT<Item = bool>,
+{
+    props: EuclideanBuilder<N, P, R, T>,
+    clock: GateToTrigRisingEdge<S>,
+    /// Cached so it's only recomputed when the number of steps or pulses changes
+    rhythm: Vec<bool>,
+    pulses: u32,
+    step_index: Option<u64>,
+    buf: Vec<bool>,
+}
+
+impl<S, N, P, R, T> SigT for Euclidean<S, N, P, R, T>
+where
+    S: SigT<Item = bool>,
+    N: SigT<Item = u32>,
+    P: SigT<Item = u32>,
+    R: SigT<Item = u32>,
+    T: SigT<Item = bool>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let clock = self.clock.sample(ctx);
+        let steps = self.props.steps.sample(ctx);
+        let pulses = self.props.pulses.sample(ctx);
+        let rotation = self.props.rotation.sample(ctx);
+        let reset_trig = self.props.reset_trig.sample(ctx);
+        for (clock, steps, pulses, rotation, reset_trig) in izip! {
+            clock.iter(),
+            steps.iter(),
+            pulses.iter(),
+            rotation.iter(),
+            reset_trig.iter(),
+        } {
+            if reset_trig {
+                self.step_index = None;
+            }
+            let steps = steps.max(1);
+            if steps as usize != self.rhythm.len() || pulses != self.pulses {
+                self.rhythm = euclidean_rhythm(pulses, steps);
+                self.pulses = pulses;
+            }
+            let out = if clock {
+                let step_index = self.step_index.map_or(0, |i| i + 1);
+                self.step_index = Some(step_index);
+                self.rhythm
+                    [((step_index + rotation as u64) % steps as u64) as usize]
+            } else {
+                false
+            };
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+builder! {
+    #[constructor = "clock_multiply"]
+    #[constructor_doc = "Trigger a given number of times evenly spaced between each pair of clock pulses"]
+    #[generic_setter_type_name = "X"]
+    pub struct ClockMultiplyBuilder {
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "M"]
+        #[default = 2]
+        multiplier: u32,
+    }
+}
+
+impl<M> Filter for ClockMultiplyBuilder<M>
+where
+    M: SigT<Item = u32>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = ClockMultiply<S, M>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        ClockMultiply {
+            props: self,
+            clock: Sig(sig).gate_to_trig_rising_edge().0,
+            period_meter: PeriodMeter::default(),
+            num_trigs_since_clock: 0,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct ClockMultiply<S, M>
+where
+    S: SigT<Item = bool>,
+    M: SigT<Item = u32>,
+{
+    props: ClockMultiplyBuilder<M>,
+    clock: GateToTrigRisingEdge<S>,
+    period_meter: PeriodMeter,
+    num_trigs_since_clock: u32,
+    buf: Vec<bool>,
+}
+
+impl<S, M> SigT for ClockMultiply<S, M>
+where
+    S: SigT<Item = bool>,
+    M: SigT<Item = u32>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let clock = self.clock.sample(ctx);
+        let multiplier = self.props.multiplier.sample(ctx);
+        for (clock, multiplier) in izip! {
+            clock.iter(),
+            multiplier.iter(),
+        } {
+            self.period_meter.tick(clock);
+            let out = if clock {
+                self.num_trigs_since_clock = 1;
+                true
+            } else if let (Some(period), Some(samples_since_clock)) = (
+                self.period_meter.period,
+                self.period_meter.samples_since_trig,
+            ) {
+                // Trigger when the time since the clock reaches the time of the next
+                // subdivision. Stop after the last subdivision in case the clock slows down.
+                let next_trig_sample = (self.num_trigs_since_clock as u64
+                    * period)
+                    / multiplier.max(1) as u64;
+                if self.num_trigs_since_clock < multiplier
+                    && samples_since_clock >= next_trig_sample
+                {
+                    self.num_trigs_since_clock += 1;
+                    true
+                } else {
+                    false
+                }
+            } else {
+                false
+            };
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+builder! {
+    #[constructor = "swing"]
+    #[constructor_doc = "Delay every second clock pulse to give a shuffled feel"]
+    #[generic_setter_type_name = "X"]
+    pub struct SwingBuilder {
+        // How far to delay every second pulse, as a fraction of the time between pulses. 0 leaves
+        // the clock unchanged and 1/3 gives a triplet feel.
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "A"]
+        #[default = 0.0]
+        amount_01: f32,
+    }
+}
+
+impl<A> Filter for SwingBuilder<A>
+where
+    A: SigT<Item = f32>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = Swing<S, A>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Swing {
+            props: self,
+            clock: Sig(sig).gate_to_trig_rising_edge().0,
+            period_meter: PeriodMeter::default(),
+            next_pulse_odd: false,
+            delayed_trig_remaining_samples: None,
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Swing<S, A>
+where
+    S: SigT<Item = bool>,
+    A: SigT<Item = f32>,
+{
+    props: SwingBuilder<A>,
+    clock: GateToTrigRisingEdge<S>,
+    period_meter: PeriodMeter,
+    /// Whether the next pulse is an odd one (counting from 0). Odd pulses are delayed.
+    next_pulse_odd: bool,
+    delayed_trig_remaining_samples: Option<u64>,
+    buf: Vec<bool>,
+}
+
+impl<S, A> SigT for Swing<S, A>
+where
+    S: SigT<Item = bool>,
+    A: SigT<Item = f32>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let clock = self.clock.sample(ctx);
+        let amount_01 = self.props.amount_01.sample(ctx);
+        for (clock, amount_01) in izip! {
+            clock.iter(),
+            amount_01.iter(),
+        } {
+            self.period_meter.tick(clock);
+            let mut out = false;
+            if let Some(remaining) = self.delayed_trig_remaining_samples {
+                if remaining == 0 {
+                    out = true;
+                    self.delayed_trig_remaining_samples = None;
+                } else {
+                    self.delayed_trig_remaining_samples = Some(remaining - 1);
+                }
+            }
+            if clock {
+                // Fire any delayed trigger which hasn't happened yet so it isn't lost.
+                if self.delayed_trig_remaining_samples.take().is_some() {
+                    out = true;
+                }
+                let odd = self.next_pulse_odd;
+                self.next_pulse_odd = !odd;
+                let delay = self.period_meter.period.map_or(0, |period| {
+                    (period as f32 * amount_01.clamp(0.0, 1.0)) as u64
+                });
+                if !odd || delay == 0 {
+                    out = true;
+                } else {
+                    self.delayed_trig_remaining_samples = Some(delay - 1);
+                }
+            }
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+builder! {
+    #[constructor = "humanize"]
+    #[constructor_doc = "Delay each clock pulse by a small random amount of time"]
+    #[generic_setter_type_name = "X"]
+    pub struct HumanizeBuilder {
+        // The maximum delay applied to each pulse
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "J"]
+        #[default = 0.01]
+        jitter_s: f32,
+    }
+}
+
+impl<J> Filter for HumanizeBuilder<J>
+where
+    J: SigT<Item = f32>,
+{
+    type ItemIn = bool;
+
+    type Out<S>
+        = Humanize<S, J>
+    where
+        S: SigT<Item = Self::ItemIn>;
+
+    fn into_sig<S>(self, sig: S) -> Self::Out<S>
+    where
+        S: SigT<Item = Self::ItemIn>,
+    {
+        Humanize {
+            props: self,
+            clock: Sig(sig).gate_to_trig_rising_edge().0,
+            rng: StdRng::from_os_rng(),
+            pending_remaining_samples: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct Humanize<S, J>
+where
+    S: SigT<Item = bool>,
+    J: SigT<Item = f32>,
+{
+    props: HumanizeBuilder<J>,
+    clock: GateToTrigRisingEdge<S>,
+    rng: StdRng,
+    /// The number of samples until each delayed pulse
+    pending_remaining_samples: Vec<u64>,
+    buf: Vec<bool>,
+}
+
+impl<S, J> SigT for Humanize<S, J>
+where
+    S: SigT<Item = bool>,
+    J: SigT<Item = f32>,
+{
+    type Item = bool;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let clock = self.clock.sample(ctx);
+        let jitter_s = self.props.jitter_s.sample(ctx);
+        for (clock, jitter_s) in izip! {
+            clock.iter(),
+            jitter_s.iter(),
+        } {
+            if clock {
+                let delay_s = self.rng.random::<f32>() * jitter_s.max(0.0);
+                self.pending_remaining_samples
+                    .push((delay_s * ctx.sample_rate_hz) as u64);
+            }
+            let mut out = false;
+            self.pending_remaining_samples.retain_mut(|remaining| {
+                if *remaining == 0 {
+                    out = true;
+                    false
+                } else {
+                    *remaining -= 1;
+                    true
+                }
+            });
+            self.buf.push(out);
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE_HZ: f32 = 1000.0;
+
+    /// A clock which pulses for a single sample at each of `pulses`.
+    fn clock(
+        pulses: &[usize],
+        num_samples: usize,
+    ) -> Sig<impl SigT<Item = bool>> {
+        let mut values = (0..num_samples).map(|i| pulses.contains(&i));
+        Sig::from_fn(move |_| values.next().unwrap_or_default())
+    }
+
+    /// The indices of the samples on which `sig` is true.
+    fn trig_indices<S: SigT<Item = bool>>(
+        mut sig: S,
+        num_samples: usize,
+    ) -> Vec<usize> {
+        let ctx = SigCtx {
+            sample_rate_hz: SAMPLE_RATE_HZ,
+            batch_index: 0,
+            num_samples,
+        };
+        sig.sample(&ctx)
+            .iter()
+            .enumerate()
+            .filter_map(|(i, trig)| trig.then_some(i))
+            .collect()
+    }
+
+    #[test]
+    fn euclid() {
+        let to_str = |v: Vec<bool>| {
+            v.into_iter()
+                .map(|b| if b { 'x' } else { '.' })
+                .collect::<String>()
+        };
+        assert_eq!(to_str(euclidean_rhythm(3, 8)), "x..x..x.");
+        assert_eq!(to_str(euclidean_rhythm(5, 8)), "x.xx.xx.");
+        assert_eq!(to_str(euclidean_rhythm(4, 4)), "xxxx");
+        assert_eq!(to_str(euclidean_rhythm(0, 3)), "...");
+    }
+
+    #[test]
+    fn clock_multiply_subdivides_after_first_period() {
+        let pulses = [0, 12, 24];
+        let sig = clock(&pulses, 36).filter(clock_multiply().multiplier(3));
+        // The period isn't known until the second pulse, then each period is split into 3.
+        assert_eq!(trig_indices(sig, 36), vec![0, 12, 16, 20, 24, 28, 32]);
+    }
+
+    #[test]
+    fn clock_multiply_by_one_passes_clock_through() {
+        let pulses = [0, 10, 20, 30];
+        let sig = clock(&pulses, 40).filter(clock_multiply().multiplier(1));
+        assert_eq!(trig_indices(sig, 40), pulses);
+    }
+
+    #[test]
+    fn clock_multiply_stops_when_clock_slows_down() {
+        // The clock slows from a period of 8 to 20. Only the subdivisions of the previous
+        // period are produced rather than continuing until the next pulse.
+        let pulses = [0, 8, 28];
+        let sig = clock(&pulses, 32).filter(clock_multiply().multiplier(2));
+        assert_eq!(trig_indices(sig, 32), vec![0, 8, 12, 28]);
+    }
+
+    #[test]
+    fn swing_delays_every_second_pulse() {
+        let pulses = [0, 10, 20, 30, 40, 50];
+        let sig = clock(&pulses, 60).filter(swing().amount_01(0.3));
+        assert_eq!(trig_indices(sig, 60), vec![0, 13, 20, 33, 40, 53]);
+    }
+
+    #[test]
+    fn swing_of_zero_leaves_clock_unchanged() {
+        let pulses = [0, 10, 20, 30];
+        let sig = clock(&pulses, 40).filter(swing().amount_01(0.0));
+        assert_eq!(trig_indices(sig, 40), pulses);
+    }
+
+    #[test]
+    fn humanize_stays_within_jitter() {
+        let period = 20;
+        let num_pulses = 200;
+        let num_samples = period * num_pulses;
+        let pulses = (0..num_pulses).map(|i| i * period).collect::<Vec<_>>();
+        let jitter_s = 0.01;
+        let max_delay = (jitter_s * SAMPLE_RATE_HZ) as usize;
+        let sig =
+            clock(&pulses, num_samples).filter(humanize().jitter_s(jitter_s));
+        let trigs = trig_indices(sig, num_samples);
+        assert_eq!(trigs.len(), num_pulses);
+        for (pulse, trig) in pulses.iter().zip(trigs) {
+            assert!(trig >= *pulse && trig <= pulse + max_delay);
+        }
+    }
+
+    #[test]
+    fn humanize_without_jitter_leaves_clock_unchanged() {
+        let pulses = [0, 10, 20, 30];
+        let sig = clock(&pulses, 40).filter(humanize().jitter_s(0.0));
+        assert_eq!(trig_indices(sig, 40), pulses);
+    }
+}
@@ -0,0 +1,661 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, ConstBuf, Sig, SigCtx, SigT};
+use caw_keyboard::{KeyEvent, KeyEvents, Note};
+use caw_persist::PersistData;
+use itertools::izip;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+/// A single step of a step sequencer.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    /// The note to play, or `None` for a rest
+    pub note: Option<Note>,
+    pub velocity_01: f32,
+    /// The fraction of the step (or of each ratchet within the step) during which the note is
+    /// held
+    pub gate_01: f32,
+    /// The chance that the step will be played each time it's reached
+    pub probability_01: f32,
+    /// Hold the note until the next step begins. If the next step plays the same note then the
+    /// note is tied (held without retriggering), otherwise the next note is pressed before this
+    /// one is released so mono voices will glide between them.
+    pub slide: bool,
+    /// The number of times the note is retriggered during the step
+    pub ratchet: u32,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self::rest()
+    }
+}
+
+impl Step {
+    pub fn note(note: Note) -> Self {
+        Self {
+            note: Some(note),
+            ..Self::rest()
+        }
+    }
+
+    pub fn rest() -> Self {
+        Self {
+            note: None,
+            velocity_01: 1.0,
+            gate_01: 0.5,
+            probability_01: 1.0,
+            slide: false,
+            ratchet: 1,
+        }
+    }
+
+    pub fn with_velocity_01(self, velocity_01: f32) -> Self {
+        Self {
+            velocity_01,
+            ..self
+        }
+    }
+
+    pub fn with_gate_01(self, gate_01: f32) -> Self {
+        Self { gate_01, ..self }
+    }
+
+    pub fn with_probability_01(self, probability_01: f32) -> Self {
+        Self {
+            probability_01,
+            ..self
+        }
+    }
+
+    pub fn with_slide(self, slide: bool) -> Self {
+        Self { slide, ..self }
+    }
+
+    pub fn with_ratchet(self, ratchet: u32) -> Self {
+        Self { ratchet, ..self }
+    }
+}
+
+impl From<Note> for Step {
+    fn from(note: Note) -> Self {
+        Self::note(note)
+    }
+}
+
+impl From<Option<Note>> for Step {
+    fn from(note: Option<Note>) -> Self {
+        match note {
+            Some(note) => Self::note(note),
+            None => Self::rest(),
+        }
+    }
+}
+
+/// A sequence of steps. Patterns can be saved and loaded with the `PersistData` trait.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StepPattern {
+    pub steps: Vec<Step>,
+}
+
+impl StepPattern {
+    pub fn new(steps: impl IntoIterator<Item = impl Into<Step>>) -> Self {
+        Self {
+            steps: steps.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl PersistData for StepPattern {
+    const NAME: &'static str = "step_pattern";
+}
+
+/// The chain of patterns played by a step sequencer. Use this to save and load all the patterns
+/// of a sequencer together with the `PersistData` trait.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StepPatternSet {
+    pub patterns: Vec<StepPattern>,
+}
+
+impl StepPatternSet {
+    pub fn new(patterns: impl IntoIterator<Item = StepPattern>) -> Self {
+        Self {
+            patterns: patterns.into_iter().collect(),
+        }
+    }
+}
+
+impl PersistData for StepPatternSet {
+    const NAME: &'static str = "step_pattern_set";
+}
+
+impl From<StepPatternSet> for Vec<StepPattern> {
+    fn from(step_pattern_set: StepPatternSet) -> Self {
+        step_pattern_set.patterns
+    }
+}
+
+/// The order in which the steps of a pattern are played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StepDirection {
+    #[default]
+    Forward,
+    Reverse,
+    /// Alternate between playing forwards and backwards without repeating the first and last
+    /// steps.
+    PingPong,
+    Random,
+}
+
+impl SigT for StepDirection {
+    type Item = StepDirection;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        ConstBuf {
+            count: ctx.num_samples,
+            value: *self,
+        }
+    }
+}
+
+/// Keeps track of which step of which pattern is currently playing.
+struct Position {
+    pattern_index: usize,
+    step_index: usize,
+    ascending: bool,
+    steps_played_in_pattern: usize,
+    started: bool,
+}
+
+impl Position {
+    fn new() -> Self {
+        Self {
+            pattern_index: 0,
+            step_index: 0,
+            ascending: true,
+            steps_played_in_pattern: 0,
+            started: false,
+        }
+    }
+
+    fn first_step_index(
+        length: usize,
+        direction: StepDirection,
+        rng: &mut StdRng,
+    ) -> usize {
+        match direction {
+            StepDirection::Forward | StepDirection::PingPong => 0,
+            StepDirection::Reverse => length - 1,
+            StepDirection::Random => rng.random_range(0..length),
+        }
+    }
+
+    /// The number of steps to play before moving on to the next pattern in the chain.
+    fn steps_per_pattern(length: usize, direction: StepDirection) -> usize {
+        match direction {
+            StepDirection::PingPong => ((2 * length) - 2).max(1),
+            _ => length,
+        }
+    }
+
+    fn advance(
+        &mut self,
+        patterns: &[StepPattern],
+        length: usize,
+        direction: StepDirection,
+        rng: &mut StdRng,
+    ) {
+        let effective_length = |pattern_index: usize| -> usize {
+            patterns[pattern_index].steps.len().min(length).max(1)
+        };
+        if !self.started {
+            self.started = true;
+            self.pattern_index = 0;
+            self.steps_played_in_pattern = 0;
+            self.ascending = true;
+            self.step_index =
+                Self::first_step_index(effective_length(0), direction, rng);
+            return;
+        }
+        let length = effective_length(self.pattern_index);
+        self.steps_played_in_pattern += 1;
+        if self.steps_played_in_pattern
+            >= Self::steps_per_pattern(length, direction)
+        {
+            self.pattern_index = (self.pattern_index + 1) % patterns.len();
+            self.steps_played_in_pattern = 0;
+            self.ascending = true;
+            self.step_index = Self::first_step_index(
+                effective_length(self.pattern_index),
+                direction,
+                rng,
+            );
+            return;
+        }
+        // The length may have changed since the previous step.
+        self.step_index = self.step_index.min(length - 1);
+        self.step_index = match direction {
+            StepDirection::Forward => (self.step_index + 1) % length,
+            StepDirection::Reverse => (self.step_index + length - 1) % length,
+            StepDirection::PingPong => {
+                if length == 1 {
+                    0
+                } else {
+                    if self.ascending && self.step_index == length - 1 {
+                        self.ascending = false;
+                    } else if !self.ascending && self.step_index == 0 {
+                        self.ascending = true;
+                    }
+                    if self.ascending {
+                        self.step_index + 1
+                    } else {
+                        self.step_index - 1
+                    }
+                }
+            }
+            StepDirection::Random => rng.random_range(0..length),
+        };
+    }
+
+    fn current<'a>(&self, patterns: &'a [StepPattern]) -> Option<&'a Step> {
+        patterns[self.pattern_index].steps.get(self.step_index)
+    }
+}
+
+struct State {
+    patterns: Vec<StepPattern>,
+    position: Position,
+    rng: StdRng,
+    /// The step currently being played, or `None` if the current step is a rest or was skipped
+    /// due to its probability
+    current_step: Option<Step>,
+    held_note: Option<Note>,
+    samples_since_trig: u64,
+    /// The number of samples between the two most recent triggers
+    step_period: Option<u64>,
+}
+
+impl State {
+    fn new(patterns: Vec<StepPattern>) -> Self {
+        let patterns = if patterns.is_empty() {
+            vec![StepPattern::default()]
+        } else {
+            patterns
+        };
+        Self {
+            patterns,
+            position: Position::new(),
+            rng: StdRng::from_os_rng(),
+            current_step: None,
+            held_note: None,
+            samples_since_trig: 0,
+            step_period: None,
+        }
+    }
+
+    fn press(&mut self, note: Note, velocity_01: f32, events: &mut KeyEvents) {
+        events.push(KeyEvent {
+            note,
+            pressed: true,
+            velocity_01,
+        });
+        self.held_note = Some(note);
+    }
+
+    fn release(&mut self, events: &mut KeyEvents) {
+        if let Some(note) = self.held_note.take() {
+            events.push(KeyEvent {
+                note,
+                pressed: false,
+                velocity_01: 0.0,
+            });
+        }
+    }
+
+    fn start_step(
+        &mut self,
+        length: usize,
+        direction: StepDirection,
+        events: &mut KeyEvents,
+    ) {
+        let previous_slide = self.current_step.is_some_and(|step| step.slide);
+        self.position
+            .advance(&self.patterns, length, direction, &mut self.rng);
+        self.current_step = self
+            .position
+            .current(&self.patterns)
+            .filter(|step| step.note.is_some())
+            .filter(|step| {
+                step.probability_01 >= 1.0
+                    || self.rng.random::<f32>() < step.probability_01
+            })
+            .cloned();
+        match (self.current_step, self.held_note) {
+            (Some(step), Some(held_note)) if previous_slide => {
+                let note = step.note.unwrap();
+                if note != held_note {
+                    // Press the new note before releasing the old one so the notes overlap.
+                    let mut release = KeyEvents::empty();
+                    self.release(&mut release);
+                    self.press(note, step.velocity_01, events);
+                    events.extend(release);
+                }
+            }
+            (Some(step), _) => {
+                self.release(events);
+                self.press(step.note.unwrap(), step.velocity_01, events);
+            }
+            (None, _) => self.release(events),
+        }
+    }
+
+    /// Press or release the current step's note based on how far through the step we are.
+    fn update_gate(&mut self, events: &mut KeyEvents) {
+        let (Some(step), Some(step_period)) =
+            (self.current_step, self.step_period)
+        else {
+            // Until we've seen two triggers we don't know how long a step is, so just hold the
+            // note until the next step.
+            return;
+        };
+        let ratchet = step.ratchet.max(1) as u64;
+        let ratchet_period = (step_period / ratchet).max(1);
+        let ratchet_index = self.samples_since_trig / ratchet_period;
+        let position_in_ratchet = self.samples_since_trig % ratchet_period;
+        let gate_samples = ((ratchet_period as f32 * step.gate_01) as u64)
+            .clamp(1, ratchet_period);
+        let note = step.note.unwrap();
+        if ratchet_index >= ratchet {
+            // The step is taking longer than the previous step.
+            if !step.slide {
+                self.release(events);
+            }
+            return;
+        }
+        if position_in_ratchet == 0 && ratchet_index > 0 {
+            // Start of a ratchet after the first. Retrigger the note.
+            self.release(events);
+            self.press(note, step.velocity_01, events);
+        } else if position_in_ratchet >= gate_samples
+            && !(step.slide && ratchet_index == ratchet - 1)
+        {
+            self.release(events);
+        }
+    }
+
+    fn reset(&mut self, events: &mut KeyEvents) {
+        // The next trigger will play the first step of the first pattern.
+        self.position = Position::new();
+        self.current_step = None;
+        self.step_period = None;
+        self.release(events);
+    }
+
+    fn tick(
+        &mut self,
+        trig: bool,
+        length: usize,
+        direction: StepDirection,
+        events: &mut KeyEvents,
+    ) {
+        if trig {
+            if self.position.started {
+                self.step_period = Some(self.samples_since_trig + 1);
+            }
+            self.samples_since_trig = 0;
+            self.start_step(length, direction, events);
+        } else {
+            self.samples_since_trig += 1;
+            self.update_gate(events);
+        }
+    }
+}
+
+pub struct StepSequencer<T, R, L, D>
+where
+    T: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    D: SigT<Item = StepDirection>,
+{
+    trig: T,
+    reset: R,
+    length: L,
+    direction: D,
+    state: State,
+    buf: Vec<KeyEvents>,
+}
+
+impl<T, R, L, D> StepSequencer<T, R, L, D>
+where
+    T: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    D: SigT<Item = StepDirection>,
+{
+    fn new(
+        trig: T,
+        reset: R,
+        length: L,
+        direction: D,
+        patterns: Vec<StepPattern>,
+    ) -> Sig<Self> {
+        Sig(Self {
+            trig,
+            reset,
+            length,
+            direction,
+            state: State::new(patterns),
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl<T, R, L, D> SigT for StepSequencer<T, R, L, D>
+where
+    T: SigT<Item = bool>,
+    R: SigT<Item = bool>,
+    L: SigT<Item = u32>,
+    D: SigT<Item = StepDirection>,
+{
+    type Item = KeyEvents;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let trig = self.trig.sample(ctx);
+        let reset = self.reset.sample(ctx);
+        let length = self.length.sample(ctx);
+        let direction = self.direction.sample(ctx);
+        for (trig, reset, length, direction) in izip! {
+            trig.iter(),
+            reset.iter(),
+            length.iter(),
+            direction.iter(),
+        } {
+            let mut events = KeyEvents::empty();
+            if reset {
+                self.state.reset(&mut events);
+            }
+            self.state.tick(
+                trig,
+                length.max(1) as usize,
+                direction,
+                &mut events,
+            );
+            self.buf.push(events);
+        }
+        &self.buf
+    }
+}
+
+builder! {
+    #[constructor = "step_sequencer"]
+    #[constructor_doc = "A step sequencer which plays a chain of patterns, advancing one step on each trigger"]
+    #[generic_setter_type_name = "X"]
+    #[build_fn = "StepSequencer::new"]
+    #[build_ty = "Sig<StepSequencer<T, R, L, D>>"]
+    pub struct StepSequencerBuilder {
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "T"]
+        trig: _,
+        // Restart from the beginning of the first pattern on the next trigger
+        #[generic_with_constraint = "SigT<Item = bool>"]
+        #[generic_name = "R"]
+        #[default = false]
+        reset: bool,
+        // The maximum number of steps to play from each pattern
+        #[generic_with_constraint = "SigT<Item = u32>"]
+        #[generic_name = "L"]
+        #[default = u32::MAX]
+        length: u32,
+        #[generic_with_constraint = "SigT<Item = StepDirection>"]
+        #[generic_name = "D"]
+        #[default = StepDirection::Forward]
+        direction: StepDirection,
+        // Patterns are played one after another, looping back to the first pattern after the
+        // last. A `StepPatternSet` can be converted into this.
+        patterns: Vec<StepPattern>,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use caw_keyboard::{NoteName, Octave};
+
+    fn note(i: u8) -> Note {
+        Note::from_midi_index(60 + i)
+    }
+
+    fn pattern(notes: &[u8]) -> StepPattern {
+        StepPattern::new(notes.iter().map(|&i| note(i)))
+    }
+
+    /// The notes pressed on each of `num_trigs` consecutive triggers.
+    fn pressed_notes(
+        state: &mut State,
+        num_trigs: usize,
+        length: usize,
+        direction: StepDirection,
+    ) -> Vec<Note> {
+        (0..num_trigs)
+            .filter_map(|_| {
+                let mut events = KeyEvents::empty();
+                state.tick(true, length, direction, &mut events);
+                events
+                    .iter()
+                    .find(|event| event.pressed)
+                    .map(|event| event.note)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forward_and_reverse() {
+        let mut state = State::new(vec![pattern(&[0, 1, 2])]);
+        assert_eq!(
+            pressed_notes(&mut state, 5, usize::MAX, StepDirection::Forward),
+            [0, 1, 2, 0, 1].map(note)
+        );
+        let mut state = State::new(vec![pattern(&[0, 1, 2])]);
+        assert_eq!(
+            pressed_notes(&mut state, 5, usize::MAX, StepDirection::Reverse),
+            [2, 1, 0, 2, 1].map(note)
+        );
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mut state = State::new(vec![pattern(&[0, 1, 2])]);
+        assert_eq!(
+            pressed_notes(&mut state, 7, usize::MAX, StepDirection::PingPong),
+            [0, 1, 2, 1, 0, 1, 2].map(note)
+        );
+    }
+
+    #[test]
+    fn length_limits_each_pattern() {
+        let mut state = State::new(vec![pattern(&[0, 1, 2, 3])]);
+        assert_eq!(
+            pressed_notes(&mut state, 5, 2, StepDirection::Forward),
+            [0, 1, 0, 1, 0].map(note)
+        );
+    }
+
+    #[test]
+    fn patterns_are_chained() {
+        let mut state =
+            State::new(vec![pattern(&[0, 1]), pattern(&[10, 11, 12])]);
+        assert_eq!(
+            pressed_notes(&mut state, 7, usize::MAX, StepDirection::Forward),
+            [0, 1, 10, 11, 12, 0, 1].map(note)
+        );
+    }
+
+    #[test]
+    fn rests_release_the_previous_note() {
+        let mut state =
+            State::new(vec![StepPattern::new([Some(note(0)), None])]);
+        let mut events = KeyEvents::empty();
+        state.tick(true, usize::MAX, StepDirection::Forward, &mut events);
+        assert!(events.iter().all(|event| event.pressed));
+        let mut events = KeyEvents::empty();
+        state.tick(true, usize::MAX, StepDirection::Forward, &mut events);
+        assert_eq!(events.len(), 1);
+        assert!(!events.last().unwrap().pressed);
+    }
+
+    #[test]
+    fn gate_releases_part_way_through_step() {
+        let mut state =
+            State::new(vec![StepPattern::new([
+                Step::note(note(0)).with_gate_01(0.5)
+            ])]);
+        let step_period = 10;
+        // Samples at which a key event occurs, after the first two triggers establish the step
+        // period.
+        let mut event_samples = Vec::new();
+        for i in 0..(3 * step_period) {
+            let mut events = KeyEvents::empty();
+            state.tick(
+                i % step_period == 0,
+                usize::MAX,
+                StepDirection::Forward,
+                &mut events,
+            );
+            if i >= 2 * step_period {
+                for event in events.iter() {
+                    event_samples.push((i % step_period, event.pressed));
+                }
+            }
+        }
+        assert_eq!(event_samples, [(0, true), (5, false)]);
+    }
+
+    #[test]
+    fn reset_restarts_the_first_pattern() {
+        let mut state = State::new(vec![pattern(&[0, 1]), pattern(&[10, 11])]);
+        pressed_notes(&mut state, 3, usize::MAX, StepDirection::Forward);
+        state.reset(&mut KeyEvents::empty());
+        assert_eq!(
+            pressed_notes(&mut state, 2, usize::MAX, StepDirection::Forward),
+            [0, 1].map(note)
+        );
+    }
+
+    #[test]
+    fn pattern_set_round_trip() {
+        let step_pattern_set = StepPatternSet::new([
+            StepPattern::new([
+                Step::note(NoteName::C.in_octave(Octave::_4))
+                    .with_velocity_01(0.5)
+                    .with_ratchet(3),
+                Step::rest(),
+            ]),
+            StepPattern::new([Step::note(note(7)).with_slide(true)]),
+        ]);
+        let title = "step_sequencer_pattern_set_round_trip_test";
+        step_pattern_set.save(title).unwrap();
+        assert_eq!(StepPatternSet::load(title).unwrap(), step_pattern_set);
+    }
+}
@@ -0,0 +1,490 @@
+use caw_builder_proc_macros::builder;
+use caw_core::{Buf, Sig, SigCtx, SigShared, SigT, sig_shared};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    /// The number of beats in each bar
+    pub beats_per_bar: u32,
+    /// The note value of a beat, as a fraction of a whole note (e.g. 4 for quarter notes)
+    pub beat_unit: u32,
+}
+
+impl TimeSignature {
+    pub const FOUR_FOUR: Self = Self::new(4, 4);
+    pub const THREE_FOUR: Self = Self::new(3, 4);
+    pub const SIX_EIGHT: Self = Self::new(6, 8);
+
+    pub const fn new(beats_per_bar: u32, beat_unit: u32) -> Self {
+        Self {
+            beats_per_bar,
+            beat_unit,
+        }
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::FOUR_FOUR
+    }
+}
+
+/// The position of a transport during a single sample.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransportPosition {
+    pub playing: bool,
+    pub bpm: f32,
+    /// Number of beats since the start of the song
+    pub beats: f64,
+    /// Bar number, counting from 0
+    pub bar: u32,
+    /// Beat within the current bar, counting from 0
+    pub beat: u32,
+    /// Tick within the current beat, counting from 0
+    pub tick: u32,
+    /// How far through the current beat the transport is
+    pub beat_phase_01: f32,
+    /// True on the first sample after the transport starts playing or is moved to a new position
+    pub jumped: bool,
+}
+
+struct TransportState {
+    time_signature: TimeSignature,
+    ticks_per_beat: u32,
+    playing: bool,
+    beats: f64,
+    jumped: bool,
+}
+
+impl TransportState {
+    fn position(&self) -> TransportPosition {
+        let beats_per_bar = self.time_signature.beats_per_bar.max(1) as f64;
+        let beats = self.beats.max(0.0);
+        let beat_phase_01 = beats.fract();
+        TransportPosition {
+            playing: self.playing,
+            bpm: 0.0,
+            beats,
+            bar: (beats / beats_per_bar) as u32,
+            beat: (beats % beats_per_bar) as u32,
+            tick: (beat_phase_01 * self.ticks_per_beat as f64) as u32,
+            beat_phase_01: beat_phase_01 as f32,
+            jumped: self.jumped,
+        }
+    }
+
+    fn locate_beats(&mut self, beats: f64) {
+        self.beats = beats.max(0.0);
+        self.jumped = true;
+    }
+
+    fn locate(&mut self, bar: u32, beat: u32) {
+        self.locate_beats(
+            ((bar * self.time_signature.beats_per_bar) + beat) as f64,
+        );
+    }
+
+    fn play(&mut self) {
+        self.playing = true;
+        self.locate_beats(0.0);
+    }
+
+    fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    fn continue_(&mut self) {
+        if !self.playing {
+            self.playing = true;
+            self.jumped = true;
+        }
+    }
+}
+
+pub struct TransportSig<B>
+where
+    B: SigT<Item = f32>,
+{
+    bpm: B,
+    state: TransportState,
+    buf: Vec<TransportPosition>,
+}
+
+impl<B> SigT for TransportSig<B>
+where
+    B: SigT<Item = f32>,
+{
+    type Item = TransportPosition;
+
+    fn sample(&mut self, ctx: &SigCtx) -> impl Buf<Self::Item> {
+        self.buf.clear();
+        let bpm = self.bpm.sample(ctx);
+        for bpm in bpm.iter() {
+            self.buf.push(TransportPosition {
+                bpm,
+                ..self.state.position()
+            });
+            self.state.jumped = false;
+            if self.state.playing {
+                self.state.beats +=
+                    bpm as f64 / (60.0 * ctx.sample_rate_hz as f64);
+            }
+        }
+        &self.buf
+    }
+}
+
+/// A shared clock which keeps track of the position within a song. Cloning a `Transport` produces
+/// another handle to the same clock, so the signals derived from all clones stay in sync, and
+/// controlling the transport through any clone affects all of them.
+pub struct Transport<B>
+where
+    B: SigT<Item = f32>,
+{
+    sig: Sig<SigShared<TransportSig<B>>>,
+}
+
+impl<B> Clone for Transport<B>
+where
+    B: SigT<Item = f32>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            sig: self.sig.clone(),
+        }
+    }
+}
+
+impl<B> Transport<B>
+where
+    B: SigT<Item = f32>,
+{
+    fn new(
+        bpm: B,
+        time_signature: TimeSignature,
+        ticks_per_beat: u32,
+        playing: bool,
+    ) -> Self {
+        Self {
+            sig: sig_shared(TransportSig {
+                bpm,
+                state: TransportState {
+                    time_signature,
+                    ticks_per_beat: ticks_per_beat.max(1),
+                    playing,
+                    beats: 0.0,
+                    jumped: playing,
+                },
+                buf: Vec::new(),
+            }),
+        }
+    }
+
+    /// Start playing from the beginning of the song.
+    pub fn play(&self) {
+        self.sig.0.with_inner_mut(|t| t.state.play());
+    }
+
+    /// Stop playing, remembering the current position.
+    pub fn stop(&self) {
+        self.sig.0.with_inner_mut(|t| t.state.stop());
+    }
+
+    /// Start playing from the current position.
+    pub fn continue_(&self) {
+        self.sig.0.with_inner_mut(|t| t.state.continue_());
+    }
+
+    /// Move to the start of a given beat of a given bar (both counting from 0).
+    pub fn locate(&self, bar: u32, beat: u32) {
+        self.sig.0.with_inner_mut(|t| t.state.locate(bar, beat));
+    }
+
+    /// Move to a position specified in beats since the start of the song.
+    pub fn locate_beats(&self, beats: f64) {
+        self.sig.0.with_inner_mut(|t| t.state.locate_beats(beats));
+    }
+
+    pub fn time_signature(&self) -> TimeSignature {
+        self.sig.0.with_inner(|t| t.state.time_signature)
+    }
+
+    pub fn position(&self) -> Sig<SigShared<TransportSig<B>>> {
+        self.sig.clone()
+    }
+
+    pub fn playing(&self) -> Sig<impl SigT<Item = bool>> {
+        self.position().map(|p| p.playing)
+    }
+
+    pub fn bpm(&self) -> Sig<impl SigT<Item = f32>> {
+        self.position().map(|p| p.bpm)
+    }
+
+    /// The duration of a beat at the current tempo. Useful for syncing delays.
+    pub fn beat_period_s(&self) -> Sig<impl SigT<Item = f32>> {
+        self.position().map(|p| 60.0 / p.bpm)
+    }
+
+    pub fn bar(&self) -> Sig<impl SigT<Item = u32>> {
+        self.position().map(|p| p.bar)
+    }
+
+    pub fn beat(&self) -> Sig<impl SigT<Item = u32>> {
+        self.position().map(|p| p.beat)
+    }
+
+    pub fn tick(&self) -> Sig<impl SigT<Item = u32>> {
+        self.position().map(|p| p.tick)
+    }
+
+    /// How far through the current beat the transport is. Useful for syncing LFOs.
+    pub fn beat_phase_01(&self) -> Sig<impl SigT<Item = f32>> {
+        self.position().map(|p| p.beat_phase_01)
+    }
+
+    /// How far through the current bar the transport is.
+    pub fn bar_phase_01(&self) -> Sig<impl SigT<Item = f32>> {
+        let beats_per_bar = self.time_signature().beats_per_bar.max(1) as f64;
+        self.position()
+            .map(move |p| ((p.beats / beats_per_bar).fract()) as f32)
+    }
+
+    /// Trigger once every `period_beats` beats while the transport is playing. Also triggers when
+    /// the transport starts or is moved to a position which lies exactly on a period boundary.
+    pub fn trig_every_beats(
+        &self,
+        period_beats: f64,
+    ) -> Sig<impl SigT<Item = bool>> {
+        let mut prev_index = None;
+        self.position().map_mut(move |p| {
+            if !p.playing || period_beats <= 0.0 {
+                prev_index = None;
+                return false;
+            }
+            let periods = p.beats / period_beats;
+            let index = periods.floor() as u64;
+            let trig = if p.jumped || prev_index.is_none() {
+                periods.fract() == 0.0
+            } else {
+                prev_index != Some(index)
+            };
+            prev_index = Some(index);
+            trig
+        })
+    }
+
+    /// Trigger `notes_per_whole_note` times per whole note. E.g. 4 for quarter notes, 16 for
+    /// sixteenth notes, or 12 for eighth note triplets.
+    pub fn trig_division(
+        &self,
+        notes_per_whole_note: f64,
+    ) -> Sig<impl SigT<Item = bool>> {
+        let beat_unit = self.time_signature().beat_unit as f64;
+        self.trig_every_beats(beat_unit / notes_per_whole_note)
+    }
+
+    pub fn bar_trig(&self) -> Sig<impl SigT<Item = bool>> {
+        self.trig_every_beats(self.time_signature().beats_per_bar as f64)
+    }
+
+    pub fn beat_trig(&self) -> Sig<impl SigT<Item = bool>> {
+        self.trig_every_beats(1.0)
+    }
+
+    pub fn tick_trig(&self) -> Sig<impl SigT<Item = bool>> {
+        let ticks_per_beat = self.sig.0.with_inner(|t| t.state.ticks_per_beat);
+        self.trig_every_beats(1.0 / ticks_per_beat as f64)
+    }
+}
+
+builder! {
+    #[constructor = "transport"]
+    #[constructor_doc = "A shared clock keeping track of the bar, beat and tick within a song"]
+    #[generic_setter_type_name = "X"]
+    #[build_fn = "Transport::new"]
+    #[build_ty = "Transport<B>"]
+    pub struct TransportBuilder {
+        #[generic_with_constraint = "SigT<Item = f32>"]
+        #[generic_name = "B"]
+        #[default = 120.0]
+        bpm: f32,
+        #[default = TimeSignature::FOUR_FOUR]
+        time_signature: TimeSignature,
+        // The resolution of the tick signal. Defaults to the resolution of midi clock.
+        #[default = 24]
+        ticks_per_beat: u32,
+        // Whether the transport starts playing as soon as it's created
+        #[default = true]
+        playing: bool,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// At 60bpm each beat lasts this many samples. A power of 2 so positions are exact.
+    const SAMPLES_PER_BEAT: usize = 64;
+
+    fn ctx(batch_index: u64, num_samples: usize) -> SigCtx {
+        SigCtx {
+            sample_rate_hz: SAMPLES_PER_BEAT as f32,
+            batch_index,
+            num_samples,
+        }
+    }
+
+    fn run<S: SigT>(
+        sig: &mut S,
+        batch_index: u64,
+        num_samples: usize,
+    ) -> Vec<S::Item> {
+        sig.sample(&ctx(batch_index, num_samples)).iter().collect()
+    }
+
+    fn trig_indices(trigs: Vec<bool>) -> Vec<usize> {
+        trigs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, trig)| trig.then_some(i))
+            .collect()
+    }
+
+    fn bar_beat_tick(p: TransportPosition) -> (u32, u32, u32) {
+        (p.bar, p.beat, p.tick)
+    }
+
+    #[test]
+    fn position_advances_with_tempo() {
+        let t = transport().bpm(60.0).ticks_per_beat(4).build();
+        let positions = run(&mut t.position(), 0, SAMPLES_PER_BEAT * 5);
+        assert!(positions[0].jumped);
+        assert!(!positions[1].jumped);
+        assert_eq!(bar_beat_tick(positions[0]), (0, 0, 0));
+        assert_eq!(bar_beat_tick(positions[15]), (0, 0, 0));
+        assert_eq!(bar_beat_tick(positions[16]), (0, 0, 1));
+        assert_eq!(bar_beat_tick(positions[64 + 48]), (0, 1, 3));
+        assert_eq!(bar_beat_tick(positions[256]), (1, 0, 0));
+        assert_eq!(positions[32].beat_phase_01, 0.5);
+        assert_eq!(positions[32].beats, 0.5);
+    }
+
+    #[test]
+    fn beat_and_bar_trigs() {
+        let t = transport().bpm(60.0).build();
+        assert_eq!(
+            trig_indices(run(&mut t.beat_trig(), 0, SAMPLES_PER_BEAT * 3)),
+            vec![0, 64, 128]
+        );
+        let t = transport().bpm(60.0).build();
+        assert_eq!(
+            trig_indices(run(&mut t.bar_trig(), 0, SAMPLES_PER_BEAT * 9)),
+            vec![0, 256, 512]
+        );
+        let t = transport().bpm(60.0).ticks_per_beat(4).build();
+        assert_eq!(
+            trig_indices(run(&mut t.tick_trig(), 0, SAMPLES_PER_BEAT)),
+            vec![0, 16, 32, 48]
+        );
+    }
+
+    #[test]
+    fn trig_every_beats() {
+        let t = transport().bpm(60.0).build();
+        assert_eq!(
+            trig_indices(run(
+                &mut t.trig_every_beats(1.5),
+                0,
+                SAMPLES_PER_BEAT * 4
+            )),
+            vec![0, 96, 192]
+        );
+    }
+
+    #[test]
+    fn trig_division_depends_on_beat_unit() {
+        // Eighth notes are half a beat in 4/4 and a whole beat in 6/8.
+        let t = transport().bpm(60.0).build();
+        assert_eq!(
+            trig_indices(run(
+                &mut t.trig_division(8.0),
+                0,
+                SAMPLES_PER_BEAT * 2
+            )),
+            vec![0, 32, 64, 96]
+        );
+        let t = transport()
+            .bpm(60.0)
+            .time_signature(TimeSignature::SIX_EIGHT)
+            .build();
+        assert_eq!(
+            trig_indices(run(
+                &mut t.trig_division(8.0),
+                0,
+                SAMPLES_PER_BEAT * 2
+            )),
+            vec![0, 64]
+        );
+    }
+
+    #[test]
+    fn locate_moves_to_bar_and_beat() {
+        let t = transport().bpm(60.0).ticks_per_beat(4).build();
+        let mut position = t.position();
+        run(&mut position, 0, 10);
+        t.locate(2, 1);
+        let positions = run(&mut position, 1, 20);
+        assert!(positions[0].jumped);
+        assert_eq!(bar_beat_tick(positions[0]), (2, 1, 0));
+        assert_eq!(positions[0].beats, 9.0);
+        assert_eq!(bar_beat_tick(positions[16]), (2, 1, 1));
+        t.locate_beats(4.5);
+        let positions = run(&mut position, 2, 1);
+        assert_eq!(bar_beat_tick(positions[0]), (1, 0, 2));
+    }
+
+    #[test]
+    fn locate_triggers_on_boundary() {
+        let t = transport().bpm(60.0).build();
+        let mut beat_trig = t.beat_trig();
+        assert_eq!(trig_indices(run(&mut beat_trig, 0, 10)), vec![0]);
+        t.locate(1, 2);
+        assert_eq!(trig_indices(run(&mut beat_trig, 1, 10)), vec![0]);
+        t.locate_beats(2.5);
+        assert_eq!(trig_indices(run(&mut beat_trig, 2, 40)), vec![32]);
+    }
+
+    #[test]
+    fn stopped_transport_holds_position() {
+        let t = transport().bpm(60.0).playing(false).build();
+        let mut position = t.position();
+        let positions = run(&mut position, 0, 10);
+        assert!(positions.iter().all(|p| !p.playing && p.beats == 0.0));
+        let t = transport().bpm(60.0).build();
+        let mut beat_trig = t.beat_trig();
+        t.stop();
+        assert!(trig_indices(run(&mut beat_trig, 0, 100)).is_empty());
+    }
+
+    #[test]
+    fn continue_resumes_and_play_restarts() {
+        let t = transport().bpm(60.0).build();
+        let mut position = t.position();
+        run(&mut position, 0, 96);
+        t.stop();
+        let positions = run(&mut position, 1, 10);
+        assert!(positions.iter().all(|p| !p.playing && p.beats == 1.5));
+        t.continue_();
+        let positions = run(&mut position, 2, 2);
+        assert!(positions[0].playing && positions[0].jumped);
+        assert_eq!(positions[0].beats, 1.5);
+        t.play();
+        let positions = run(&mut position, 3, 1);
+        assert!(positions[0].jumped);
+        assert_eq!(positions[0].beats, 0.0);
+        // Continuing while already playing doesn't jump.
+        t.continue_();
+        let positions = run(&mut position, 4, 1);
+        assert!(!positions[0].jumped);
+    }
+}